@@ -60,6 +60,8 @@ function opts()
 		width = 1000,
 		height = 100,
 		layer = "overlay",
+		show_fps = true,
+		background_colour = 0xFF707070,
 		-- exclusive_zone = 100,
 		-- margin = { top = 20 },
 		-- namespace = "hello world",
@@ -81,4 +83,7 @@ function draw(canvas)
 	for _, w in ipairs(wid) do
 		w:draw(canvas)
 	end
+
+	-- keep the rect moving
+	request_redraw()
 end
@@ -10,29 +10,106 @@
 ---@field left? number
 local Margin = {}
 
+---@alias KeyboardInteractivity "none"|"exclusive"|"on_demand"
+
+---Returned by the config's `opts()`, or a list of them for several bars. Functions taking a bar
+---number count from 1 in the order of the list.
 ---@class WindowOpts
----@field width number
----@field height number
----@field layer "background"|"bottom"|"top"|"overlay"
----@field exclusive_zone? number
----@field anchor? number
----@field margin? Margin
----@field namespace? string
+---@field width? number defaults to 100, at most 8192
+---@field height? number defaults to 100, at most 8192
+---@field layer? "background"|"bottom"|"top"|"overlay" defaults to "bottom"
+---@field exclusive_zone? number defaults to -1
+---@field exclusive? "fixed"|"auto" "auto" reserves the bar's height on the top or bottom edge it is anchored to, or its width on the left or right, ignoring `exclusive_zone`; defaults to "fixed"
+---@field anchor? number bitflags of top = 1, bottom = 2, left = 4, right = 8, defaults to top
+---@field margin? Margin|integer[] named sides, or a list of top, right, bottom and left
+---@field namespace? string identifies the bar to the compositor, defaults to "status-bar" when unset or blank. Only read at startup
+---@field show_fps? boolean draw a debug fps counter over the bar
+---@field background_colour? number|string colour cleared to before each draw, transparent by default. Also takes "#RRGGBB", "#AARRGGBB", a name from `colours` or an entry of the theme
+---@field pixel_format? "argb8888"|"xrgb8888" of the bar's buffers; "xrgb8888" ignores alpha for opaque bars. Defaults to "argb8888"
+---@field input_passthrough? boolean let clicks pass through the bar
+---@field keyboard_interactivity? KeyboardInteractivity defaults to "on_demand"
+---@field max_fps? integer limit how often the bar is drawn, 0 or unset for no limit
+---@field stretch? boolean fill the output's width instead of using `width`, needs anchor left and right
+---@field backend? "cpu"|"gpu" render on the CPU or with OpenGL ES, "gpu" needs the gpu feature; defaults to "cpu"
+---@field draw? string name of the global function that draws the bar, defaults to "draw"
+---@field idle_timeout? integer seconds without input before the bar stops drawing until there is input again, 0 or unset to keep drawing
+---@field key_repeat? boolean call `on_key` again for held keys at the seat's repeat rate, defaults to false
+---@field draw_timeout? integer milliseconds `draw` may run before it is stopped with an error shown on the bar, 0 to let it run; defaults to 1000
 ---@field widgets Widget,
 local WindowOpts = {}
 
 ---@class Canvas
 local Canvas = {}
 
----@param colour number
+---Width of the bar in pixels, as last configured by the compositor.
+---@return integer
+function Canvas:width() end
+
+---Height of the bar in pixels, as last configured by the compositor.
+---@return integer
+function Canvas:height() end
+
+---Physical pixels per unit of the bar's output. Everything drawn, text included, is scaled by
+---it, so sizes stay the same on outputs of any scale.
+---@return number
+function Canvas:scale_factor() end
+
+---What of the buffer being drawn the previous frame did not bring up to date: the regions
+---marked dirty by the frames drawn since it was last shown, as {x, y, width, height}. An empty
+---list means it holds the previous frame, nil that none of it can be relied on, as with a fresh
+---buffer or the gpu backend.
+---Before draw is called only the stale region is cleared to the background, so a config that
+---relies on it must clear whatever it marks dirty itself.
+---@return number[][]?
+function Canvas:stale_region() end
+
+---@param colour number|string
 function Canvas:clear(colour) end
 
+---Marks a region as changed this frame. When nothing is marked the whole bar is damaged.
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+function Canvas:mark_dirty(px, py, sx, sy) end
+
 ---@param px number
 ---@param py number
 ---@param sx number
 ---@param sy number
 function Canvas:draw_rect(px, py, sx, sy) end
 
+---Limits drawing to a rect, inside any clip already pushed, until the matching `pop_clip`.
+---Nest them so each module clips to its own bounds.
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+function Canvas:push_clip(px, py, sx, sy) end
+
+---Like `push_clip`, with the corners rounded, to cut a module's content to a card. Popped
+---with `pop_clip`.
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+---@param radius number
+function Canvas:clip_round_rect(px, py, sx, sy, radius) end
+
+---Removes the last clip pushed by `push_clip` or `clip_round_rect`. Errors without one to pop.
+function Canvas:pop_clip() end
+
+---Fills a rect with a linear gradient through `stops`, evenly spaced. The gradient spans the
+---rect at `angle`, so at 45 the first colour is at the top left corner and the last at the
+---bottom right. Leaves the paint as it was.
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+---@param stops (number|string)[] at least two colours
+---@param angle? number degrees clockwise from left to right, defaults to 0
+function Canvas:draw_rect_gradient(px, py, sx, sy, stops, angle) end
+
 ---@param px number
 ---@param py number
 ---@param radius number
@@ -44,18 +121,126 @@ function Canvas:draw_circle(px, py, radius) end
 ---@param ty number
 function Canvas:draw_line(fx, fy, tx, ty) end
 
+---Draws lines between the points, filled or stroked depending on the paint style.
+---@param points number[][] list of { x, y }
+---@param closed? boolean join the last point back to the first, defaults to true
+function Canvas:draw_polygon(points, closed) end
+
+---Characters the monospace font has no glyph for, such as emoji and CJK, fall back to an
+---installed font that has one.
 ---@param px number
 ---@param py number
 ---@param str string
 function Canvas:draw_text(px, py, str) end
 
+---Draws text with its left edge, centre or right edge at `px`, on the same baseline as
+---`draw_text`.
+---@param px number
+---@param py number baseline
+---@param str string
+---@param size? number font size, defaults to 18
+---@param align? "left"|"center"|"right" defaults to "left"
+function Canvas:draw_text_aligned(px, py, str, size, align) end
+
+---Draws text shaded from `from` at its left edge to `to` at its right, with the paint's alpha.
+---@param px number
+---@param py number baseline
+---@param str string
+---@param size? number font size, defaults to 18
+---@param from number|string
+---@param to number|string
+function Canvas:draw_text_gradient(px, py, str, size, from, to) end
+
+---Draws text centred on a rounded box, such as a count or a label, with the box's top left
+---corner at the given position.
+---@param px number
+---@param py number
+---@param str string
+---@param size? number font size, defaults to 18
+---@param text_colour number|string
+---@param background_colour number|string
+---@param padding? number space between the text and the box's edges, defaults to 4
+---@param radius? number corner radius, defaults to 0
+---@return number width of the box
+---@return number height
+function Canvas:draw_badge(px, py, str, size, text_colour, background_colour, padding, radius) end
+
+---@param str string
+---@param size? number font size, defaults to 18
+---@return number width advance width of the text
+---@return number height line spacing of the font
+function Canvas:measure_text(str, size) end
+
+---@class TextRun
+---@field text string
+---@field size? number font size, defaults to 18
+---@field colour? number|string defaults to the paint's colour
+
+---Lays out runs of differently sized and coloured text as one paragraph, wrapping at
+---`max_width`.
+---@param px number left edge
+---@param py number top edge
+---@param runs (TextRun|string)[]
+---@param max_width number
+---@return number height the height the paragraph took
+function Canvas:draw_paragraph(px, py, runs, max_width) end
+
+---Draws an image file scaled to the given size. Decoded images are kept until the file changes.
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+---@param path string
+function Canvas:draw_image(px, py, sx, sy, path) end
+
+---Draws the frame of an animated GIF or WEBP shown `time` seconds in, following the file's frame
+---delays and looping. Pass a running total of `draw`'s delta, or `uptime()`. Still images work
+---too. Decoded frames are kept until the file changes.
+---@param path string
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+---@param time number seconds
+function Canvas:draw_animated(path, px, py, sx, sy, time) end
+
+---Draws part of an image file, such as one icon of an atlas, scaled to the given size.
+---@param path string
+---@param sx number left of the region in image pixels
+---@param sy number top of the region
+---@param sw number width of the region
+---@param sh number height of the region
+---@param px number
+---@param py number
+---@param width number
+---@param height number
+function Canvas:draw_image_region(path, sx, sy, sw, sh, px, py, width, height) end
+
+---Draws raw pixels, such as a tray item's icon, scaled to the given size.
+---@param px number
+---@param py number
+---@param sx number
+---@param sy number
+---@param pixmap TrayPixmap
+function Canvas:draw_pixmap(px, py, sx, sy, pixmap) end
+
 function Canvas:draw_path_stroke() end
 function Canvas:draw_path_fill() end
 
+---Starts a new path at the point, discarding the current one without drawing it. Draw a path
+---with `draw_path_stroke` or `draw_path_fill` before starting the next. Earlier versions drew
+---the current path with the paint as it was first, which `path_begin` still does.
 ---@param px number
 ---@param py number
 function Canvas:path_begin_from(px, py) end
 
+---Draws the current path with the paint as it is, then starts a new empty one.
+---@deprecated draw the path with `draw_path_stroke` or `draw_path_fill`, then call `clear_path`
+function Canvas:path_begin() end
+
+---Discards the current path without drawing it.
+function Canvas:clear_path() end
+
 ---@param cp1x number
 ---@param cp1y number
 ---@param cp2x number
@@ -64,7 +249,7 @@ function Canvas:path_begin_from(px, py) end
 ---@param py number
 function Canvas:path_bezier_curve_to(cp1x, cp1y, cp2x, cp2y, px, py) end
 
----@param colour number
+---@param colour number|string 0xAARRGGBB, see `colour` and `colours`, or "#RRGGBB", "#AARRGGBB", a name from `colours` or a colour of the theme
 function Canvas:set_paint_colour(colour) end
 
 ---@param style "fill" | "stroke"
@@ -72,3 +257,590 @@ function Canvas:set_paint_style(style) end
 
 ---@param width number
 function Canvas:set_stroke_width(width) end
+
+---Anti-aliasing is on by default, turn it off for crisp single pixel lines.
+---@param on boolean
+function Canvas:set_anti_alias(on) end
+
+---Space added between characters of text for the rest of the frame, 0 by default.
+---@param px number
+function Canvas:set_letter_spacing(px) end
+
+---How images and pixmaps are filtered when scaled for the rest of the frame. "nearest" keeps
+---pixel art sharp.
+---@param mode "nearest"|"linear" defaults to "linear"
+function Canvas:set_image_sampling(mode) end
+
+---Which parts of a self-intersecting path are filled for the rest of the frame. "even-odd"
+---leaves the overlaps, like the middle of a star, empty.
+---@param rule "winding"|"even-odd" defaults to "winding"
+function Canvas:set_fill_rule(rule) end
+
+---Creates a transparent offscreen layer to draw into once and blit with `draw_layer` each
+---frame, for drawing that is too expensive to repeat every frame. Keep it in a variable
+---outside `draw`, it is freed once nothing references it.
+---@param width integer
+---@param height integer
+---@return Layer
+function Canvas:create_layer(width, height) end
+
+---Draws a layer at its size with its top left corner at the given position.
+---@param layer Layer
+---@param px number
+---@param py number
+function Canvas:draw_layer(layer, px, py) end
+
+---Encodes what has been drawn so far this frame.
+---@return string png
+function Canvas:snapshot_png() end
+
+---Encodes what has been drawn so far this frame.
+---@param format? "png"|"jpeg"|"webp" defaults to "png"
+---@param quality? integer 0 to 100 for jpeg and webp, defaults to 90
+---@return string
+function Canvas:encode(format, quality) end
+
+---Writes what has been drawn so far this frame to `path` as a PNG.
+---@param path string
+function Canvas:save_png(path) end
+
+---@class Layer
+local Layer = {}
+
+---@return integer
+function Layer:width() end
+
+---@return integer
+function Layer:height() end
+
+---Calls `f` with a canvas over the layer. It draws over what the layer already holds, clear
+---it first to redraw it from scratch.
+---@param f fun(canvas: Canvas)
+function Layer:draw(f) end
+
+---@param colour? number|string defaults to transparent
+function Layer:clear(colour) end
+
+---Draws a frame of the bar. Each bar may name its own with `WindowOpts.draw`.
+---@param canvas Canvas
+---@param delta number seconds since the previous frame
+---@param bar integer the bar being drawn
+function draw(canvas, delta, bar) end
+
+---Called before each `draw`, for setup such as resetting layout state.
+---@param bar integer
+function before_draw(bar) end
+
+---Called after each `draw`.
+---@param bar integer
+function after_draw(bar) end
+
+---Draws the tooltip opened with `show_tooltip`, each time its bar is drawn.
+---@param canvas Canvas
+---@param delta number seconds since the previous frame
+---@param bar integer the bar the tooltip is shown on
+function draw_popup(canvas, delta, bar) end
+
+---@class Modifiers
+---@field ctrl boolean
+---@field shift boolean
+---@field alt boolean
+---@field meta boolean the logo key
+
+---Called when a key is pressed while the bar has keyboard focus, and again while it is held
+---if the bar sets `key_repeat`.
+---@param name string xkb keysym name, e.g. "Return" or "a"
+---@param text string text produced by the key, empty for modifiers
+---@param modifiers Modifiers held when the key was pressed
+function on_key(name, text, modifiers) end
+
+---Whether a key from `on_key` is the combo `spec`, e.g. "ctrl+q" or "shift+alt+x". Names are
+---matched in any case, and the modifiers held must be exactly those in `spec`: ctrl, shift, alt
+---and meta (or super).
+---@param name string the key's name from `on_key`
+---@param modifiers Modifiers
+---@param spec string
+---@return boolean
+function key_combo(name, modifiers, spec) end
+
+---Called when the bar gains keyboard focus.
+function on_keyboard_enter() end
+
+---Called when the bar loses keyboard focus.
+function on_keyboard_leave() end
+
+---@return boolean
+function has_keyboard_focus() end
+
+---Called when the compositor resizes a bar, before it is next drawn. Not called for configures
+---that keep the size.
+---@param width integer
+---@param height integer
+---@param bar integer
+function on_resize(width, height, bar) end
+
+---Called when the pointer enters a bar, e.g. to start a hover highlight.
+---@param x number
+---@param y number
+---@param bar integer
+function on_pointer_enter(x, y, bar) end
+
+---Called when the pointer leaves the bars, e.g. to clear hover highlights.
+function on_pointer_leave() end
+
+---Calls `callback` every `ms` milliseconds.
+---@param ms integer
+---@param callback function|string a function or the name of a global function
+function set_interval(ms, callback) end
+
+---Runs a one-shot transition: calls `callback` with the progress from 0 to 1 and redraws, about
+---once a frame for `ms` milliseconds, then stops. The last call always gets 1. Redraws return to
+---on demand afterwards, unlike calling `request_redraw` from `draw`.
+---@param ms integer
+---@param callback fun(progress: number)|string a function or the name of a global function
+function animate(ms, callback) end
+
+---@alias OutputTransform "normal"|"90"|"180"|"270"|"flipped"|"flipped-90"|"flipped-180"|"flipped-270"
+
+---@class Output
+---@field name? string connector name, e.g. "DP-1"
+---@field description? string
+---@field width integer resolution of the current mode in pixels
+---@field height integer
+---@field scale integer
+---@field transform OutputTransform
+---@field subpixel "unknown"|"none"|"horizontal-rgb"|"horizontal-bgr"|"vertical-rgb"|"vertical-bgr"
+---@field x integer position in the compositor's logical space
+---@field y integer
+
+---@return Output[]
+function outputs() end
+
+---The bar's layer surface namespace. It is fixed when the bar starts, reloading the config
+---does not change it.
+---@param bar? integer defaults to 1
+---@return string? namespace nil if there is no such bar
+function namespace(bar) end
+
+---How the output the bar is on is rotated or flipped. The compositor rotates the bar along with
+---it, so drawing needs no changes, but a config may lay itself out differently.
+---@param bar? integer defaults to 1
+---@return OutputTransform? transform nil if there is no such bar
+function transform(bar) end
+
+---The last known pointer position relative to the bar it was last over.
+---@return number x
+---@return number y
+---@return boolean inside whether the pointer is over the bar
+---@return integer bar
+function pointer_position() end
+
+---Whether the pointer is over the bar being drawn, or the first bar outside `draw`, and inside
+---the rect. Once a config uses `hover`, the
+---bar redraws whenever the pointer moves.
+---@param x number
+---@param y number
+---@param width number
+---@param height number
+---@return boolean
+function hover(x, y, width, height) end
+
+---Logs its arguments separated by tabs, at info level under the `lua` target. Shown by
+---default, `RUST_LOG=lua=off` hides them.
+---@param ... any
+function print(...) end
+
+---Seconds since the bar started, unaffected by reloads and changes to the system clock. Useful
+---for animations driven by phase rather than by `delta`.
+---@return number
+function uptime() end
+
+---@class PerfStats
+---@field min number milliseconds
+---@field avg number milliseconds
+---@field max number milliseconds
+---@field p99 number milliseconds, exceeded by only the slowest 1% of frames
+
+---How long recent frames took to draw, including `before_draw` and `after_draw`, to help find
+---expensive drawing code. Covers the last 120 frames of every bar.
+---@return PerfStats? nil until the first frame is drawn
+function perf_stats() end
+
+---Schedules a redraw. The bar only redraws when requested or resized.
+function request_redraw() end
+
+---Called when a button is pressed and released over the bar without the pointer moving away.
+---@param button "left"|"right"|"middle"|"side"|"extra"|string other buttons are "button<code>"
+---@param x number
+---@param y number
+---@param bar integer the bar clicked
+---@param modifiers Modifiers held when the button was released
+function on_click(button, x, y, bar, modifiers) end
+
+---Called after `on_click` when the click quickly follows another of the same button.
+---@param button "left"|"right"|"middle"|"side"|"extra"|string
+---@param x number
+---@param y number
+---@param bar integer
+---@param modifiers Modifiers
+function on_double_click(button, x, y, bar, modifiers) end
+
+---Called once before the bar exits.
+function on_exit() end
+
+---Called when the bar stops drawing because the user has been idle for its `idle_timeout`.
+---@param bar integer
+function on_idle(bar) end
+
+---Called when the user is active again after `on_idle`, before the bar redraws.
+---@param bar integer
+function on_resume(bar) end
+
+---Exits the bar once the current callback returns, after calling `on_exit`.
+function quit() end
+
+---Moves the bar away from its anchored edges, e.g. to slide it in from off screen.
+---@param top integer
+---@param right integer
+---@param bottom integer
+---@param left integer
+---@param bar? integer defaults to 1
+function set_margin(top, right, bottom, left, bar) end
+
+---Slides the bar `offset` pixels back past the edge it is anchored to, from the margin set by
+---`opts` or `set_margin`. An offset of the bar's height, or its width when anchored left or
+---right, hides it; 0 brings it back. Bars anchored to no single edge are left where they are.
+---@param offset integer
+---@param bar? integer defaults to 1
+function slide_offset(offset, bar) end
+
+---Opens a tooltip drawn by `draw_popup`, replacing any already shown. `x` and `y` place its top
+---left corner relative to the bar, the compositor may move it to keep it on screen.
+---@param x integer
+---@param y integer
+---@param width integer
+---@param height integer
+---@param bar? integer the bar to show it on, defaults to 1
+function show_tooltip(x, y, width, height, bar) end
+
+---Closes the tooltip, if one is shown.
+function hide_tooltip() end
+
+---@alias CursorShape "default"|"pointer"|"text"
+
+---Sets the cursor shown over the bars, e.g. "pointer" while `hover` is true for something that
+---can be clicked. It goes back to "default" when the pointer leaves. Needs a compositor with
+---the cursor shape protocol.
+---@param shape CursorShape
+function set_cursor(shape) end
+
+---Only accept pointer input inside the given rect. Call without a rect to accept input on the
+---whole bar again.
+---@param x? integer
+---@param y? integer
+---@param width? integer
+---@param height? integer
+---@param bar? integer defaults to 1
+function set_input_region(x, y, width, height, bar) end
+
+---@param interactivity KeyboardInteractivity
+---@param bar? integer defaults to 1
+function set_keyboard_interactivity(interactivity, bar) end
+
+---Hides or shows the bar, e.g. from a keybind. A hidden bar is unmapped, giving up its
+---exclusive zone, and is not drawn until it is shown again.
+---@param visible boolean
+---@param bar? integer defaults to 1
+function set_visible(visible, bar) end
+
+---Packs channels from 0 to 255 into the 0xAARRGGBB colours taken by the canvas.
+---@param r integer
+---@param g integer
+---@param b integer
+---@param a? integer defaults to 255
+---@return integer
+function colour(r, g, b, a) end
+
+---Skia's named colours, e.g. `colours.red`.
+---@type table<"transparent"|"black"|"dark_gray"|"gray"|"light_gray"|"white"|"red"|"green"|"blue"|"yellow"|"cyan"|"magenta", integer>
+colours = {}
+
+---Named colours and sizes for the whole config, read back with `theme`. Defined by the config
+---and checked once it has run, before `opts` is called, so a bad entry fails the load. Strings
+---are colours, as "#RRGGBB", "#AARRGGBB" or a name from `colours`, and numbers are sizes.
+---Anything taking a colour also takes the name of a colour entry, e.g. `background_colour =
+---"accent"`, but not of a size.
+---@type table<string, number|string>?
+palette = nil
+
+---An entry of `palette`, e.g. `canvas:set_paint_colour(theme("accent"))`. Colours are returned as
+---0xAARRGGBB numbers. Errors if there is no such entry and no default.
+---@param name string
+---@param default? any returned when the theme has no entry `name`
+---@return any
+function theme(name, default) end
+
+---The system clock, in the local timezone.
+time = {}
+
+---@return integer seconds since the unix epoch
+function time.now() end
+
+---@param fmt string strftime style format, e.g. "%H:%M"
+---@param timestamp? integer unix seconds, defaults to now
+---@return string
+function time.format(fmt, timestamp) end
+
+---Runs `cmd` with `sh -c` and waits for it. This blocks the bar while the command runs, use
+---`exec_async` for anything slow. Output past 64KiB is discarded.
+---@param cmd string
+---@return string stdout
+---@return integer code exit code, -1 if killed by a signal
+function exec(cmd) end
+
+---Runs `cmd` with `sh -c` in the background and calls `callback` once it exits, unless the
+---config has been reloaded in the meantime.
+---@param cmd string
+---@param callback fun(stdout: string, code: integer)|string
+function exec_async(cmd, callback) end
+
+---Runs `cmd` in the background straight away and then every `ms` milliseconds, calling
+---`callback` with its output and redrawing after each run, like `set_interval` around
+---`exec_async`. Stops when the config is reloaded.
+---@param ms integer
+---@param cmd string
+---@param callback fun(stdout: string, code: integer)|string
+function poll(ms, cmd, callback) end
+
+---Reads an environment variable of the bar's process.
+---@param name string
+---@return string? value nil when unset
+function env(name) end
+
+---@param path string
+---@return string? contents
+---@return string? err
+function read_file(path) end
+
+---Calls `callback` with the new contents of `path` when they change, or nil when the file is
+---removed. The file is checked every second and does not need to exist yet.
+---@param path string
+---@param callback fun(contents: string?)|string
+function watch_file(path, callback) end
+
+---@class TrayPixmap
+---@field width integer
+---@field height integer
+---@field data string ARGB32 pixels in network byte order
+
+---@class TrayItem
+---@field service string identifies the item in calls to `tray`
+---@field id string
+---@field title string
+---@field status "Passive"|"Active"|"NeedsAttention"
+---@field icon_name string themed icon name, may be empty
+---@field icon? TrayPixmap the largest pixmap the item provides
+---@field menu string dbusmenu object path, may be empty
+
+---StatusNotifierItem system tray. The tray starts on first use, acting as the
+---StatusNotifierWatcher unless another program already runs one.
+tray = {}
+
+---@return TrayItem[]
+function tray.items() end
+
+---@param service string
+---@param x integer screen position of the click
+---@param y integer
+function tray.activate(service, x, y) end
+
+---@param service string
+---@param x integer
+---@param y integer
+function tray.secondary_activate(service, x, y) end
+
+---Asks the item to show its own context menu.
+---@param service string
+---@param x integer
+---@param y integer
+function tray.context_menu(service, x, y) end
+
+---@param service string
+---@param delta integer
+---@param orientation "vertical"|"horizontal"
+function tray.scroll(service, delta, orientation) end
+
+---Called when tray items are added, removed or changed.
+function on_tray_changed() end
+
+---@class MprisPlayer
+---@field name string the player's bus name without "org.mpris.MediaPlayer2.", e.g. "firefox"
+---@field title string
+---@field artist string the track's artists, joined with commas
+---@field album string
+---@field status "Playing"|"Paused"|"Stopped"
+
+---The active MPRIS media player: the first one playing, otherwise the first paused. Followed
+---on the session bus once first used.
+mpris = {}
+
+---@return MprisPlayer? player nil when no player is running
+function mpris.player() end
+
+function mpris.play_pause() end
+function mpris.next() end
+function mpris.previous() end
+
+---Called when the active player or what it is playing changes.
+---@param player MprisPlayer?
+function on_mpris_changed(player) end
+
+---Volume of the default sink, followed through `pactl` so it works with PulseAudio and
+---PipeWire.
+audio = {}
+
+---@return integer? percent averaged across channels, nil if the sound server is unreachable or until it first answers, after which `on_audio_changed` is called
+function audio.volume() end
+
+---@return boolean? nil like `audio.volume`
+function audio.muted() end
+
+---@param delta integer percent to add, negative to lower the volume
+function audio.set_volume(delta) end
+
+---@param muted? boolean toggles when omitted
+function audio.set_muted(muted) end
+
+---Called when the default sink's volume or mute state changes.
+---@param volume integer?
+---@param muted boolean?
+function on_audio_changed(volume, muted) end
+
+---@class Workspace
+---@field name string
+---@field num integer the number the name starts with, -1 when it has none
+---@field focused boolean
+---@field visible boolean shown on its output, though possibly not focused
+---@field urgent boolean
+---@field output string
+
+---Sway or i3 workspaces, through the ipc socket at $SWAYSOCK or $I3SOCK. Changes are followed
+---once first used.
+wm = {}
+
+---@return Workspace[]
+function wm.workspaces() end
+
+---@return string? name of the focused workspace
+function wm.focused() end
+
+---Switches to the workspace, e.g. from `on_click`.
+---@param name string
+function wm.focus(name) end
+
+---Called when workspaces are added, removed, focused or marked urgent.
+function on_workspaces_changed() end
+
+---CPU and memory usage from /proc.
+sys = {}
+
+---CPU usage across all cores since the previous call, or since boot on the first. Call it at
+---a steady interval, e.g. from `set_interval`, for a meaningful reading.
+---@return number? percent nil if /proc/stat is unreadable
+function sys.cpu_percent() end
+
+---Memory in use, excluding what the kernel can reclaim such as caches.
+---@return integer? used bytes, nil if /proc/meminfo is unreadable
+---@return integer? total bytes
+function sys.mem() end
+
+---Battery state from /sys/class/power_supply. Multiple batteries are combined into one.
+---Each call reads sysfs. To update when the battery changes, `watch_file` a file such as
+---"uevent" in one of `battery.paths()`.
+battery = {}
+
+---@return integer? percent nil without a battery
+function battery.percent() end
+
+---@return "Charging"|"Discharging"|"Full"|"Not charging"|"Unknown"|nil
+function battery.status() end
+
+---@return integer? seconds until empty, or until full while charging
+function battery.time_remaining() end
+
+---@return string[] directories of each battery, e.g. "/sys/class/power_supply/BAT0"
+function battery.paths() end
+
+---Interpolation and easing for animations, advanced with the delta passed to `draw`.
+anim = {}
+
+---@alias Easing "linear"|"ease_in"|"ease_out"|"ease_in_out"
+
+---@param a number
+---@param b number
+---@param t number 0 gives `a` and 1 gives `b`, not clamped
+---@return number
+function anim.lerp(a, b, t) end
+
+---@param t number progress, clamped to 0 to 1
+---@return number
+function anim.ease_in(t) end
+
+---@param t number progress, clamped to 0 to 1
+---@return number
+function anim.ease_out(t) end
+
+---@param t number progress, clamped to 0 to 1
+---@return number
+function anim.ease_in_out(t) end
+
+---@class Tween
+local Tween = {}
+
+---Advances the tween and returns its new value.
+---@param delta number seconds
+---@return number
+function Tween:update(delta) end
+
+---@return number
+function Tween:value() end
+
+---@return boolean
+function Tween:done() end
+
+---Starts moving towards `to` from the current value.
+---@param to number
+function Tween:retarget(to) end
+
+---@param from number
+---@param to number
+---@param duration number seconds
+---@param easing? Easing defaults to "linear"
+---@return Tween
+function anim.tween(from, to, duration, easing) end
+
+---Places modules one after another, so their offsets need not be tracked by hand.
+layout = {}
+
+---@class Layout
+local Layout = {}
+
+---Returns where a module of the given width, or height in a column, goes and moves past it.
+---@param size number
+---@return number x
+---@return number y
+function Layout:add(size) end
+
+---The space taken by the modules added so far, gaps between them included.
+---@return number
+function Layout:size() end
+
+---Starts over from the layout's origin, such as at the start of each frame.
+function Layout:reset() end
+
+---@param direction "row"|"column"
+---@param gap? number space between modules, defaults to 0
+---@param x? number where the first module goes, defaults to 0
+---@param y? number defaults to 0
+---@return Layout
+function layout.new(direction, gap, x, y) end
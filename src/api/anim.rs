@@ -0,0 +1,179 @@
+//! Interpolation and easing for animating the bar, advanced by the delta passed to `draw`.
+
+use mlua::{FromLua, Lua, Table, UserData, UserDataMethods};
+
+/// The `anim` table: easing curves and tweens.
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set(
+        "lerp",
+        lua.create_function(|_, (a, b, t): (f64, f64, f64)| Ok(lerp(a, b, t)))?,
+    )?;
+    t.set(
+        "ease_in",
+        lua.create_function(|_, t: f64| Ok(Easing::In.apply(t)))?,
+    )?;
+    t.set(
+        "ease_out",
+        lua.create_function(|_, t: f64| Ok(Easing::Out.apply(t)))?,
+    )?;
+    t.set(
+        "ease_in_out",
+        lua.create_function(|_, t: f64| Ok(Easing::InOut.apply(t)))?,
+    )?;
+    t.set("tween", lua.create_function(tween)?)?;
+    Ok(t)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Curves mapping progress from 0 to 1 onto eased progress, clamped to that range.
+#[derive(Debug, Clone, Copy, Default)]
+enum Easing {
+    #[default]
+    Linear,
+    In,
+    Out,
+    InOut,
+}
+
+impl Easing {
+    fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::In => t * t,
+            Easing::Out => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::InOut if t < 0.5 => 2.0 * t * t,
+            Easing::InOut => 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0,
+        }
+    }
+}
+
+impl FromLua for Easing {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        match String::from_lua(value, lua)?.as_str() {
+            "linear" => Ok(Easing::Linear),
+            "ease_in" => Ok(Easing::In),
+            "ease_out" => Ok(Easing::Out),
+            "ease_in_out" => Ok(Easing::InOut),
+            other => Err(mlua::Error::runtime(format!(
+                r#"unknown easing "{other}", expected "linear", "ease_in", "ease_out" or "ease_in_out""#
+            ))),
+        }
+    }
+}
+
+/// Moves from one value to another over `duration` seconds.
+struct Tween {
+    from: f64,
+    to: f64,
+    duration: f64,
+    elapsed: f64,
+    easing: Easing,
+}
+
+impl Tween {
+    fn value(&self) -> f64 {
+        let t = if self.duration > 0.0 {
+            self.elapsed / self.duration
+        } else {
+            1.0
+        };
+        lerp(self.from, self.to, self.easing.apply(t))
+    }
+
+    fn done(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+impl UserData for Tween {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("update", |_, this, delta: f64| {
+            this.elapsed = (this.elapsed + delta.max(0.0)).min(this.duration);
+            Ok(this.value())
+        });
+
+        methods.add_method("value", |_, this, ()| Ok(this.value()));
+
+        methods.add_method("done", |_, this, ()| Ok(this.done()));
+
+        // Restarts from the current value, so retargeting mid-way does not jump.
+        methods.add_method_mut("retarget", |_, this, to: f64| {
+            this.from = this.value();
+            this.to = to;
+            this.elapsed = 0.0;
+            Ok(())
+        });
+    }
+}
+
+fn tween(
+    _: &Lua,
+    (from, to, duration, easing): (f64, f64, f64, Option<Easing>),
+) -> mlua::Result<Tween> {
+    if duration < 0.0 {
+        return Err(mlua::Error::runtime(
+            "anim.tween: duration must not be negative",
+        ));
+    }
+
+    Ok(Tween {
+        from,
+        to,
+        duration,
+        elapsed: 0.0,
+        easing: easing.unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 4] = [Easing::Linear, Easing::In, Easing::Out, Easing::InOut];
+
+    #[test]
+    fn easings_run_from_zero_to_one() {
+        for easing in EASINGS {
+            assert_eq!(easing.apply(0.0), 0.0, "{easing:?}");
+            assert_eq!(easing.apply(1.0), 1.0, "{easing:?}");
+            assert_eq!(easing.apply(-1.0), 0.0, "{easing:?}");
+            assert_eq!(easing.apply(2.0), 1.0, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn easings_curve() {
+        assert_eq!(Easing::Linear.apply(0.25), 0.25);
+        assert_eq!(Easing::In.apply(0.5), 0.25);
+        assert_eq!(Easing::Out.apply(0.5), 0.75);
+        assert_eq!(Easing::InOut.apply(0.25), 0.125);
+        assert_eq!(Easing::InOut.apply(0.5), 0.5);
+        assert_eq!(Easing::InOut.apply(0.75), 0.875);
+    }
+
+    #[test]
+    fn tween_reaches_target() {
+        let lua = Lua::new();
+        let mut tween = tween(&lua, (10.0, 20.0, 2.0, None)).unwrap();
+        assert_eq!(tween.value(), 10.0);
+
+        tween.elapsed = 1.0;
+        assert_eq!(tween.value(), 15.0);
+        assert!(!tween.done());
+
+        tween.elapsed = 2.0;
+        assert_eq!(tween.value(), 20.0);
+        assert!(tween.done());
+
+        assert!(super::tween(&lua, (0.0, 1.0, -1.0, None)).is_err());
+        assert_eq!(
+            super::tween(&lua, (0.0, 1.0, 0.0, None)).unwrap().value(),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Volume and mute state of the default sink. Talks to the sound server through `pactl`, which
+//! works with both PulseAudio and PipeWire's pulse server, and follows changes with
+//! `pactl subscribe` instead of polling.
+
+use std::{
+    io::{BufRead, BufReader},
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+use calloop::channel;
+use mlua::{Lua, Table};
+
+use super::{Task, call_global, push_event};
+use crate::window::Event;
+
+const DEFAULT_SINK: &str = "@DEFAULT_SINK@";
+
+/// Audio state kept as lua app data, created by the first call into the audio table.
+struct Audio {
+    volume: Option<u32>,
+    muted: Option<bool>,
+    subscriber: Child,
+}
+
+impl Drop for Audio {
+    // Ends the subscription when the config is reloaded, which stops the watching thread.
+    fn drop(&mut self) {
+        let _ = self.subscriber.kill();
+        let _ = self.subscriber.wait();
+    }
+}
+
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("volume", lua.create_function(volume)?)?;
+    t.set("muted", lua.create_function(muted)?)?;
+    t.set("set_volume", lua.create_function(set_volume)?)?;
+    t.set("set_muted", lua.create_function(set_muted)?)?;
+    Ok(t)
+}
+
+fn volume(lua: &Lua, _: ()) -> mlua::Result<Option<u32>> {
+    start(lua)?;
+    Ok(lua.app_data_ref::<Audio>().and_then(|a| a.volume))
+}
+
+fn muted(lua: &Lua, _: ()) -> mlua::Result<Option<bool>> {
+    start(lua)?;
+    Ok(lua.app_data_ref::<Audio>().and_then(|a| a.muted))
+}
+
+fn set_volume(_: &Lua, delta: i32) -> mlua::Result<()> {
+    run_detached(pactl(&[
+        "set-sink-volume",
+        DEFAULT_SINK,
+        &format!("{delta:+}%"),
+    ]));
+    Ok(())
+}
+
+fn set_muted(_: &Lua, muted: Option<bool>) -> mlua::Result<()> {
+    let state = match muted {
+        Some(true) => "1",
+        Some(false) => "0",
+        None => "toggle",
+    };
+    run_detached(pactl(&["set-sink-mute", DEFAULT_SINK, state]));
+    Ok(())
+}
+
+/// Starts following the default sink, unless this lua state already does.
+fn start(lua: &Lua) -> mlua::Result<()> {
+    if lua.app_data_ref::<Audio>().is_some() {
+        return Ok(());
+    }
+
+    let tasks = super::task_sender(lua)?;
+    let mut subscriber = pactl(&["subscribe"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| mlua::Error::runtime(format!("audio: failed to run pactl: {e}")))?;
+    let stdout = subscriber.stdout.take().expect("stdout is piped");
+    thread::spawn(move || watch(stdout, tasks));
+
+    // Unknown until the watching thread's first query completes.
+    lua.set_app_data(Audio {
+        volume: None,
+        muted: None,
+        subscriber,
+    });
+    Ok(())
+}
+
+fn watch(stdout: ChildStdout, tasks: channel::Sender<Task>) {
+    // The first query runs here too, so the bar never waits on pactl.
+    let mut last = query();
+    if send_state(&tasks, last).is_err() {
+        return;
+    }
+
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        // Sink events cover volume and mute, server events a change of the default sink.
+        if !line.contains(" on sink ") && !line.contains(" on server ") {
+            continue;
+        }
+
+        let state = query();
+        if state == last {
+            continue;
+        }
+        last = state;
+
+        if send_state(&tasks, state).is_err() {
+            break;
+        }
+    }
+}
+
+/// Hands the volume and mute state to lua, failing once the lua state has been replaced.
+fn send_state(
+    tasks: &channel::Sender<Task>,
+    (volume, muted): (Option<u32>, Option<bool>),
+) -> Result<(), mpsc::SendError<Task>> {
+    tasks.send(Box::new(move |lua: &Lua| {
+        if let Some(mut audio) = lua.app_data_mut::<Audio>() {
+            audio.volume = volume;
+            audio.muted = muted;
+        }
+        if let Err(e) = call_global(lua, "on_audio_changed", (volume, muted)) {
+            log::error!("on_audio_changed failed: {e}");
+        }
+        push_event(lua, Event::Redraw);
+    }))
+}
+
+fn query() -> (Option<u32>, Option<bool>) {
+    let output = |args: &[&str]| {
+        pactl(args)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+    };
+
+    let volume = output(&["get-sink-volume", DEFAULT_SINK]).and_then(|s| parse_volume(&s));
+    let muted = output(&["get-sink-mute", DEFAULT_SINK]).map(|s| s.trim().ends_with("yes"));
+    (volume, muted)
+}
+
+/// Averages the per channel percentages in `pactl get-sink-volume` output.
+fn parse_volume(s: &str) -> Option<u32> {
+    let percents: Vec<u32> = s
+        .split_whitespace()
+        .filter_map(|w| w.strip_suffix('%')?.parse().ok())
+        .collect();
+    (!percents.is_empty()).then(|| percents.iter().sum::<u32>() / percents.len() as u32)
+}
+
+fn pactl(args: &[&str]) -> Command {
+    let mut cmd = Command::new("pactl");
+    // Output is parsed, so keep it unlocalised.
+    cmd.env("LC_ALL", "C").args(args);
+    cmd
+}
+
+fn run_detached(mut cmd: Command) {
+    thread::spawn(move || {
+        if let Err(e) = cmd.status() {
+            log::error!("audio: failed to run pactl: {e}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_channel_volumes() {
+        let output = "Volume: front-left: 32768 /  50% / -18.06 dB,   \
+                      front-right: 39322 /  60% / -13.31 dB\n        balance 0.17\n";
+        assert_eq!(parse_volume(output), Some(55));
+        assert_eq!(
+            parse_volume("Volume: mono: 65536 / 100% / 0.00 dB"),
+            Some(100)
+        );
+        assert_eq!(parse_volume("Failed to get sink volume"), None);
+    }
+}
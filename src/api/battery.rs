@@ -0,0 +1,295 @@
+//! Battery state read from sysfs. Systems with several batteries are reported as one.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use mlua::{Lua, Table};
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+struct Battery {
+    status: String,
+    /// How full it is, from 0 to 1.
+    level: f64,
+    /// Known when the battery reports its energy or its charge and voltage.
+    energy: Option<Energy>,
+}
+
+/// A battery's energy in µWh and power draw in µW, which unlike charge in µAh can be added up
+/// across batteries of different voltages.
+struct Energy {
+    now: f64,
+    full: f64,
+    power: Option<f64>,
+}
+
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set(
+        "percent",
+        lua.create_function(|_, ()| Ok(percent(&batteries(Path::new(POWER_SUPPLY)))))?,
+    )?;
+    t.set(
+        "status",
+        lua.create_function(|_, ()| Ok(status(&batteries(Path::new(POWER_SUPPLY)))))?,
+    )?;
+    t.set(
+        "time_remaining",
+        lua.create_function(|_, ()| Ok(time_remaining(&batteries(Path::new(POWER_SUPPLY)))))?,
+    )?;
+    t.set(
+        "paths",
+        lua.create_function(|_, ()| {
+            Ok(battery_dirs(Path::new(POWER_SUPPLY))
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect::<Vec<_>>())
+        })?,
+    )?;
+    Ok(t)
+}
+
+/// Directories of the batteries under `root`, named BAT0, BAT1 and so on.
+fn battery_dirs(root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("BAT"))
+        .map(|e| e.path())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+fn batteries(root: &Path) -> Vec<Battery> {
+    battery_dirs(root)
+        .iter()
+        .filter_map(|dir| read_battery(dir))
+        .collect()
+}
+
+fn read_battery(dir: &Path) -> Option<Battery> {
+    let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+    let number = |name: &str| read(name)?.trim().parse::<f64>().ok();
+
+    let status = read("status").map_or_else(|| "Unknown".to_string(), |s| s.trim().to_string());
+    // In volts, preferring the design voltage which unlike voltage_now doesn't sag as the
+    // battery drains.
+    let volts = number("voltage_min_design")
+        .or_else(|| number("voltage_now"))
+        .map(|uv| uv / 1_000_000.0);
+    let charge = number("charge_now").zip(number("charge_full"));
+
+    let energy = match (number("energy_now"), number("energy_full")) {
+        (Some(now), Some(full)) => Some(Energy {
+            now,
+            full,
+            power: number("power_now"),
+        }),
+        _ => charge.zip(volts).map(|((now, full), volts)| Energy {
+            now: now * volts,
+            full: full * volts,
+            power: number("current_now").map(|current| current * volts),
+        }),
+    }
+    .filter(|e| e.full > 0.0)
+    .map(|e| Energy {
+        power: e.power.map(f64::abs).filter(|p| *p > 0.0),
+        ..e
+    });
+
+    let level = match (&energy, charge) {
+        (Some(e), _) => e.now / e.full,
+        (None, Some((now, full))) if full > 0.0 => now / full,
+        // Some drivers only report a percentage.
+        _ => number("capacity")? / 100.0,
+    };
+
+    Some(Battery {
+        status,
+        level,
+        energy,
+    })
+}
+
+/// The remaining energy of all batteries over their total when every battery's energy is
+/// known, otherwise the average of their levels.
+fn percent(batteries: &[Battery]) -> Option<u32> {
+    if batteries.is_empty() {
+        return None;
+    }
+
+    let level = match energies(batteries) {
+        Some(energies) => {
+            energies.iter().map(|e| e.now).sum::<f64>()
+                / energies.iter().map(|e| e.full).sum::<f64>()
+        }
+        None => batteries.iter().map(|b| b.level).sum::<f64>() / batteries.len() as f64,
+    };
+    Some((level * 100.0).round().clamp(0.0, 100.0) as u32)
+}
+
+/// The energy of every battery, or `None` if any battery's is unknown.
+fn energies(batteries: &[Battery]) -> Option<Vec<&Energy>> {
+    batteries.iter().map(|b| b.energy.as_ref()).collect()
+}
+
+/// "Charging" or "Discharging" if any battery is, otherwise the status of the first battery.
+fn status(batteries: &[Battery]) -> Option<String> {
+    ["Charging", "Discharging"]
+        .into_iter()
+        .find(|s| batteries.iter().any(|b| b.status == *s))
+        .map(str::to_string)
+        .or_else(|| batteries.first().map(|b| b.status.clone()))
+}
+
+/// Seconds until empty while discharging, or until full while charging.
+fn time_remaining(batteries: &[Battery]) -> Option<u64> {
+    let energies = energies(batteries)?;
+    let power: f64 = energies.iter().filter_map(|e| e.power).sum();
+    if power <= 0.0 {
+        return None;
+    }
+
+    let now: f64 = energies.iter().map(|e| e.now).sum();
+    let full: f64 = energies.iter().map(|e| e.full).sum();
+    let hours = match status(batteries)?.as_str() {
+        "Charging" => (full - now).max(0.0) / power,
+        "Discharging" => now / power,
+        _ => return None,
+    };
+    Some((hours * 3600.0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Writes a fake power_supply dir with the batteries in `supplies`, given as a dir name
+    /// and its files.
+    fn sysfs(name: &str, supplies: &[(&str, &[(&str, &str)])]) -> PathBuf {
+        let root = env::temp_dir().join(format!("status-bar-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (supply, files) in supplies {
+            let dir = root.join(supply);
+            fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in *files {
+                fs::write(dir.join(file), format!("{contents}\n")).unwrap();
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn combines_batteries() {
+        let root = sysfs(
+            "battery-combined",
+            &[
+                (
+                    "BAT0",
+                    &[
+                        ("status", "Discharging"),
+                        ("energy_now", "20000000"),
+                        ("energy_full", "40000000"),
+                        ("power_now", "10000000"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[
+                        ("status", "Full"),
+                        ("charge_now", "4000000"),
+                        ("charge_full", "4000000"),
+                        ("voltage_now", "10000000"),
+                    ],
+                ),
+                ("AC", &[("online", "0")]),
+            ],
+        );
+
+        assert_eq!(battery_dirs(&root), [root.join("BAT0"), root.join("BAT1")]);
+        let batteries = batteries(&root);
+        assert_eq!(percent(&batteries), Some(75));
+        assert_eq!(status(&batteries).as_deref(), Some("Discharging"));
+        // BAT1 holds 4 Ah at 10 V, so 60 of 80 Wh are left at 10 W.
+        assert_eq!(time_remaining(&batteries), Some(6 * 3600));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn averages_batteries_without_energy() {
+        let root = sysfs(
+            "battery-charge",
+            &[
+                (
+                    "BAT0",
+                    &[
+                        ("status", "Discharging"),
+                        ("energy_now", "10000000"),
+                        ("energy_full", "40000000"),
+                        ("power_now", "10000000"),
+                    ],
+                ),
+                (
+                    "BAT1",
+                    &[
+                        ("status", "Discharging"),
+                        ("charge_now", "1500000"),
+                        ("charge_full", "2000000"),
+                        ("current_now", "-1000000"),
+                    ],
+                ),
+            ],
+        );
+
+        // Without a voltage BAT1's charge can't be weighed against BAT0's energy, so their
+        // levels of 25% and 75% are averaged.
+        let unweighed = batteries(&root);
+        assert_eq!(percent(&unweighed), Some(50));
+        assert_eq!(time_remaining(&unweighed), None);
+
+        fs::write(root.join("BAT1/voltage_min_design"), "10000000\n").unwrap();
+        // BAT1 holds 1.5 of 2 Ah at 10 V, so 25 of 60 Wh are left at 20 W.
+        let weighed = batteries(&root);
+        assert_eq!(percent(&weighed), Some(42));
+        assert_eq!(time_remaining(&weighed), Some(4500));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_capacity() {
+        let root = sysfs(
+            "battery-capacity",
+            &[("BAT0", &[("status", "Charging"), ("capacity", "42")])],
+        );
+
+        let batteries = batteries(&root);
+        assert_eq!(percent(&batteries), Some(42));
+        assert_eq!(status(&batteries).as_deref(), Some("Charging"));
+        assert_eq!(time_remaining(&batteries), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn reports_nothing_without_batteries() {
+        let root = sysfs("battery-none", &[("AC", &[("online", "1")])]);
+
+        let batteries = batteries(&root);
+        assert!(batteries.is_empty());
+        assert_eq!(percent(&batteries), None);
+        assert_eq!(status(&batteries), None);
+
+        fs::remove_dir_all(root).unwrap();
+        assert!(battery_dirs(&root).is_empty());
+    }
+}
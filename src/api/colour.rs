@@ -0,0 +1,118 @@
+//! Building the packed ARGB colours taken by the canvas.
+
+use mlua::{FromLua, Lua, Table};
+use skia_safe::Color;
+
+use super::theme::Entry;
+
+const NAMED: [(&str, Color); 12] = [
+    ("transparent", Color::TRANSPARENT),
+    ("black", Color::BLACK),
+    ("dark_gray", Color::DARK_GRAY),
+    ("gray", Color::GRAY),
+    ("light_gray", Color::LIGHT_GRAY),
+    ("white", Color::WHITE),
+    ("red", Color::RED),
+    ("green", Color::GREEN),
+    ("blue", Color::BLUE),
+    ("yellow", Color::YELLOW),
+    ("cyan", Color::CYAN),
+    ("magenta", Color::MAGENTA),
+];
+
+/// The `colours` table: Skia's named colours.
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    for (name, colour) in NAMED {
+        t.set(name, pack(colour))?;
+    }
+    Ok(t)
+}
+
+/// Packs 0 to 255 channels into `0xAARRGGBB`, opaque when `a` is omitted.
+pub fn colour(_: &Lua, (r, g, b, a): (u8, u8, u8, Option<u8>)) -> mlua::Result<u32> {
+    Ok(pack(Color::from_argb(a.unwrap_or(255), r, g, b)))
+}
+
+/// Parses `"#RRGGBB"`, `"#AARRGGBB"` or the name of one of Skia's named colours.
+pub fn parse(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        return match hex.len() {
+            6 => Some(0xFF000000 | value),
+            8 => Some(value),
+            _ => None,
+        };
+    }
+
+    NAMED
+        .iter()
+        .find(|(name, _)| *name == s)
+        .map(|(_, colour)| pack(*colour))
+}
+
+/// A colour from lua: packed `0xAARRGGBB`, a string `parse` understands, or the name of a
+/// colour in the theme.
+#[derive(Debug, Clone, Copy)]
+pub struct Colour(pub u32);
+
+impl FromLua for Colour {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        let mlua::Value::String(s) = value else {
+            return u32::from_lua(value, lua).map(Colour);
+        };
+
+        let s = s.to_str()?;
+        match super::theme::get(lua, &s) {
+            Some(Entry::Colour(colour)) => return Ok(Colour(colour)),
+            Some(Entry::Size(_)) => {
+                return Err(mlua::Error::runtime(format!(
+                    r#""{s}" is a size in the theme, not a colour"#
+                )));
+            }
+            None => {}
+        }
+        parse(&s).map(Colour).ok_or_else(|| {
+            mlua::Error::runtime(format!(
+                r##""{s}" is not a colour, expected "#RRGGBB", "#AARRGGBB", a named colour or an entry of the theme"##
+            ))
+        })
+    }
+}
+
+fn pack(colour: Color) -> u32 {
+    u32::from_be_bytes([colour.a(), colour.r(), colour.g(), colour.b()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_and_names() {
+        assert_eq!(parse("#ff8000"), Some(0xFFFF8000));
+        assert_eq!(parse("#80FF8000"), Some(0x80FF8000));
+        assert_eq!(parse("red"), Some(0xFFFF0000));
+        assert_eq!(parse("transparent"), Some(0));
+    }
+
+    #[test]
+    fn rejects_malformed_colours() {
+        assert_eq!(parse("#fff"), None);
+        assert_eq!(parse("#ff80001"), None);
+        assert_eq!(parse("#+f8000"), None);
+        assert_eq!(parse("ff8000"), None);
+        assert_eq!(parse("purple"), None);
+    }
+
+    #[test]
+    fn packs_channels() {
+        let lua = Lua::new();
+        assert_eq!(colour(&lua, (255, 0, 0, Some(255))).unwrap(), 0xFFFF0000);
+        assert_eq!(colour(&lua, (0x12, 0x34, 0x56, None)).unwrap(), 0xFF123456);
+        assert_eq!(colour(&lua, (0, 0, 255, Some(0x80))).unwrap(), 0x800000FF);
+    }
+}
@@ -0,0 +1,29 @@
+use std::{fs, path::PathBuf};
+
+use mlua::Lua;
+
+use crate::{
+    api::{Callback, push_event},
+    window::Event,
+};
+
+/// Returns the contents of `path`, or nil and an error message.
+pub fn read_file(_: &Lua, path: String) -> mlua::Result<(Option<String>, Option<String>)> {
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok((Some(contents), None)),
+        Err(e) => Ok((None, Some(format!("{path}: {e}")))),
+    }
+}
+
+/// Calls `callback` with the new contents of `path` whenever they change, or with nil when
+/// the file is removed. The file does not need to exist yet.
+pub fn watch_file(lua: &Lua, (path, callback): (String, Callback)) -> mlua::Result<()> {
+    push_event(
+        lua,
+        Event::WatchFile {
+            path: PathBuf::from(path),
+            callback,
+        },
+    );
+    Ok(())
+}
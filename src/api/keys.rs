@@ -0,0 +1,92 @@
+//! Matching key presses from `on_key` against combos like `"ctrl+shift+q"`.
+
+use mlua::Lua;
+
+use crate::window::Modifiers;
+
+/// Whether the key `name`, pressed with `modifiers`, is the combo `spec`: modifiers and a key
+/// joined by `+`, in any case. The modifiers held must be exactly those in `spec`.
+pub fn key_combo(
+    _: &Lua,
+    (name, modifiers, spec): (String, Modifiers, String),
+) -> mlua::Result<bool> {
+    let (key, required) =
+        parse(&spec).map_err(|e| mlua::Error::runtime(format!("key_combo: {e}")))?;
+    Ok(name.eq_ignore_ascii_case(&key) && modifiers == required)
+}
+
+/// Splits `spec` into its key and the modifiers it needs.
+fn parse(spec: &str) -> Result<(String, Modifiers), String> {
+    let mut modifiers = Modifiers::default();
+    let mut key = None;
+    for part in spec.split('+').map(str::trim) {
+        let held = match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => &mut modifiers.control,
+            "shift" => &mut modifiers.shift,
+            "alt" => &mut modifiers.alt,
+            "meta" | "super" | "logo" => &mut modifiers.meta,
+            "" => return Err(format!(r#""{spec}" has an empty part"#)),
+            _ if key.is_some() => return Err(format!(r#""{spec}" names more than one key"#)),
+            _ => {
+                key = Some(part.to_string());
+                continue;
+            }
+        };
+        *held = true;
+    }
+
+    let key = key.ok_or_else(|| format!(r#""{spec}" names no key"#))?;
+    Ok((key, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modifiers_and_key() {
+        let (key, modifiers) = parse("Ctrl + shift+Q").unwrap();
+        assert_eq!(key, "Q");
+        assert_eq!(
+            modifiers,
+            Modifiers {
+                control: true,
+                shift: true,
+                ..Modifiers::default()
+            }
+        );
+
+        let (key, modifiers) = parse("super+Return").unwrap();
+        assert_eq!(key, "Return");
+        assert!(modifiers.meta && !modifiers.alt);
+
+        assert_eq!(parse("a").unwrap(), ("a".to_string(), Modifiers::default()));
+    }
+
+    #[test]
+    fn rejects_malformed_combos() {
+        assert!(parse("ctrl+").is_err());
+        assert!(parse("ctrl+shift").is_err());
+        assert!(parse("a+b").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn matches_exact_modifiers() {
+        let lua = Lua::new();
+        let ctrl = Modifiers {
+            control: true,
+            ..Modifiers::default()
+        };
+        let combo = |name: &str, modifiers: &Modifiers, spec: &str| {
+            key_combo(
+                &lua,
+                (name.to_string(), modifiers.clone(), spec.to_string()),
+            )
+            .unwrap()
+        };
+        assert!(combo("q", &ctrl, "ctrl+Q"));
+        assert!(!combo("q", &Modifiers::default(), "ctrl+q"));
+        assert!(!combo("w", &ctrl, "ctrl+q"));
+    }
+}
@@ -0,0 +1,76 @@
+//! Cursor based placement of modules, so configs need not track offsets by hand.
+
+use mlua::{FromLua, Lua, Table, UserData, UserDataMethods};
+
+/// The `layout` table.
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("new", lua.create_function(new)?)?;
+    Ok(t)
+}
+
+/// The axis modules are stacked along.
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Row,
+    Column,
+}
+
+impl FromLua for Direction {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        match String::from_lua(value, lua)?.as_str() {
+            "row" => Ok(Direction::Row),
+            "column" => Ok(Direction::Column),
+            other => Err(mlua::Error::runtime(format!(
+                r#"unknown direction "{other}", expected "row" or "column""#
+            ))),
+        }
+    }
+}
+
+/// Hands out positions one after another along its direction, `gap` apart.
+struct Layout {
+    direction: Direction,
+    gap: f64,
+    origin: (f64, f64),
+    /// How far along the direction the next module starts, from `origin`.
+    cursor: f64,
+}
+
+impl Layout {
+    /// Where a module `size` long goes, moving the cursor past it.
+    fn add(&mut self, size: f64) -> (f64, f64) {
+        let position = match self.direction {
+            Direction::Row => (self.origin.0 + self.cursor, self.origin.1),
+            Direction::Column => (self.origin.0, self.origin.1 + self.cursor),
+        };
+        self.cursor += size + self.gap;
+        position
+    }
+}
+
+impl UserData for Layout {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("add", |_, this, size: f64| Ok(this.add(size)));
+
+        // The space taken so far, without the gap after the last module.
+        methods.add_method("size", |_, this, ()| Ok((this.cursor - this.gap).max(0.0)));
+
+        methods.add_method_mut("reset", |_, this, ()| {
+            this.cursor = 0.0;
+            Ok(())
+        });
+    }
+}
+
+fn new(
+    _: &Lua,
+    (direction, gap, x, y): (Direction, Option<f64>, Option<f64>, Option<f64>),
+) -> mlua::Result<Layout> {
+    Ok(Layout {
+        direction,
+        gap: gap.unwrap_or(0.0),
+        origin: (x.unwrap_or(0.0), y.unwrap_or(0.0)),
+        cursor: 0.0,
+    })
+}
@@ -0,0 +1,542 @@
+use std::time::{Duration, Instant};
+
+use calloop::channel;
+use mlua::{FromLua, FromLuaMulti, IntoLuaMulti, Lua};
+
+use crate::window::{CursorShape, Event, KeyboardInteractivity, MAX_SIZE, Margin, Output};
+
+mod anim;
+mod audio;
+mod battery;
+pub mod colour;
+mod fs;
+mod keys;
+mod layout;
+mod mpris;
+pub mod process;
+mod sys;
+pub mod theme;
+mod time;
+mod tray;
+mod wm;
+
+/// Work sent from a background thread to run on the main thread, where lua lives.
+pub type Task = Box<dyn FnOnce(&Lua) + Send>;
+
+/// State shared between the window and the lua api, stored as lua app data so that
+/// functions called from the config can reach it.
+pub struct Context {
+    /// Events raised from lua, moved into the window's queue after each dispatch and after the
+    /// bars are drawn. Setters queue their change here rather than making it, so the window
+    /// applies them in order between frames.
+    pub events: Vec<Event>,
+    /// Connected outputs, kept up to date by the window.
+    pub outputs: Vec<Output>,
+    /// Kept up to date by the window.
+    pub pointer: Pointer,
+    /// Set once the config calls `hover`, after which pointer motion redraws the bar.
+    pub hover_used: bool,
+    pub keyboard_focus: bool,
+    /// One per bar, in the order `opts()` returned them, kept up to date by the window.
+    pub bars: Vec<Bar>,
+    /// The index of the bar being drawn, so `hover` can tell whether the pointer is over it.
+    pub drawing: Option<usize>,
+    /// Draw times of recent frames, kept up to date by the window. Unset until the first frame.
+    pub perf: Option<PerfStats>,
+    /// When the bar started, set by the window so that it survives reloads.
+    pub started: Instant,
+    /// Cloned into background services to run tasks against this lua state.
+    pub tasks: channel::Sender<Task>,
+    /// The receiving end of `tasks`, taken by the window and inserted into its event loop.
+    pub task_channel: Option<channel::Channel<Task>>,
+}
+
+impl Context {
+    fn new() -> Self {
+        let (tasks, task_channel) = channel::channel();
+        Context {
+            events: Vec::new(),
+            outputs: Vec::new(),
+            pointer: Pointer::default(),
+            hover_used: false,
+            keyboard_focus: false,
+            bars: Vec::new(),
+            drawing: None,
+            perf: None,
+            started: Instant::now(),
+            tasks,
+            task_channel: Some(task_channel),
+        }
+    }
+}
+
+/// The last known pointer position, in surface coordinates of the bar it was last over.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pointer {
+    pub x: f64,
+    pub y: f64,
+    /// Whether the pointer is over `bar`.
+    pub inside: bool,
+    pub bar: usize,
+}
+
+/// A summary of recent frames' draw times, in milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct PerfStats {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    /// Exceeded by only the slowest 1% of frames.
+    pub p99: f64,
+}
+
+impl PerfStats {
+    /// Summarises `times`, or `None` if there are none.
+    pub fn new(times: impl IntoIterator<Item = Duration>) -> Option<Self> {
+        let mut ms: Vec<f64> = times
+            .into_iter()
+            .map(|time| time.as_secs_f64() * 1000.0)
+            .collect();
+        if ms.is_empty() {
+            return None;
+        }
+        ms.sort_by(f64::total_cmp);
+
+        let last = ms.len() - 1;
+        Some(PerfStats {
+            min: ms[0],
+            avg: ms.iter().sum::<f64>() / ms.len() as f64,
+            max: ms[last],
+            p99: ms[(last as f64 * 0.99).round() as usize],
+        })
+    }
+}
+
+/// What lua can ask about a bar.
+#[derive(Debug, Clone)]
+pub struct Bar {
+    /// The layer surface's namespace.
+    pub namespace: String,
+    /// The transform of the output the bar is on.
+    pub transform: &'static str,
+}
+
+/// Converts an optional 1-based bar argument from lua into an index, defaulting to the first.
+fn bar_index(bar: Option<usize>) -> mlua::Result<usize> {
+    match bar {
+        Some(0) => Err(mlua::Error::runtime("bars are numbered from 1")),
+        Some(bar) => Ok(bar - 1),
+        None => Ok(0),
+    }
+}
+
+/// Looks up what lua can ask about the given bar, if it exists.
+fn bar_info(lua: &Lua, bar: Option<usize>) -> mlua::Result<Option<Bar>> {
+    let index = bar_index(bar)?;
+    Ok(lua
+        .app_data_ref::<Context>()
+        .and_then(|ctx| ctx.bars.get(index).cloned()))
+}
+
+/// A lua function, either passed directly or named by its global.
+#[derive(Debug, Clone)]
+pub enum Callback {
+    Global(String),
+    Function(mlua::Function),
+}
+
+impl Callback {
+    pub fn call<R: FromLuaMulti>(&self, lua: &Lua, args: impl IntoLuaMulti) -> mlua::Result<R> {
+        match self {
+            Callback::Global(name) => lua
+                .globals()
+                .get::<mlua::Function>(name.as_str())?
+                .call(args),
+            Callback::Function(f) => f.call(args),
+        }
+    }
+}
+
+impl FromLua for Callback {
+    fn from_lua(value: mlua::Value, _: &Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::String(s) => Ok(Callback::Global(s.to_string_lossy())),
+            mlua::Value::Function(f) => Ok(Callback::Function(f)),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "Callback".to_string(),
+                message: Some("expected a function or the name of a global function".to_string()),
+            }),
+        }
+    }
+}
+
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    lua.set_app_data(Context::new());
+
+    let g = lua.globals();
+    g.set("print", lua.create_function(print)?)?;
+    g.set("time", time::table(lua)?)?;
+    g.set("anim", anim::table(lua)?)?;
+    g.set("layout", layout::table(lua)?)?;
+    g.set("colour", lua.create_function(colour::colour)?)?;
+    g.set("colours", colour::table(lua)?)?;
+    g.set("theme", lua.create_function(theme::theme)?)?;
+    g.set("exec", lua.create_function(process::exec)?)?;
+    g.set("exec_async", lua.create_function(process::exec_async)?)?;
+    g.set("poll", lua.create_function(process::poll)?)?;
+    g.set("env", lua.create_function(process::getenv)?)?;
+    g.set("tray", tray::table(lua)?)?;
+    g.set("audio", audio::table(lua)?)?;
+    g.set("mpris", mpris::table(lua)?)?;
+    g.set("battery", battery::table(lua)?)?;
+    g.set("sys", sys::table(lua)?)?;
+    g.set("wm", wm::table(lua)?)?;
+    g.set("read_file", lua.create_function(fs::read_file)?)?;
+    g.set("watch_file", lua.create_function(fs::watch_file)?)?;
+    g.set("outputs", lua.create_function(outputs)?)?;
+    g.set("namespace", lua.create_function(namespace)?)?;
+    g.set("transform", lua.create_function(transform)?)?;
+    g.set("pointer_position", lua.create_function(pointer_position)?)?;
+    g.set("hover", lua.create_function(hover)?)?;
+    g.set("key_combo", lua.create_function(keys::key_combo)?)?;
+    g.set(
+        "has_keyboard_focus",
+        lua.create_function(has_keyboard_focus)?,
+    )?;
+    g.set("set_interval", lua.create_function(set_interval)?)?;
+    g.set("animate", lua.create_function(animate)?)?;
+    g.set("request_redraw", lua.create_function(request_redraw)?)?;
+    g.set("quit", lua.create_function(quit)?)?;
+    g.set("uptime", lua.create_function(uptime)?)?;
+    g.set("perf_stats", lua.create_function(perf_stats)?)?;
+    g.set("set_margin", lua.create_function(set_margin)?)?;
+    g.set("set_visible", lua.create_function(set_visible)?)?;
+    g.set("slide_offset", lua.create_function(slide_offset)?)?;
+    g.set("show_tooltip", lua.create_function(show_tooltip)?)?;
+    g.set("hide_tooltip", lua.create_function(hide_tooltip)?)?;
+    g.set("set_cursor", lua.create_function(set_cursor)?)?;
+    g.set("set_input_region", lua.create_function(set_input_region)?)?;
+    g.set(
+        "set_keyboard_interactivity",
+        lua.create_function(set_keyboard_interactivity)?,
+    )?;
+
+    Ok(())
+}
+
+pub fn push_event(lua: &Lua, event: Event) {
+    if let Some(mut ctx) = lua.app_data_mut::<Context>() {
+        ctx.events.push(event);
+    }
+}
+
+/// Calls the global function `name` if the config defines one.
+pub fn call_global(lua: &Lua, name: &str, args: impl IntoLuaMulti) -> mlua::Result<()> {
+    match lua.globals().get::<Option<mlua::Function>>(name)? {
+        Some(f) => f.call(args),
+        None => Ok(()),
+    }
+}
+
+/// Returns a sender for running tasks on the main thread from a background service.
+pub fn task_sender(lua: &Lua) -> mlua::Result<channel::Sender<Task>> {
+    lua.app_data_ref::<Context>()
+        .map(|ctx| ctx.tasks.clone())
+        .ok_or_else(|| mlua::Error::runtime("lua api is not registered"))
+}
+
+/// Replaces lua's `print`, logging its arguments under the `lua` target rather than writing
+/// them to stdout.
+fn print(_: &Lua, values: mlua::Variadic<mlua::Value>) -> mlua::Result<()> {
+    let line = values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<mlua::Result<Vec<_>>>()?
+        .join("\t");
+    log::info!(target: "lua", "{line}");
+    Ok(())
+}
+
+fn set_interval(lua: &Lua, (ms, callback): (u64, Callback)) -> mlua::Result<()> {
+    if ms == 0 {
+        return Err(mlua::Error::runtime(
+            "set_interval: interval must be positive",
+        ));
+    }
+
+    push_event(
+        lua,
+        Event::AddInterval {
+            interval: Duration::from_millis(ms),
+            callback,
+        },
+    );
+    Ok(())
+}
+
+fn animate(lua: &Lua, (ms, callback): (u64, Callback)) -> mlua::Result<()> {
+    push_event(
+        lua,
+        Event::Animate {
+            duration: Duration::from_millis(ms),
+            callback,
+        },
+    );
+    Ok(())
+}
+
+fn outputs(lua: &Lua, _: ()) -> mlua::Result<Vec<Output>> {
+    Ok(lua
+        .app_data_ref::<Context>()
+        .map(|ctx| ctx.outputs.clone())
+        .unwrap_or_default())
+}
+
+fn namespace(lua: &Lua, bar: Option<usize>) -> mlua::Result<Option<String>> {
+    Ok(bar_info(lua, bar)?.map(|bar| bar.namespace))
+}
+
+fn transform(lua: &Lua, bar: Option<usize>) -> mlua::Result<Option<&'static str>> {
+    Ok(bar_info(lua, bar)?.map(|bar| bar.transform))
+}
+
+fn pointer_position(lua: &Lua, _: ()) -> mlua::Result<(f64, f64, bool, usize)> {
+    let pointer = lua
+        .app_data_ref::<Context>()
+        .map(|ctx| ctx.pointer)
+        .unwrap_or_default();
+    Ok((pointer.x, pointer.y, pointer.inside, pointer.bar + 1))
+}
+
+/// Whether the pointer is over the bar being drawn, or the first bar outside of drawing, and
+/// inside the given rect.
+fn hover(lua: &Lua, (x, y, width, height): (f64, f64, f64, f64)) -> mlua::Result<bool> {
+    let Some(mut ctx) = lua.app_data_mut::<Context>() else {
+        return Ok(false);
+    };
+    ctx.hover_used = true;
+
+    let pointer = ctx.pointer;
+    Ok(pointer.inside
+        && pointer.bar == ctx.drawing.unwrap_or(0)
+        && (x..x + width).contains(&pointer.x)
+        && (y..y + height).contains(&pointer.y))
+}
+
+fn has_keyboard_focus(lua: &Lua, _: ()) -> mlua::Result<bool> {
+    Ok(lua
+        .app_data_ref::<Context>()
+        .is_some_and(|ctx| ctx.keyboard_focus))
+}
+
+fn request_redraw(lua: &Lua, _: ()) -> mlua::Result<()> {
+    push_event(lua, Event::Redraw);
+    Ok(())
+}
+
+/// Seconds since the bar started, from a monotonic clock.
+fn uptime(lua: &Lua, _: ()) -> mlua::Result<f64> {
+    Ok(lua
+        .app_data_ref::<Context>()
+        .map_or(0.0, |ctx| ctx.started.elapsed().as_secs_f64()))
+}
+
+/// Draw times of recent frames, or nil before the first.
+fn perf_stats(lua: &Lua, _: ()) -> mlua::Result<Option<mlua::Table>> {
+    let Some(perf) = lua.app_data_ref::<Context>().and_then(|ctx| ctx.perf) else {
+        return Ok(None);
+    };
+
+    let t = lua.create_table()?;
+    t.set("min", perf.min)?;
+    t.set("avg", perf.avg)?;
+    t.set("max", perf.max)?;
+    t.set("p99", perf.p99)?;
+    Ok(Some(t))
+}
+
+/// Exits once the current callback returns, after the config's `on_exit`.
+fn quit(lua: &Lua, _: ()) -> mlua::Result<()> {
+    push_event(lua, Event::Exit);
+    Ok(())
+}
+
+fn set_margin(
+    lua: &Lua,
+    (top, right, bottom, left, bar): (i32, i32, i32, i32, Option<usize>),
+) -> mlua::Result<()> {
+    push_event(
+        lua,
+        Event::SetMargin {
+            bar: bar_index(bar)?,
+            margin: Margin::from((top, right, bottom, left)),
+        },
+    );
+    Ok(())
+}
+
+fn slide_offset(lua: &Lua, (offset, bar): (i32, Option<usize>)) -> mlua::Result<()> {
+    push_event(
+        lua,
+        Event::SlideOffset {
+            bar: bar_index(bar)?,
+            offset,
+        },
+    );
+    Ok(())
+}
+
+fn show_tooltip(
+    lua: &Lua,
+    (x, y, width, height, bar): (i32, i32, i32, i32, Option<usize>),
+) -> mlua::Result<()> {
+    if width <= 0 || height <= 0 {
+        return Err(mlua::Error::runtime(
+            "show_tooltip: width and height must be positive",
+        ));
+    }
+    if width > MAX_SIZE as i32 || height > MAX_SIZE as i32 {
+        return Err(mlua::Error::runtime(format!(
+            "show_tooltip: width and height must be at most {MAX_SIZE}"
+        )));
+    }
+
+    push_event(
+        lua,
+        Event::ShowTooltip {
+            bar: bar_index(bar)?,
+            x,
+            y,
+            width,
+            height,
+        },
+    );
+    Ok(())
+}
+
+fn hide_tooltip(lua: &Lua, _: ()) -> mlua::Result<()> {
+    push_event(lua, Event::HideTooltip);
+    Ok(())
+}
+
+fn set_cursor(lua: &Lua, shape: CursorShape) -> mlua::Result<()> {
+    push_event(lua, Event::SetCursor(shape));
+    Ok(())
+}
+
+fn set_input_region(
+    lua: &Lua,
+    (x, y, width, height, bar): (
+        Option<i32>,
+        Option<i32>,
+        Option<i32>,
+        Option<i32>,
+        Option<usize>,
+    ),
+) -> mlua::Result<()> {
+    let region = match (x, y, width, height) {
+        (Some(x), Some(y), Some(width), Some(height)) => Some((x, y, width, height)),
+        (None, None, None, None) => None,
+        _ => {
+            return Err(mlua::Error::runtime(
+                "set_input_region: expected x, y, width and height, or nil for each",
+            ));
+        }
+    };
+
+    push_event(
+        lua,
+        Event::SetInputRegion {
+            bar: bar_index(bar)?,
+            region,
+        },
+    );
+    Ok(())
+}
+
+fn set_visible(lua: &Lua, (visible, bar): (bool, Option<usize>)) -> mlua::Result<()> {
+    push_event(
+        lua,
+        Event::SetVisible {
+            bar: bar_index(bar)?,
+            visible,
+        },
+    );
+    Ok(())
+}
+
+fn set_keyboard_interactivity(
+    lua: &Lua,
+    (interactivity, bar): (KeyboardInteractivity, Option<usize>),
+) -> mlua::Result<()> {
+    push_event(
+        lua,
+        Event::SetKeyboardInteractivity {
+            bar: bar_index(bar)?,
+            interactivity,
+        },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lua() -> Lua {
+        let lua = Lua::new();
+        register(&lua).unwrap();
+        lua
+    }
+
+    /// Takes the events the config has queued so far.
+    fn events(lua: &Lua) -> Vec<Event> {
+        std::mem::take(&mut lua.app_data_mut::<Context>().unwrap().events)
+    }
+
+    #[test]
+    fn set_input_region_takes_a_rect_or_nothing() {
+        let lua = lua();
+        lua.load("set_input_region(1, 2, 3, 4, 2) set_input_region()")
+            .exec()
+            .unwrap();
+        assert!(matches!(
+            events(&lua).as_slice(),
+            [
+                Event::SetInputRegion {
+                    bar: 1,
+                    region: Some((1, 2, 3, 4)),
+                },
+                Event::SetInputRegion {
+                    bar: 0,
+                    region: None,
+                },
+            ]
+        ));
+
+        assert!(lua.load("set_input_region(1, 2)").exec().is_err());
+        assert!(events(&lua).is_empty());
+    }
+
+    #[test]
+    fn summarises_frame_times() {
+        let stats = PerfStats::new((1..=100).rev().map(Duration::from_millis)).unwrap();
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.avg, 50.5);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(stats.p99, 99.0);
+
+        assert!(PerfStats::new([]).is_none());
+    }
+
+    #[test]
+    fn uptime_increases() {
+        let lua = lua();
+        let uptime = || lua.load("return uptime()").eval::<f64>().unwrap();
+        let first = uptime();
+        std::thread::sleep(Duration::from_millis(10));
+        let second = uptime();
+        assert!(second - first >= 0.01, "{first} then {second}");
+    }
+}
@@ -0,0 +1,253 @@
+//! The active MPRIS media player, for now playing widgets. Players are found on the session bus
+//! and followed through their property change signals.
+
+use std::{collections::HashMap, sync::mpsc, thread};
+
+use calloop::channel;
+use mlua::{IntoLua, Lua, Table};
+use zbus::{
+    MatchRule,
+    blocking::{Connection, Proxy, fdo::DBusProxy},
+    message,
+    zvariant::{Array, OwnedValue},
+};
+
+use super::{Task, call_global, push_event, tray::Forwarders};
+use crate::window::Event;
+
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Player {
+    /// The player's bus name without the MPRIS prefix, e.g. "firefox".
+    name: String,
+    title: String,
+    /// The track's artists, joined with commas.
+    artist: String,
+    album: String,
+    status: String,
+}
+
+/// Messages handled by the mpris thread.
+enum Message {
+    /// A player appeared, went away or changed its properties.
+    Changed,
+    /// Calls a method of the active player's Player interface.
+    Call(&'static str),
+    Stop,
+}
+
+/// Mpris state kept as lua app data, created by the first call into the mpris table.
+struct Mpris {
+    player: Option<Player>,
+    messages: mpsc::Sender<Message>,
+}
+
+impl Drop for Mpris {
+    // The lua state is dropped on reload, the new config starts its own thread.
+    fn drop(&mut self) {
+        let _ = self.messages.send(Message::Stop);
+    }
+}
+
+impl IntoLua for Player {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        let t = lua.create_table()?;
+        t.set("name", self.name)?;
+        t.set("title", self.title)?;
+        t.set("artist", self.artist)?;
+        t.set("album", self.album)?;
+        t.set("status", self.status)?;
+        Ok(mlua::Value::Table(t))
+    }
+}
+
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("player", lua.create_function(player)?)?;
+    t.set(
+        "play_pause",
+        lua.create_function(|lua, ()| call(lua, "PlayPause"))?,
+    )?;
+    t.set("next", lua.create_function(|lua, ()| call(lua, "Next"))?)?;
+    t.set(
+        "previous",
+        lua.create_function(|lua, ()| call(lua, "Previous"))?,
+    )?;
+    Ok(t)
+}
+
+fn player(lua: &Lua, _: ()) -> mlua::Result<Option<Player>> {
+    start(lua)?;
+    Ok(lua
+        .app_data_ref::<Mpris>()
+        .and_then(|mpris| mpris.player.clone()))
+}
+
+fn call(lua: &Lua, method: &'static str) -> mlua::Result<()> {
+    start(lua)?;
+    if let Some(mpris) = lua.app_data_ref::<Mpris>() {
+        let _ = mpris.messages.send(Message::Call(method));
+    }
+    Ok(())
+}
+
+/// Starts the mpris thread, unless it is already running for this lua state.
+fn start(lua: &Lua) -> mlua::Result<()> {
+    if lua.app_data_ref::<Mpris>().is_some() {
+        return Ok(());
+    }
+
+    let tasks = super::task_sender(lua)?;
+    let (sender, receiver) = mpsc::channel();
+    let messages = sender.clone();
+    thread::spawn(move || {
+        if let Err(e) = run(messages, receiver, tasks) {
+            log::error!("mpris failed: {e}");
+        }
+    });
+
+    lua.set_app_data(Mpris {
+        player: None,
+        messages: sender,
+    });
+    Ok(())
+}
+
+fn run(
+    messages: mpsc::Sender<Message>,
+    receiver: mpsc::Receiver<Message>,
+    tasks: channel::Sender<Task>,
+) -> zbus::Result<()> {
+    let conn = Connection::session()?;
+    let mut forwarders = Forwarders::new(&conn);
+
+    let rule = MatchRule::builder()
+        .msg_type(message::Type::Signal)
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .path(PLAYER_PATH)?
+        .build();
+    forwarders.forward(rule, &messages, |_| Some(Message::Changed))?;
+
+    let rule = MatchRule::builder()
+        .msg_type(message::Type::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameOwnerChanged")?
+        .build();
+    forwarders.forward(rule, &messages, |msg| {
+        let (name, _, _) = msg.body().deserialize::<(String, String, String)>().ok()?;
+        name.starts_with(PLAYER_PREFIX).then_some(Message::Changed)
+    })?;
+    drop(messages);
+
+    let mut active = fetch_active(&conn);
+    if send_player(&tasks, active.clone()).is_err() {
+        return Ok(());
+    }
+
+    for message in receiver {
+        match message {
+            Message::Changed => {
+                let player = fetch_active(&conn);
+                if player == active {
+                    continue;
+                }
+                active = player;
+                if send_player(&tasks, active.clone()).is_err() {
+                    break;
+                }
+            }
+            Message::Call(method) => {
+                let Some(player) = &active else {
+                    continue;
+                };
+                let bus = format!("{PLAYER_PREFIX}{}", player.name);
+                let result = Proxy::new(&conn, bus, PLAYER_PATH, PLAYER_INTERFACE)
+                    .and_then(|p| p.call_noreply(method, &()));
+                if let Err(e) = result {
+                    log::error!("mpris {method} on {} failed: {e}", player.name);
+                }
+            }
+            Message::Stop => break,
+        }
+    }
+    Ok(())
+}
+
+/// Hands the active player to lua, failing once the lua state has been replaced.
+fn send_player(
+    tasks: &channel::Sender<Task>,
+    player: Option<Player>,
+) -> Result<(), mpsc::SendError<Task>> {
+    tasks.send(Box::new(move |lua: &Lua| {
+        if let Some(mut mpris) = lua.app_data_mut::<Mpris>() {
+            mpris.player = player.clone();
+        }
+
+        if let Err(e) = call_global(lua, "on_mpris_changed", player) {
+            log::error!("on_mpris_changed failed: {e}");
+        }
+        push_event(lua, Event::Redraw);
+    }))
+}
+
+/// The player to show: the first one playing, otherwise the first paused, otherwise any.
+fn fetch_active(conn: &Connection) -> Option<Player> {
+    let names = match DBusProxy::new(conn).and_then(|p| Ok(p.list_names()?)) {
+        Ok(names) => names,
+        Err(e) => {
+            log::error!("mpris: failed to list bus names: {e}");
+            return None;
+        }
+    };
+
+    let rank = |player: &Player| match player.status.as_str() {
+        "Playing" => 0,
+        "Paused" => 1,
+        _ => 2,
+    };
+    names
+        .iter()
+        .filter_map(|name| name.as_str().strip_prefix(PLAYER_PREFIX))
+        .filter_map(|name| fetch(conn, name).ok())
+        .min_by_key(rank)
+}
+
+fn fetch(conn: &Connection, name: &str) -> zbus::Result<Player> {
+    let bus = format!("{PLAYER_PREFIX}{name}");
+    let proxy = Proxy::new(conn, bus, PLAYER_PATH, PLAYER_INTERFACE)?;
+
+    let metadata = proxy
+        .get_property::<HashMap<String, OwnedValue>>("Metadata")
+        .unwrap_or_default();
+    let text = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(|v| v.downcast_ref::<&str>().ok())
+            .map(str::to_string)
+            .unwrap_or_default()
+    };
+    let artist = metadata
+        .get("xesam:artist")
+        .and_then(|v| v.downcast_ref::<&Array>().ok())
+        .map(|artists| {
+            artists
+                .iter()
+                .filter_map(|a| a.downcast_ref::<&str>().ok())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+
+    Ok(Player {
+        name: name.to_string(),
+        title: text("xesam:title"),
+        artist,
+        album: text("xesam:album"),
+        status: proxy.get_property::<String>("PlaybackStatus")?,
+    })
+}
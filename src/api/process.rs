@@ -0,0 +1,231 @@
+use std::{
+    env,
+    io::{self, Read},
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use mlua::{IntoLuaMulti, Lua, RegistryKey};
+
+use crate::{
+    api::{Callback, push_event},
+    window::Event,
+};
+
+/// Output beyond this many bytes is discarded.
+const MAX_OUTPUT: u64 = 64 * 1024;
+
+/// Runs `cmd` with `sh -c`, returning its stdout (truncated to `MAX_OUTPUT` bytes) and exit
+/// code. The exit code is -1 if the command was killed by a signal.
+pub fn run(cmd: &str) -> io::Result<(String, i32)> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdout = Vec::new();
+    if let Some(out) = child.stdout.take() {
+        out.take(MAX_OUTPUT).read_to_end(&mut stdout)?;
+    }
+    // Dropping the pipe lets a command with more output than we read exit.
+    let status = child.wait()?;
+
+    Ok((
+        String::from_utf8_lossy(&stdout).into_owned(),
+        status.code().unwrap_or(-1),
+    ))
+}
+
+/// Runs `cmd` to completion. This blocks the bar, prefer `exec_async` for slow commands.
+pub fn exec(_: &Lua, cmd: String) -> mlua::Result<(String, i32)> {
+    run(&cmd).map_err(|e| mlua::Error::runtime(format!("exec: failed to run `{cmd}`: {e}")))
+}
+
+/// Reads the environment variable `name`, nil when it is unset or not valid unicode.
+pub fn getenv(_: &Lua, name: String) -> mlua::Result<Option<String>> {
+    Ok(env::var(name).ok())
+}
+
+/// Runs `cmd` on a separate thread and calls `callback` with its stdout and exit code on the
+/// next event loop iteration after it finishes.
+pub fn exec_async(lua: &Lua, (cmd, callback): (String, Callback)) -> mlua::Result<()> {
+    let callback = SentCallback::new(lua, callback)?;
+    spawn(lua, cmd, move |lua, output| callback.call(lua, output))
+}
+
+/// Runs `cmd` on a separate thread, then `done` with its output on the main thread. It goes
+/// through the task channel of the lua state that started it, so a command still running when
+/// the config is reloaded is never reported to the new config.
+fn spawn(
+    lua: &Lua,
+    cmd: String,
+    done: impl FnOnce(&Lua, (String, i32)) -> mlua::Result<()> + Send + 'static,
+) -> mlua::Result<()> {
+    let tasks = super::task_sender(lua)?;
+    thread::spawn(move || {
+        let output = run(&cmd);
+        let _ = tasks.send(Box::new(move |lua: &Lua| {
+            let result = output
+                .map_err(|e| mlua::Error::runtime(format!("failed to run `{cmd}`: {e}")))
+                .and_then(|output| done(lua, output));
+            if let Err(e) = result {
+                log::error!("callback for `{cmd}` failed: {e}");
+            }
+        }));
+    });
+    Ok(())
+}
+
+/// A `Callback` that can be handed to another thread, keeping a function in the registry of
+/// the lua state that passed it.
+#[derive(Debug, Clone)]
+enum SentCallback {
+    Global(String),
+    Registry(Arc<RegistryKey>),
+}
+
+impl SentCallback {
+    fn new(lua: &Lua, callback: Callback) -> mlua::Result<Self> {
+        Ok(match callback {
+            Callback::Global(name) => SentCallback::Global(name),
+            Callback::Function(f) => {
+                SentCallback::Registry(Arc::new(lua.create_registry_value(f)?))
+            }
+        })
+    }
+
+    fn call(&self, lua: &Lua, args: impl IntoLuaMulti) -> mlua::Result<()> {
+        match self {
+            SentCallback::Global(name) => Callback::Global(name.clone()).call(lua, args),
+            SentCallback::Registry(key) => lua.registry_value::<mlua::Function>(key)?.call(args),
+        }
+    }
+}
+
+/// Runs `cmd` in the background now and then every `ms` milliseconds, calling `callback` with
+/// its stdout and exit code and redrawing after each run.
+pub fn poll(lua: &Lua, (ms, cmd, callback): (u64, String, Callback)) -> mlua::Result<()> {
+    if ms == 0 {
+        return Err(mlua::Error::runtime("poll: interval must be positive"));
+    }
+
+    push_event(
+        lua,
+        Event::Poll {
+            interval: Duration::from_millis(ms),
+            poller: Poller {
+                cmd,
+                callback: SentCallback::new(lua, callback)?,
+            },
+        },
+    );
+    Ok(())
+}
+
+/// A command started by `poll`, run by a timer the window removes on reload. It holds the
+/// callback in the registry rather than in a lua function, so nothing lua owns keeps it alive.
+#[derive(Debug, Clone)]
+pub struct Poller {
+    cmd: String,
+    callback: SentCallback,
+}
+
+impl Poller {
+    /// Runs the command in the background, then calls back and redraws.
+    pub fn run(&self, lua: &Lua) {
+        let callback = self.callback.clone();
+        let result = spawn(lua, self.cmd.clone(), move |lua, output| {
+            callback.call(lua, output)?;
+            push_event(lua, Event::Redraw);
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::error!("poll: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{self, Context};
+
+    /// A lua state with the api registered, and the task channel the window would run.
+    fn lua() -> (Lua, calloop::channel::Channel<api::Task>) {
+        let lua = Lua::new();
+        api::register(&lua).unwrap();
+        let tasks = lua
+            .app_data_mut::<Context>()
+            .and_then(|mut ctx| ctx.task_channel.take())
+            .unwrap();
+        (lua, tasks)
+    }
+
+    #[test]
+    fn runs_commands() {
+        assert_eq!(run("echo hello").unwrap(), ("hello\n".to_string(), 0));
+        assert_eq!(run("exit 3").unwrap(), (String::new(), 3));
+    }
+
+    #[test]
+    fn exec_async_calls_back_through_the_task_channel() {
+        let (lua, tasks) = lua();
+        lua.load(
+            r#"
+            exec_async("echo hi", function(stdout, code) result = stdout .. code end)
+            function named(stdout) named_result = stdout end
+            exec_async("printf there", "named")
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(lua.globals().get::<Option<String>>("result").unwrap(), None);
+
+        tasks.recv().unwrap()(&lua);
+        tasks.recv().unwrap()(&lua);
+        assert_eq!(lua.globals().get::<String>("result").unwrap(), "hi\n0");
+        assert_eq!(
+            lua.globals().get::<String>("named_result").unwrap(),
+            "there"
+        );
+    }
+
+    #[test]
+    fn poll_queues_a_poller_that_redraws() {
+        let (lua, tasks) = lua();
+        assert!(lua.load(r#"poll(0, "true", print)"#).exec().is_err());
+        lua.load(r#"poll(1000, "echo hi", function(stdout) result = stdout end)"#)
+            .exec()
+            .unwrap();
+
+        let events = std::mem::take(&mut lua.app_data_mut::<Context>().unwrap().events);
+        let [Event::Poll { interval, poller }] = events.as_slice() else {
+            panic!("expected a poll event, got {events:?}");
+        };
+        assert_eq!(*interval, Duration::from_secs(1));
+
+        poller.run(&lua);
+        poller.run(&lua);
+        tasks.recv().unwrap()(&lua);
+        tasks.recv().unwrap()(&lua);
+        assert_eq!(lua.globals().get::<String>("result").unwrap(), "hi\n");
+        let ctx = lua.app_data_ref::<Context>().unwrap();
+        assert!(matches!(
+            ctx.events.as_slice(),
+            [Event::Redraw, Event::Redraw]
+        ));
+    }
+
+    #[test]
+    fn callbacks_only_run_in_their_own_lua() {
+        let (lua, _tasks) = lua();
+        let f: mlua::Function = lua.load("return function() end").eval().unwrap();
+        let callback = SentCallback::new(&lua, Callback::Function(f)).unwrap();
+        assert!(callback.call(&lua, ()).is_ok());
+        assert!(callback.call(&Lua::new(), ()).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! CPU and memory usage read from /proc.
+
+use std::fs;
+
+use mlua::{Lua, Table};
+
+const PROC_STAT: &str = "/proc/stat";
+const PROC_MEMINFO: &str = "/proc/meminfo";
+
+/// Jiffies spent by all CPUs since boot, from the aggregate line of /proc/stat.
+#[derive(Debug, Clone, Copy)]
+struct CpuTimes {
+    /// Idle and waiting on io.
+    idle: u64,
+    total: u64,
+}
+
+/// The sample taken by the previous `sys.cpu_percent()`, kept as lua app data.
+struct LastCpuTimes(CpuTimes);
+
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("cpu_percent", lua.create_function(cpu_percent)?)?;
+    t.set(
+        "mem",
+        lua.create_function(|_, ()| {
+            Ok(fs::read_to_string(PROC_MEMINFO)
+                .ok()
+                .and_then(|s| parse_meminfo(&s))
+                .map_or((None, None), |(used, total)| (Some(used), Some(total))))
+        })?,
+    )?;
+    Ok(t)
+}
+
+/// CPU usage since the previous call, or since boot on the first.
+fn cpu_percent(lua: &Lua, _: ()) -> mlua::Result<Option<f64>> {
+    let Some(now) = fs::read_to_string(PROC_STAT)
+        .ok()
+        .and_then(|s| parse_stat(&s))
+    else {
+        return Ok(None);
+    };
+
+    let last = lua
+        .app_data_ref::<LastCpuTimes>()
+        .map(|last| last.0)
+        .unwrap_or(CpuTimes { idle: 0, total: 0 });
+    lua.set_app_data(LastCpuTimes(now));
+    Ok(usage(last, now))
+}
+
+/// The percentage of time not idle between two samples, `None` if no time passed.
+fn usage(last: CpuTimes, now: CpuTimes) -> Option<f64> {
+    let total = now.total.saturating_sub(last.total);
+    let idle = now.idle.saturating_sub(last.idle);
+    (total > 0).then(|| 100.0 * (total - idle.min(total)) as f64 / total as f64)
+}
+
+fn parse_stat(s: &str) -> Option<CpuTimes> {
+    let line = s.lines().find(|l| l.starts_with("cpu "))?;
+    // user nice system idle iowait irq softirq steal, guest time is already part of user.
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|t| t.parse().ok())
+        .collect::<Option<_>>()?;
+    if times.len() < 4 {
+        return None;
+    }
+
+    Some(CpuTimes {
+        idle: times[3] + times.get(4).unwrap_or(&0),
+        total: times.iter().sum(),
+    })
+}
+
+/// Used and total memory in bytes. Used excludes what the kernel can reclaim, like caches.
+fn parse_meminfo(s: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        s.lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+            .map(|kib| kib * 1024)
+    };
+
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable")?;
+    Some((total.saturating_sub(available), total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "\
+cpu  100 20 30 400 50 6 7 8 90 0
+cpu0 50 10 15 200 25 3 3 4 45 0
+intr 12345
+";
+
+    const MEMINFO: &str = "\
+MemTotal:       16000000 kB
+MemFree:         2000000 kB
+MemAvailable:    6000000 kB
+Cached:          3000000 kB
+";
+
+    #[test]
+    fn parses_aggregate_cpu_line() {
+        let times = parse_stat(STAT).unwrap();
+        assert_eq!(times.idle, 450);
+        assert_eq!(times.total, 621);
+
+        assert!(parse_stat("cpu0 1 2 3 4\n").is_none());
+        assert!(parse_stat("cpu  1 2 3\n").is_none());
+        assert!(parse_stat("cpu  1 2 x 4\n").is_none());
+    }
+
+    #[test]
+    fn usage_between_samples() {
+        let last = CpuTimes {
+            idle: 100,
+            total: 200,
+        };
+        let now = CpuTimes {
+            idle: 175,
+            total: 300,
+        };
+        assert_eq!(usage(last, now), Some(25.0));
+        assert_eq!(usage(now, now), None);
+    }
+
+    #[test]
+    fn parses_meminfo_in_bytes() {
+        assert_eq!(
+            parse_meminfo(MEMINFO),
+            Some((10_000_000 * 1024, 16_000_000 * 1024))
+        );
+        assert_eq!(parse_meminfo("MemTotal: 100 kB\n"), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Named colours and sizes shared across the config, defined by its `palette` global and read
+//! with `theme`.
+
+use std::collections::HashMap;
+
+use mlua::{IntoLua, Lua, Table};
+
+use super::colour;
+
+/// The global the config defines its theme in.
+const GLOBAL: &str = "palette";
+
+/// An entry of the theme. Strings in `palette` are colours, numbers are sizes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    /// Packed `0xAARRGGBB`.
+    Colour(u32),
+    Size(f64),
+}
+
+impl IntoLua for Entry {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        match self {
+            Entry::Colour(colour) => colour.into_lua(lua),
+            Entry::Size(size) => size.into_lua(lua),
+        }
+    }
+}
+
+/// The theme's entries, kept as lua app data.
+#[derive(Default)]
+struct Theme(HashMap<String, Entry>);
+
+/// Reads the config's `palette` table into the theme, once the config has run and before
+/// `opts()` is called so it can name entries. A config without one has an empty theme.
+pub fn load(lua: &Lua) -> mlua::Result<()> {
+    let Some(t) = lua.globals().get::<Option<Table>>(GLOBAL)? else {
+        lua.set_app_data(Theme::default());
+        return Ok(());
+    };
+
+    let mut entries = HashMap::new();
+    for pair in t.pairs::<String, mlua::Value>() {
+        let (name, value) = pair?;
+        let entry = match value {
+            mlua::Value::Integer(size) => Entry::Size(size as f64),
+            mlua::Value::Number(size) => Entry::Size(size),
+            mlua::Value::String(s) => {
+                let s = s.to_str()?;
+                colour::parse(&s).map(Entry::Colour).ok_or_else(|| {
+                    mlua::Error::runtime(format!(
+                        r##"{GLOBAL}.{name}: "{s}" is not a colour, expected "#RRGGBB", "#AARRGGBB" or a named colour"##
+                    ))
+                })?
+            }
+            other => {
+                return Err(mlua::Error::runtime(format!(
+                    "{GLOBAL}.{name}: a {} is neither a colour nor a size",
+                    other.type_name()
+                )));
+            }
+        };
+        entries.insert(name, entry);
+    }
+
+    lua.set_app_data(Theme(entries));
+    Ok(())
+}
+
+/// The theme's entry `name`, or `default` when there is none.
+pub fn theme(
+    lua: &Lua,
+    (name, default): (String, Option<mlua::Value>),
+) -> mlua::Result<mlua::Value> {
+    match get(lua, &name) {
+        Some(entry) => entry.into_lua(lua),
+        None => default
+            .ok_or_else(|| mlua::Error::runtime(format!(r#"theme: no entry named "{name}""#))),
+    }
+}
+
+/// The theme's entry `name`, if the config defines one.
+pub fn get(lua: &Lua, name: &str) -> Option<Entry> {
+    lua.app_data_ref::<Theme>()
+        .and_then(|theme| theme.0.get(name).copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::colour::Colour,
+        config::{self, ConfigError},
+    };
+
+    const CONFIG: &str = r##"
+        palette = { accent = "#336699", warning = "red", bar_height = 24 }
+        function opts()
+            return { height = theme("bar_height"), background_colour = "accent" }
+        end
+    "##;
+
+    #[test]
+    fn resolves_entries_through_the_bindings() {
+        let (bars, lua) = config::load_from(CONFIG.as_bytes(), "test").unwrap();
+        assert_eq!(bars[0].height, 24);
+        assert_eq!(bars[0].background_colour, Some(0xFF336699));
+
+        assert_eq!(get(&lua, "warning"), Some(Entry::Colour(0xFFFF0000)));
+        let accent: u32 = lua.load(r#"return theme("accent")"#).eval().unwrap();
+        assert_eq!(accent, 0xFF336699);
+        let missing: u32 = lua.load(r#"return theme("missing", 7)"#).eval().unwrap();
+        assert_eq!(missing, 7);
+        assert!(lua.load(r#"return theme("missing")"#).exec().is_err());
+
+        let colour: Colour = lua.load(r#"return "accent""#).eval().unwrap();
+        assert_eq!(colour.0, 0xFF336699);
+    }
+
+    #[test]
+    fn sizes_are_not_colours() {
+        let (_, lua) = config::load_from(CONFIG.as_bytes(), "test").unwrap();
+        let error = lua
+            .load(r#"return "bar_height""#)
+            .eval::<Colour>()
+            .unwrap_err();
+        assert!(error.to_string().contains("is a size"), "{error}");
+    }
+
+    #[test]
+    fn checks_palette_at_load() {
+        for palette in [r##"{ accent = "#12345" }"##, "{ accent = true }"] {
+            let conf = format!("palette = {palette} function opts() return {{}} end");
+            match config::load_from(conf.as_bytes(), "test") {
+                Err(ConfigError::InvalidTheme(e)) => {
+                    assert!(e.to_string().contains("palette.accent"), "{e}")
+                }
+                Err(e) => panic!("expected an invalid theme, got {e}"),
+                Ok(_) => panic!("{palette} was accepted"),
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt::Write;
+
+use chrono::{Local, TimeZone};
+use mlua::{Lua, Table};
+
+/// The `time` table: reading the system clock in the local timezone.
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("now", lua.create_function(now)?)?;
+    t.set("format", lua.create_function(format)?)?;
+    Ok(t)
+}
+
+/// Seconds since the unix epoch.
+fn now(_: &Lua, _: ()) -> mlua::Result<i64> {
+    Ok(Local::now().timestamp())
+}
+
+/// Formats `timestamp`, or the current time, with strftime style `fmt`.
+fn format(_: &Lua, (fmt, timestamp): (String, Option<i64>)) -> mlua::Result<String> {
+    let time = match timestamp {
+        Some(ts) => Local
+            .timestamp_opt(ts, 0)
+            .single()
+            .ok_or_else(|| mlua::Error::runtime(format!("time.format: invalid timestamp {ts}")))?,
+        None => Local::now(),
+    };
+
+    let mut out = String::new();
+    write!(out, "{}", time.format(&fmt))
+        .map_err(|_| mlua::Error::runtime(format!("time.format: invalid format \"{fmt}\"")))?;
+    Ok(out)
+}
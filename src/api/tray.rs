@@ -0,0 +1,527 @@
+//! A StatusNotifierItem tray host. The watcher service is run on the session bus, or joined as
+//! a host when another program already provides it, and registered items are mirrored into lua.
+
+use std::{
+    sync::{Arc, Mutex, mpsc},
+    thread::{self, JoinHandle},
+};
+
+use calloop::channel;
+use mlua::{IntoLua, Lua, Table};
+use zbus::{
+    MatchRule,
+    blocking::{Connection, MessageIterator, Proxy, connection, fdo::DBusProxy},
+    message::{self, Header},
+    names::BusName,
+    object_server::SignalEmitter,
+    zvariant::OwnedObjectPath,
+};
+
+use super::{Task, call_global, push_event};
+use crate::window::Event;
+
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+/// Used when an item registers with only its bus name.
+const DEFAULT_ITEM_PATH: &str = "/StatusNotifierItem";
+
+#[derive(Debug, Clone)]
+struct TrayItem {
+    /// The bus name and object path the item registered with.
+    service: String,
+    /// The unique bus name currently owning the item.
+    owner: String,
+    id: String,
+    title: String,
+    status: String,
+    icon_name: String,
+    menu: String,
+    icon: Option<Pixmap>,
+}
+
+#[derive(Debug, Clone)]
+struct Pixmap {
+    width: i32,
+    height: i32,
+    /// ARGB32 pixels in network byte order.
+    data: Vec<u8>,
+}
+
+type Call = Box<dyn FnOnce(&Proxy) -> zbus::Result<()> + Send>;
+
+/// Messages handled by the tray thread.
+enum Message {
+    Registered(String),
+    Unregistered(String),
+    /// An item owned by this unique name emitted a signal, so its properties may have changed.
+    Changed(String),
+    /// A bus name lost its owner.
+    NameLost(String),
+    /// Calls a method on the item registered as `service`.
+    Call {
+        service: String,
+        call: Call,
+    },
+    Stop,
+}
+
+/// Tray state kept as lua app data, created by the first call into the tray.
+struct Tray {
+    items: Vec<TrayItem>,
+    messages: mpsc::Sender<Message>,
+}
+
+impl Drop for Tray {
+    // The lua state is dropped on reload, the new config starts its own tray.
+    fn drop(&mut self) {
+        let _ = self.messages.send(Message::Stop);
+    }
+}
+
+impl IntoLua for TrayItem {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        let t = lua.create_table()?;
+        t.set("service", self.service)?;
+        t.set("id", self.id)?;
+        t.set("title", self.title)?;
+        t.set("status", self.status)?;
+        t.set("icon_name", self.icon_name)?;
+        t.set("menu", self.menu)?;
+        if let Some(icon) = self.icon {
+            let i = lua.create_table()?;
+            i.set("width", icon.width)?;
+            i.set("height", icon.height)?;
+            i.set("data", lua.create_string(&icon.data)?)?;
+            t.set("icon", i)?;
+        }
+        Ok(mlua::Value::Table(t))
+    }
+}
+
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("items", lua.create_function(items)?)?;
+    t.set(
+        "activate",
+        lua.create_function(|lua, (service, x, y): (String, i32, i32)| {
+            call(lua, service, move |p| p.call_noreply("Activate", &(x, y)))
+        })?,
+    )?;
+    t.set(
+        "secondary_activate",
+        lua.create_function(|lua, (service, x, y): (String, i32, i32)| {
+            call(lua, service, move |p| {
+                p.call_noreply("SecondaryActivate", &(x, y))
+            })
+        })?,
+    )?;
+    t.set(
+        "context_menu",
+        lua.create_function(|lua, (service, x, y): (String, i32, i32)| {
+            call(lua, service, move |p| {
+                p.call_noreply("ContextMenu", &(x, y))
+            })
+        })?,
+    )?;
+    t.set(
+        "scroll",
+        lua.create_function(
+            |lua, (service, delta, orientation): (String, i32, String)| {
+                if orientation != "vertical" && orientation != "horizontal" {
+                    return Err(mlua::Error::runtime(
+                        "tray.scroll: orientation must be \"vertical\" or \"horizontal\"",
+                    ));
+                }
+                call(lua, service, move |p| {
+                    p.call_noreply("Scroll", &(delta, orientation.as_str()))
+                })
+            },
+        )?,
+    )?;
+    Ok(t)
+}
+
+fn items(lua: &Lua, _: ()) -> mlua::Result<Vec<TrayItem>> {
+    start(lua)?;
+    Ok(lua
+        .app_data_ref::<Tray>()
+        .map(|tray| tray.items.clone())
+        .unwrap_or_default())
+}
+
+fn call(
+    lua: &Lua,
+    service: String,
+    call: impl FnOnce(&Proxy) -> zbus::Result<()> + Send + 'static,
+) -> mlua::Result<()> {
+    start(lua)?;
+    if let Some(tray) = lua.app_data_ref::<Tray>() {
+        let _ = tray.messages.send(Message::Call {
+            service,
+            call: Box::new(call),
+        });
+    }
+    Ok(())
+}
+
+/// Starts the tray thread, unless it is already running for this lua state.
+fn start(lua: &Lua) -> mlua::Result<()> {
+    if lua.app_data_ref::<Tray>().is_some() {
+        return Ok(());
+    }
+
+    let tasks = super::task_sender(lua)?;
+    let (sender, receiver) = mpsc::channel();
+    let messages = sender.clone();
+    thread::spawn(move || {
+        if let Err(e) = run(messages, receiver, tasks) {
+            log::error!("tray failed: {e}");
+        }
+    });
+
+    lua.set_app_data(Tray {
+        items: Vec::new(),
+        messages: sender,
+    });
+    Ok(())
+}
+
+fn run(
+    messages: mpsc::Sender<Message>,
+    receiver: mpsc::Receiver<Message>,
+    tasks: channel::Sender<Task>,
+) -> zbus::Result<()> {
+    let registered = Arc::new(Mutex::new(Vec::new()));
+    let watcher = Watcher {
+        items: registered.clone(),
+        messages: messages.clone(),
+    };
+
+    let built = connection::Builder::session()?
+        .name(WATCHER_NAME)?
+        .serve_at(WATCHER_PATH, watcher)?
+        .build();
+    let (conn, is_watcher) = match built {
+        Ok(conn) => (conn, true),
+        // Another program already runs the watcher, so only act as a host to it.
+        Err(zbus::Error::NameTaken) => (Connection::session()?, false),
+        Err(e) => return Err(e),
+    };
+    let mut forwarders = Forwarders::new(&conn);
+    if !is_watcher {
+        join_watcher(&conn, &mut forwarders, &messages)?;
+    }
+
+    let rule = MatchRule::builder()
+        .msg_type(message::Type::Signal)
+        .interface(ITEM_INTERFACE)?
+        .build();
+    forwarders.forward(rule, &messages, |msg| {
+        Some(Message::Changed(msg.header().sender()?.to_string()))
+    })?;
+
+    let rule = MatchRule::builder()
+        .msg_type(message::Type::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameOwnerChanged")?
+        .build();
+    forwarders.forward(rule, &messages, |msg| {
+        let (name, _, new_owner) = msg.body().deserialize::<(String, String, String)>().ok()?;
+        new_owner.is_empty().then_some(Message::NameLost(name))
+    })?;
+    drop(messages);
+
+    let mut items: Vec<TrayItem> = Vec::new();
+    for message in receiver {
+        let changed = match message {
+            Message::Registered(service) => {
+                items.retain(|i| i.service != service);
+                match fetch(&conn, &service) {
+                    Ok(item) => items.push(item),
+                    Err(e) => log::error!("failed to read tray item {service}: {e}"),
+                }
+                true
+            }
+            Message::Unregistered(service) => {
+                let count = items.len();
+                items.retain(|i| i.service != service);
+                items.len() != count
+            }
+            Message::Changed(owner) => {
+                let mut changed = false;
+                for item in items.iter_mut().filter(|i| i.owner == owner) {
+                    if let Ok(new) = fetch(&conn, &item.service) {
+                        *item = new;
+                        changed = true;
+                    }
+                }
+                changed
+            }
+            Message::NameLost(name) => {
+                let (lost, kept) = items.drain(..).partition::<Vec<_>, _>(|i| {
+                    i.owner == name || split_service(&i.service).0 == name
+                });
+                items = kept;
+
+                if is_watcher {
+                    registered
+                        .lock()
+                        .unwrap()
+                        .retain(|s| !lost.iter().any(|i| &i.service == s));
+                    for item in &lost {
+                        let _ = conn.emit_signal(
+                            None::<BusName>,
+                            WATCHER_PATH,
+                            WATCHER_NAME,
+                            "StatusNotifierItemUnregistered",
+                            &(item.service.as_str(),),
+                        );
+                    }
+                }
+                !lost.is_empty()
+            }
+            Message::Call { service, call } => {
+                let (bus, path) = split_service(&service);
+                let result = Proxy::new(&conn, bus.to_string(), path.to_string(), ITEM_INTERFACE)
+                    .and_then(|p| call(&p));
+                if let Err(e) = result {
+                    log::error!("tray item {service} call failed: {e}");
+                }
+                false
+            }
+            Message::Stop => break,
+        };
+
+        if changed && send_items(&tasks, items.clone()).is_err() {
+            break;
+        }
+    }
+
+    if is_watcher {
+        let _ = conn.release_name(WATCHER_NAME);
+    }
+    Ok(())
+}
+
+/// Registers as a host with an existing watcher and queues the items it already knows about.
+fn join_watcher(
+    conn: &Connection,
+    forwarders: &mut Forwarders,
+    messages: &mpsc::Sender<Message>,
+) -> zbus::Result<()> {
+    let host = format!("org.kde.StatusNotifierHost-{}", std::process::id());
+    conn.request_name(host.as_str())?;
+
+    let rule = MatchRule::builder()
+        .msg_type(message::Type::Signal)
+        .interface(WATCHER_NAME)?
+        .build();
+    forwarders.forward(rule, messages, |msg| {
+        let service = msg.body().deserialize::<String>().ok()?;
+        match msg.header().member()?.as_str() {
+            "StatusNotifierItemRegistered" => Some(Message::Registered(service)),
+            "StatusNotifierItemUnregistered" => Some(Message::Unregistered(service)),
+            _ => None,
+        }
+    })?;
+
+    let watcher = Proxy::new(conn, WATCHER_NAME, WATCHER_PATH, WATCHER_NAME)?;
+    watcher.call_method("RegisterStatusNotifierHost", &(host.as_str(),))?;
+    for service in watcher.get_property::<Vec<String>>("RegisteredStatusNotifierItems")? {
+        let _ = messages.send(Message::Registered(service));
+    }
+    Ok(())
+}
+
+/// Threads forwarding signals from a connection to a service thread. Dropping it closes the
+/// connection, which ends the threads' iterators, and waits for them, so a service thread that
+/// returns leaves nothing blocked on the bus.
+pub(super) struct Forwarders {
+    conn: Connection,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Forwarders {
+    pub(super) fn new(conn: &Connection) -> Self {
+        Forwarders {
+            conn: conn.clone(),
+            threads: Vec::new(),
+        }
+    }
+
+    /// Forwards signals matching `rule` to `messages`, from a thread of their own.
+    pub(super) fn forward<M: Send + 'static>(
+        &mut self,
+        rule: MatchRule<'static>,
+        messages: &mpsc::Sender<M>,
+        f: impl Fn(&zbus::Message) -> Option<M> + Send + 'static,
+    ) -> zbus::Result<()> {
+        let iter = MessageIterator::for_match_rule(rule, &self.conn, None)?;
+        let messages = messages.clone();
+        self.threads.push(thread::spawn(move || {
+            for msg in iter.flatten() {
+                if let Some(m) = f(&msg)
+                    && messages.send(m).is_err()
+                {
+                    break;
+                }
+            }
+        }));
+        Ok(())
+    }
+}
+
+impl Drop for Forwarders {
+    fn drop(&mut self) {
+        if let Err(e) = self.conn.clone().close() {
+            log::debug!("failed to close the dbus connection: {e}");
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Hands a snapshot of the items to lua, failing once the lua state has been replaced.
+fn send_items(
+    tasks: &channel::Sender<Task>,
+    items: Vec<TrayItem>,
+) -> Result<(), mpsc::SendError<Task>> {
+    tasks.send(Box::new(move |lua: &Lua| {
+        if let Some(mut tray) = lua.app_data_mut::<Tray>() {
+            tray.items = items;
+        }
+
+        if let Err(e) = call_global(lua, "on_tray_changed", ()) {
+            log::error!("on_tray_changed failed: {e}");
+        }
+        push_event(lua, Event::Redraw);
+    }))
+}
+
+fn fetch(conn: &Connection, service: &str) -> zbus::Result<TrayItem> {
+    let (bus, path) = split_service(service);
+    let owner = match bus.starts_with(':') {
+        true => bus.to_string(),
+        false => DBusProxy::new(conn)?
+            .get_name_owner(BusName::try_from(bus)?)?
+            .to_string(),
+    };
+
+    let proxy = Proxy::new(conn, bus.to_string(), path.to_string(), ITEM_INTERFACE)?;
+    let text = |name| proxy.get_property::<String>(name).unwrap_or_default();
+
+    // Items may offer several sizes, keep the largest and let the canvas scale it.
+    let icon = proxy
+        .get_property::<Vec<(i32, i32, Vec<u8>)>>("IconPixmap")
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(w, h, data)| *w > 0 && *h > 0 && data.len() >= (*w * *h * 4) as usize)
+        .max_by_key(|(w, h, _)| w * h)
+        .map(|(width, height, data)| Pixmap {
+            width,
+            height,
+            data,
+        });
+
+    Ok(TrayItem {
+        service: service.to_string(),
+        owner,
+        id: text("Id"),
+        title: text("Title"),
+        status: text("Status"),
+        icon_name: text("IconName"),
+        menu: proxy
+            .get_property::<OwnedObjectPath>("Menu")
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        icon,
+    })
+}
+
+/// Splits a registered service into its bus name and object path.
+fn split_service(service: &str) -> (&str, &str) {
+    match service.find('/') {
+        Some(i) => service.split_at(i),
+        None => (service, DEFAULT_ITEM_PATH),
+    }
+}
+
+struct Watcher {
+    items: Arc<Mutex<Vec<String>>>,
+    messages: mpsc::Sender<Message>,
+}
+
+#[zbus::interface(name = "org.kde.StatusNotifierWatcher")]
+impl Watcher {
+    async fn register_status_notifier_item(
+        &self,
+        service: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        // Some items register with only their object path, leaving the bus name implied.
+        let service = match (service.starts_with('/'), header.sender()) {
+            (true, Some(sender)) => format!("{sender}{service}"),
+            (true, None) => {
+                return Err(zbus::fdo::Error::InvalidArgs(
+                    "cannot register a path without a sender".to_string(),
+                ));
+            }
+            (false, _) => service.to_string(),
+        };
+
+        {
+            let mut items = self.items.lock().unwrap();
+            if items.contains(&service) {
+                return Ok(());
+            }
+            items.push(service.clone());
+        }
+
+        let _ = self.messages.send(Message::Registered(service.clone()));
+        Self::status_notifier_item_registered(&emitter, &service).await?;
+        Ok(())
+    }
+
+    async fn register_status_notifier_host(
+        &self,
+        _service: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        Self::status_notifier_host_registered(&emitter).await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn registered_status_notifier_items(&self) -> Vec<String> {
+        self.items.lock().unwrap().clone()
+    }
+
+    #[zbus(property)]
+    fn is_status_notifier_host_registered(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn protocol_version(&self) -> i32 {
+        0
+    }
+
+    #[zbus(signal)]
+    async fn status_notifier_item_registered(
+        emitter: &SignalEmitter<'_>,
+        service: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_item_unregistered(
+        emitter: &SignalEmitter<'_>,
+        service: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_host_registered(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
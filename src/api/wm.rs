@@ -0,0 +1,261 @@
+//! Workspaces of sway, or i3, through its ipc socket at `$SWAYSOCK`. The list is followed by
+//! subscribing to workspace events rather than polling.
+
+use std::{
+    env,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    thread,
+};
+
+use calloop::channel;
+use mlua::{IntoLua, Lua, Table};
+
+use super::{Task, call_global, push_event};
+use crate::window::Event;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+/// Set in the type of events, as opposed to replies.
+const EVENT: u32 = 1 << 31;
+
+#[derive(Debug, Clone)]
+struct Workspace {
+    name: String,
+    /// The number the name starts with, -1 when it has none.
+    num: i64,
+    focused: bool,
+    /// Shown on its output, though possibly not focused.
+    visible: bool,
+    urgent: bool,
+    output: String,
+}
+
+/// Workspace state kept as lua app data, created by the first call into the wm table.
+struct Wm {
+    workspaces: Vec<Workspace>,
+    /// The subscribed connection, shut down to stop the watching thread.
+    events: UnixStream,
+}
+
+impl Drop for Wm {
+    // The lua state is dropped on reload, the new config subscribes again.
+    fn drop(&mut self) {
+        let _ = self.events.shutdown(Shutdown::Both);
+    }
+}
+
+impl IntoLua for Workspace {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        let t = lua.create_table()?;
+        t.set("name", self.name)?;
+        t.set("num", self.num)?;
+        t.set("focused", self.focused)?;
+        t.set("visible", self.visible)?;
+        t.set("urgent", self.urgent)?;
+        t.set("output", self.output)?;
+        Ok(mlua::Value::Table(t))
+    }
+}
+
+pub fn table(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("workspaces", lua.create_function(workspaces)?)?;
+    t.set("focused", lua.create_function(focused)?)?;
+    t.set("focus", lua.create_function(focus)?)?;
+    Ok(t)
+}
+
+fn workspaces(lua: &Lua, _: ()) -> mlua::Result<Vec<Workspace>> {
+    start(lua)?;
+    Ok(lua
+        .app_data_ref::<Wm>()
+        .map(|wm| wm.workspaces.clone())
+        .unwrap_or_default())
+}
+
+fn focused(lua: &Lua, _: ()) -> mlua::Result<Option<String>> {
+    start(lua)?;
+    Ok(lua.app_data_ref::<Wm>().and_then(|wm| {
+        wm.workspaces
+            .iter()
+            .find(|w| w.focused)
+            .map(|w| w.name.clone())
+    }))
+}
+
+/// Switches to the workspace `name`, on a thread of its own so the bar does not wait on the
+/// window manager.
+fn focus(_: &Lua, name: String) -> mlua::Result<()> {
+    let escaped = name.replace('\\', "\\\\").replace('"', "\\\"");
+    let command = format!("workspace \"{escaped}\"");
+    thread::spawn(move || {
+        let result = request(RUN_COMMAND, &command).and_then(|reply| {
+            let replies: serde_json::Value = serde_json::from_slice(&reply)?;
+            match replies[0]["error"].as_str() {
+                Some(e) => Err(io::Error::other(e.to_string())),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = result {
+            log::error!("wm.focus: failed to focus {name}: {e}");
+        }
+    });
+    Ok(())
+}
+
+/// Subscribes to workspace events, unless this lua state already has.
+fn start(lua: &Lua) -> mlua::Result<()> {
+    if lua.app_data_ref::<Wm>().is_some() {
+        return Ok(());
+    }
+
+    let tasks = super::task_sender(lua)?;
+    let error = |e: io::Error| mlua::Error::runtime(format!("wm: {e}"));
+    let mut events = connect().map_err(error)?;
+    send(&mut events, SUBSCRIBE, r#"["workspace"]"#).map_err(error)?;
+    let workspaces = query_workspaces().map_err(error)?;
+
+    let reader = events.try_clone().map_err(error)?;
+    thread::spawn(move || watch(reader, tasks));
+
+    lua.set_app_data(Wm { workspaces, events });
+    Ok(())
+}
+
+fn watch(mut stream: UnixStream, tasks: channel::Sender<Task>) {
+    // Ends with an error once `Wm` shuts the stream down, or the window manager exits.
+    while let Ok((kind, _)) = receive(&mut stream) {
+        // The reply to the subscription comes first.
+        if kind & EVENT == 0 {
+            continue;
+        }
+
+        // Events only describe what changed, so the whole list is fetched again.
+        let workspaces = match query_workspaces() {
+            Ok(workspaces) => workspaces,
+            Err(e) => {
+                log::error!("wm: failed to get workspaces: {e}");
+                continue;
+            }
+        };
+        let task: Task = Box::new(move |lua| {
+            if let Some(mut wm) = lua.app_data_mut::<Wm>() {
+                wm.workspaces = workspaces;
+            }
+            if let Err(e) = call_global(lua, "on_workspaces_changed", ()) {
+                log::error!("on_workspaces_changed failed: {e}");
+            }
+            push_event(lua, Event::Redraw);
+        });
+        if tasks.send(task).is_err() {
+            break;
+        }
+    }
+}
+
+fn query_workspaces() -> io::Result<Vec<Workspace>> {
+    let reply = request(GET_WORKSPACES, "")?;
+    let value: serde_json::Value = serde_json::from_slice(&reply)?;
+    Ok(parse_workspaces(&value))
+}
+
+fn parse_workspaces(value: &serde_json::Value) -> Vec<Workspace> {
+    let text = |v: &serde_json::Value| v.as_str().unwrap_or_default().to_string();
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|w| Workspace {
+            name: text(&w["name"]),
+            num: w["num"].as_i64().unwrap_or(-1),
+            focused: w["focused"].as_bool().unwrap_or(false),
+            visible: w["visible"].as_bool().unwrap_or(false),
+            urgent: w["urgent"].as_bool().unwrap_or(false),
+            output: text(&w["output"]),
+        })
+        .collect()
+}
+
+fn connect() -> io::Result<UnixStream> {
+    let path = env::var_os("SWAYSOCK")
+        .or_else(|| env::var_os("I3SOCK"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "SWAYSOCK is not set"))?;
+    UnixStream::connect(path)
+}
+
+/// Sends a message on a connection of its own and returns the reply's payload.
+fn request(kind: u32, payload: &str) -> io::Result<Vec<u8>> {
+    let mut stream = connect()?;
+    send(&mut stream, kind, payload)?;
+    Ok(receive(&mut stream)?.1)
+}
+
+fn send(stream: &mut UnixStream, kind: u32, payload: &str) -> io::Result<()> {
+    let mut message = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream.write_all(&message)
+}
+
+/// Reads a reply or event, returning its type and payload.
+fn receive(stream: &mut UnixStream) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an i3-ipc message",
+        ));
+    }
+
+    let len = u32::from_ne_bytes(header[6..10].try_into().expect("4 bytes"));
+    let kind = u32::from_ne_bytes(header[10..14].try_into().expect("4 bytes"));
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((kind, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_workspace_list() {
+        let value = serde_json::json!([
+            {
+                "name": "1: web",
+                "num": 1,
+                "focused": true,
+                "visible": true,
+                "urgent": false,
+                "output": "DP-1"
+            },
+            { "name": "chat", "urgent": true, "output": "HDMI-A-1" }
+        ]);
+
+        let workspaces = parse_workspaces(&value);
+        assert_eq!(workspaces.len(), 2);
+
+        let web = &workspaces[0];
+        assert_eq!((web.name.as_str(), web.num), ("1: web", 1));
+        assert!(web.focused && web.visible && !web.urgent);
+        assert_eq!(web.output, "DP-1");
+
+        let chat = &workspaces[1];
+        assert_eq!((chat.name.as_str(), chat.num), ("chat", -1));
+        assert!(!chat.focused && !chat.visible && chat.urgent);
+        assert_eq!(chat.output, "HDMI-A-1");
+    }
+
+    #[test]
+    fn ignores_replies_that_are_not_lists() {
+        assert!(parse_workspaces(&serde_json::json!({ "success": false })).is_empty());
+        assert!(parse_workspaces(&serde_json::Value::Null).is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    env,
+    ffi::OsString,
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use mlua::Lua;
+
+use crate::{api, window::Opts};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// None of the searched paths exist.
+    NotFound(Vec<PathBuf>),
+    Read(PathBuf, io::Error),
+    /// The config is not valid lua, the message includes the line.
+    Syntax(String),
+    /// The config raised an error while running.
+    Runtime(mlua::Error),
+    /// The config's `palette` has an entry that is neither a colour nor a size.
+    InvalidTheme(mlua::Error),
+    /// The config does not define an `opts` function.
+    MissingOpts,
+    /// `opts()` failed or returned something that is not a valid window description.
+    InvalidOpts(mlua::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NotFound(paths) => {
+                write!(f, "no config file found, searched:")?;
+                for path in paths {
+                    write!(f, "\n  {}", path.display())?;
+                }
+                Ok(())
+            }
+            ConfigError::Read(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+            ConfigError::Syntax(message) => write!(f, "syntax error in config: {message}"),
+            ConfigError::Runtime(e) => write!(f, "error while running config: {e}"),
+            ConfigError::InvalidTheme(e) => write!(f, "invalid theme: {e}"),
+            ConfigError::MissingOpts => write!(f, "config does not define an `opts` function"),
+            ConfigError::InvalidOpts(e) => write!(f, "invalid `opts`: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Where to look for the config, in order: the first cli argument, `$STATUS_BAR_CONFIG`,
+/// `$XDG_CONFIG_HOME/status-bar/config.lua` and `~/.config/status-bar/config.lua`.
+pub fn search_paths(arg: Option<String>) -> Vec<PathBuf> {
+    search_paths_with(arg, env::var_os)
+}
+
+/// `search_paths`, looking environment variables up with `var`.
+fn search_paths_with(arg: Option<String>, var: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    paths.extend(arg.map(PathBuf::from));
+    paths.extend(var("STATUS_BAR_CONFIG").map(PathBuf::from));
+
+    if let Some(xdg) = var("XDG_CONFIG_HOME") {
+        paths.push(PathBuf::from(xdg).join("status-bar/config.lua"));
+    }
+    if let Some(home) = var("HOME") {
+        paths.push(PathBuf::from(home).join(".config/status-bar/config.lua"));
+    }
+
+    paths
+}
+
+/// Returns the first of `paths` that exists.
+pub fn locate(paths: Vec<PathBuf>) -> Result<PathBuf, ConfigError> {
+    match paths.iter().find(|p| p.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(ConfigError::NotFound(paths)),
+    }
+}
+
+/// Creates a lua state with the api registered, runs the config at `path` in it and
+/// evaluates its `opts` function into the bars to create.
+pub fn load_config(path: &Path) -> Result<(Vec<Opts>, Lua), ConfigError> {
+    let conf = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    run(conf, format!("@{}", path.display()))
+}
+
+/// Like `load_config`, with the config read from `reader` instead, such as stdin for
+/// `--config -`. `name` stands in for the path in errors.
+pub fn load_from(mut reader: impl Read, name: &str) -> Result<(Vec<Opts>, Lua), ConfigError> {
+    let mut conf = String::new();
+    reader
+        .read_to_string(&mut conf)
+        .map_err(|e| ConfigError::Read(PathBuf::from(name), e))?;
+    run(conf, format!("={name}"))
+}
+
+/// Runs the config `conf` in a fresh lua state, naming the chunk `chunk_name` in lua's errors.
+fn run(conf: String, chunk_name: String) -> Result<(Vec<Opts>, Lua), ConfigError> {
+    let lua = Lua::new();
+    api::register(&lua).map_err(ConfigError::Runtime)?;
+
+    lua.load(conf)
+        .set_name(chunk_name)
+        .exec()
+        .map_err(|e| match e {
+            mlua::Error::SyntaxError { message, .. } => ConfigError::Syntax(message),
+            e => ConfigError::Runtime(e),
+        })?;
+    api::theme::load(&lua).map_err(ConfigError::InvalidTheme)?;
+
+    let opts = lua
+        .globals()
+        .get::<Option<mlua::Function>>("opts")
+        .map_err(ConfigError::InvalidOpts)?
+        .ok_or(ConfigError::MissingOpts)?
+        .call::<mlua::Value>(())
+        .and_then(|value| Opts::list(value, &lua))
+        .map_err(ConfigError::InvalidOpts)?;
+
+    Ok((opts, lua))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("status-bar-{name}-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn searches_argument_then_environment() {
+        let var = |name: &str| match name {
+            "STATUS_BAR_CONFIG" => Some(OsString::from("/env/bar.lua")),
+            "XDG_CONFIG_HOME" => Some(OsString::from("/xdg")),
+            "HOME" => Some(OsString::from("/home/user")),
+            _ => None,
+        };
+        assert_eq!(
+            search_paths_with(Some("arg.lua".to_string()), var),
+            [
+                PathBuf::from("arg.lua"),
+                PathBuf::from("/env/bar.lua"),
+                PathBuf::from("/xdg/status-bar/config.lua"),
+                PathBuf::from("/home/user/.config/status-bar/config.lua"),
+            ]
+        );
+
+        assert_eq!(
+            search_paths_with(None, |name| (name == "HOME")
+                .then(|| OsString::from("/root"))),
+            [PathBuf::from("/root/.config/status-bar/config.lua")]
+        );
+    }
+
+    #[test]
+    fn locates_first_existing_file() {
+        let dir = TempDir::new("locate");
+        let missing = dir.0.join("missing.lua");
+        let config = dir.0.join("config.lua");
+        fs::write(&config, "").unwrap();
+
+        let found = locate(vec![missing.clone(), dir.0.clone(), config.clone()]).unwrap();
+        assert_eq!(found, config);
+
+        match locate(vec![missing.clone()]) {
+            Err(ConfigError::NotFound(paths)) => assert_eq!(paths, [missing]),
+            other => panic!("expected NotFound, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn loads_opts_from_reader() {
+        let conf = "function opts() return { { width = 300, height = 20 }, { height = 30 } } end";
+        let (bars, _lua) = load_from(conf.as_bytes(), "test").unwrap();
+        assert_eq!(bars.len(), 2);
+        assert_eq!((bars[0].width, bars[0].height), (300, 20));
+        assert_eq!(bars[1].height, 30);
+    }
+
+    #[test]
+    fn reports_config_errors() {
+        assert!(matches!(
+            load_from("function opts(".as_bytes(), "test"),
+            Err(ConfigError::Syntax(_))
+        ));
+        assert!(matches!(
+            load_from(r#"error("oops")"#.as_bytes(), "test"),
+            Err(ConfigError::Runtime(_))
+        ));
+        assert!(matches!(
+            load_from("local x = 1".as_bytes(), "test"),
+            Err(ConfigError::MissingOpts)
+        ));
+        assert!(matches!(
+            load_from(
+                r#"function opts() return { layer = "sideways" } end"#.as_bytes(),
+                "test"
+            ),
+            Err(ConfigError::InvalidOpts(_))
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+};
+
+use calloop::channel;
+
+/// A command read from the socket, one per line.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Reload,
+    Quit,
+    Redraw,
+    /// Runs lua against the live config, replying with its results.
+    Eval(String),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s.split_once(' ').unwrap_or((s, ""));
+        match name {
+            "reload" => Ok(Command::Reload),
+            "quit" => Ok(Command::Quit),
+            "redraw" => Ok(Command::Redraw),
+            "eval" => Ok(Command::Eval(rest.to_string())),
+            _ => Err(format!("unknown command `{name}`")),
+        }
+    }
+}
+
+/// A command together with where to send the reply.
+pub struct Request {
+    pub command: Command,
+    pub reply: mpsc::Sender<String>,
+}
+
+/// `$XDG_RUNTIME_DIR/status-bar.sock`
+pub fn socket_path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("status-bar.sock"))
+}
+
+/// Listens on the socket at `path`, forwarding each command to the returned channel.
+/// Connections are served from background threads, which wait for the reply before reading
+/// the next line.
+pub fn listen(path: &Path) -> io::Result<channel::Channel<Request>> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is used by another bar", path.display()),
+        ));
+    }
+    // Left behind by a bar that did not exit cleanly.
+    let _ = fs::remove_file(path);
+
+    let listener = UnixListener::bind(path)?;
+    let (sender, receiver) = channel::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let sender = sender.clone();
+            thread::spawn(move || serve(stream, sender));
+        }
+    });
+
+    Ok(receiver)
+}
+
+fn serve(stream: UnixStream, sender: channel::Sender<Request>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let reply = match line.parse() {
+            Ok(command) => {
+                let (reply, response) = mpsc::channel();
+                if sender.send(Request { command, reply }).is_err() {
+                    return;
+                }
+                response.recv().unwrap_or_default()
+            }
+            Err(e) => format!("error: {e}"),
+        };
+
+        if writeln!(writer, "{reply}").is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert!(matches!("reload".parse::<Command>(), Ok(Command::Reload)));
+        assert!(matches!("quit".parse::<Command>(), Ok(Command::Quit)));
+        assert!(matches!("redraw".parse::<Command>(), Ok(Command::Redraw)));
+        assert!(matches!(
+            "eval return 1 + 1".parse::<Command>(),
+            Ok(Command::Eval(code)) if code == "return 1 + 1"
+        ));
+        assert!(matches!("eval".parse::<Command>(), Ok(Command::Eval(code)) if code.is_empty()));
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert_eq!(
+            "restart now".parse::<Command>().unwrap_err(),
+            "unknown command `restart`"
+        );
+        assert!("Reload".parse::<Command>().is_err());
+    }
+}
@@ -1,21 +1,131 @@
+mod api;
+mod config;
+mod ipc;
 mod renderer;
 mod widgets;
 mod window;
-use std::fs::read_to_string;
-
-use mlua::{Function, Lua};
+use std::{env, io, path::PathBuf, process::ExitCode};
 
 use crate::window::Window;
 
-fn main() {
-    let conf = read_to_string("./config.lua").unwrap();
-    let lua = Lua::new();
+/// Passed as the config path to read the config from stdin.
+const STDIN: &str = "-";
+
+/// What the command line asks for: `[--render-once <output.png>] [[--config] <config>]`.
+#[derive(Debug, Default)]
+struct Args {
+    /// A path, or `STDIN`.
+    config: Option<String>,
+    /// Draw each bar once to this PNG and exit, without connecting to a compositor.
+    render_once: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--render-once" => {
+                    let path = args.next().ok_or("--render-once needs an output path")?;
+                    parsed.render_once = Some(PathBuf::from(path));
+                }
+                "--config" => {
+                    let config = args.next().ok_or("--config needs a path, or - for stdin")?;
+                    if parsed.config.replace(config).is_some() {
+                        return Err("the config is given more than once".to_string());
+                    }
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+                _ if parsed.config.is_none() => parsed.config = Some(arg),
+                _ => return Err(format!("unexpected argument {arg}")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn main() -> ExitCode {
+    // Warnings and errors by default, plus the config's `print`s. RUST_LOG picks anything else.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,lua=info"))
+        .init();
+
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-    lua.load(conf).exec().unwrap();
+    // Without a file there is nothing to watch, so a config from stdin is never reloaded.
+    let loaded = if args.config.as_deref() == Some(STDIN) {
+        config::load_from(io::stdin().lock(), "stdin").map(|loaded| (None, loaded))
+    } else {
+        config::locate(config::search_paths(args.config))
+            .and_then(|path| config::load_config(&path).map(|loaded| (Some(path), loaded)))
+    };
+    let (path, (bars, lua)) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(output) = args.render_once {
+        return match renderer::headless::render_once(&bars, &lua, &output) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                log::error!("{e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
 
     // TODO call SimpleLayer::new(...).run() from inside of lua
-    let g = lua.globals();
-    let opts = g.get::<Function>("opts").unwrap().call(()).unwrap();
+    let mut layer = match window::wayland::SimpleLayer::new(bars, lua) {
+        Ok(layer) => layer,
+        Err(e) => {
+            log::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = &path {
+        layer.watch_config(path);
+    }
+    layer.listen_ipc();
+    layer.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_config_and_render_once() {
+        let args = parse(&["--render-once", "out.png", "bar.lua"]).unwrap();
+        assert_eq!(args.config.as_deref(), Some("bar.lua"));
+        assert_eq!(args.render_once, Some(PathBuf::from("out.png")));
+
+        let args = parse(&["--config", STDIN]).unwrap();
+        assert_eq!(args.config.as_deref(), Some(STDIN));
+        assert_eq!(args.render_once, None);
+
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.config, None);
+    }
 
-    window::wayland::SimpleLayer::new(opts, lua).run();
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--render-once"]).is_err());
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--config", "a.lua", "b.lua"]).is_err());
+        assert!(parse(&["a.lua", "--config", "b.lua"]).is_err());
+        assert!(parse(&["a.lua", "b.lua"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
 }
@@ -0,0 +1,177 @@
+//! Draws bars to PNG files without a compositor, for previewing configs and for CI.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use mlua::Lua;
+
+use super::skia_cpu::Canvas;
+use crate::{api, window::Opts};
+
+/// Runs the config's draw function once for each of `bars` at its requested size and writes
+/// the frames as PNG: the first to `path`, later ones next to it with their number appended.
+pub fn render_once(bars: &[Opts], lua: &Lua, path: &Path) -> Result<(), String> {
+    if let Some(mut ctx) = lua.app_data_mut::<api::Context>() {
+        ctx.bars = bars
+            .iter()
+            .map(|opts| api::Bar {
+                namespace: opts.namespace.clone(),
+                transform: "normal",
+            })
+            .collect();
+    }
+
+    for (index, opts) in bars.iter().enumerate() {
+        let path = numbered(path, index);
+        let png = render(lua, opts, index)?;
+        fs::write(&path, png.as_bytes())
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+        log::info!("bar {} written to {}", index + 1, path.display());
+    }
+    Ok(())
+}
+
+/// Draws bar `index` into a buffer of its own and encodes it.
+fn render(lua: &Lua, opts: &Opts, index: usize) -> Result<skia_safe::Data, String> {
+    // A size of 0 fills the output, which there is none of here.
+    let (width, height) = match opts.surface_size() {
+        (0, _) => {
+            return Err(format!(
+                "bar {}: the width must be set for --render-once, it is 0 or stretched",
+                index + 1
+            ));
+        }
+        (_, 0) => {
+            return Err(format!(
+                "bar {}: the height must be set for --render-once, it is 0",
+                index + 1
+            ));
+        }
+        (width, height) => (width as i32, height as i32),
+    };
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    let mut damage = Vec::new();
+    let mut canvas = Canvas::new(width, height, &mut pixels, opts.pixel_format, &mut damage);
+    canvas.clear(opts.background_colour.unwrap_or(0x00000000));
+
+    let call = |name: &str| {
+        api::call_global(lua, name, index + 1)
+            .map_err(|e| format!("bar {}: {name}: {e}", index + 1))
+    };
+    if let Some(mut ctx) = lua.app_data_mut::<api::Context>() {
+        ctx.drawing = Some(index);
+    }
+    call("before_draw")?;
+    let result = lua
+        .globals()
+        .get::<mlua::Function>(opts.draw.as_str())
+        .and_then(|draw| {
+            lua.scope(|scope| {
+                let canvas = scope.create_userdata(canvas)?;
+                draw.call::<()>((canvas, 0.0, index + 1))
+            })
+        })
+        .map_err(|e| format!("bar {}: {}: {e}", index + 1, opts.draw));
+    if let Some(mut ctx) = lua.app_data_mut::<api::Context>() {
+        ctx.drawing = None;
+    }
+    result?;
+    call("after_draw")?;
+
+    // A fresh canvas over the same pixels picks up the frame as drawn.
+    Canvas::new(width, height, &mut pixels, opts.pixel_format, &mut damage)
+        .data()
+        .map_err(|e| format!("bar {}: {e}", index + 1))
+}
+
+/// `path` for the first bar, and `path` with `-2`, `-3` and so on before its extension for
+/// the rest.
+fn numbered(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{}.{}", index + 1, extension.to_string_lossy()),
+        None => format!("{stem}-{}", index + 1),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use skia_safe::{Data, Image};
+
+    use super::*;
+    use crate::config;
+
+    /// Loads the config `conf` and renders it to a PNG in the temp dir named `name`.
+    fn render_config(conf: &str, name: &str) -> Result<Vec<PathBuf>, String> {
+        let (bars, lua) = config::load_from(conf.as_bytes(), "test").map_err(|e| e.to_string())?;
+        let path = env::temp_dir().join(format!("status-bar-{name}-{}.png", std::process::id()));
+        render_once(&bars, &lua, &path)?;
+        Ok((0..bars.len())
+            .map(|index| numbered(&path, index))
+            .collect())
+    }
+
+    #[test]
+    fn renders_each_bar_at_its_size() {
+        let paths = render_config(
+            r#"
+            function opts()
+                return { { width = 40, height = 12 }, { width = 8, height = 3 } }
+            end
+            function draw(canvas)
+                canvas:set_paint_colour(colour(255, 0, 0))
+                canvas:draw_rect(0, 0, canvas:width(), canvas:height())
+            end
+            "#,
+            "render-once",
+        )
+        .unwrap();
+
+        let sizes: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let png = fs::read(path).unwrap();
+                fs::remove_file(path).unwrap();
+                let image = Image::from_encoded(Data::new_copy(&png)).expect("a PNG");
+                (image.width(), image.height())
+            })
+            .collect();
+        assert_eq!(sizes, [(40, 12), (8, 3)]);
+    }
+
+    #[test]
+    fn needs_a_size() {
+        let draw = "function draw() end";
+        let error = render_config(
+            &format!("function opts() return {{ width = 0 }} end {draw}"),
+            "render-once-no-width",
+        )
+        .unwrap_err();
+        assert!(error.contains("width must be set"), "{error}");
+
+        let error = render_config(
+            &format!("function opts() return {{ stretch = true, anchor = 13 }} end {draw}"),
+            "render-once-stretched",
+        )
+        .unwrap_err();
+        assert!(error.contains("width must be set"), "{error}");
+    }
+
+    #[test]
+    fn numbers_later_bars() {
+        let path = Path::new("/tmp/bar.png");
+        assert_eq!(numbered(path, 0), path);
+        assert_eq!(numbered(path, 1), Path::new("/tmp/bar-2.png"));
+        assert_eq!(numbered(path, 2), Path::new("/tmp/bar-3.png"));
+        assert_eq!(numbered(Path::new("out"), 1), Path::new("out-2"));
+    }
+}
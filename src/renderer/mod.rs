@@ -1 +1,4 @@
+pub mod headless;
 pub mod skia_cpu;
+#[cfg(feature = "gpu")]
+pub mod skia_gpu;
@@ -1,11 +1,24 @@
-use std::{fs, mem, sync::LazyLock};
+use std::{
+    collections::HashMap,
+    fs, mem,
+    ops::{Deref, DerefMut},
+    sync::{LazyLock, Mutex, Once},
+    time::SystemTime,
+};
 
-use mlua::UserData;
+use mlua::{FromLua, Lua, UserData};
 use skia_safe::{
-    Borrows, Color, Color4f, Data, EncodedImageFormat, Font, FontMgr, Image, ImageInfo, Paint,
-    PaintStyle, Path, Rect, Surface, surfaces,
+    Borrows, Color, Color4f, Data, EncodedImageFormat, FilterMode, Font, FontMgr, IRect, Image,
+    ImageInfo, MipmapMode, Paint, PaintStyle, Path, PathFillType, Point, RRect, Rect,
+    SamplingOptions, Shader, Surface, TileMode, Typeface,
+    canvas::{ClipOp, SrcRectConstraint},
+    codec::{self, Codec},
+    images, surfaces,
+    textlayout::{FontCollection, ParagraphBuilder, ParagraphStyle, TextStyle},
 };
 
+use crate::{api::colour::Colour, window::PixelFormat};
+
 static FONT_MONOSPACE: LazyLock<Font> = LazyLock::new(|| {
     Font::from_typeface(
         FontMgr::new()
@@ -15,29 +28,375 @@ static FONT_MONOSPACE: LazyLock<Font> = LazyLock::new(|| {
     )
 });
 
+impl From<PixelFormat> for skia_safe::AlphaType {
+    fn from(format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::Argb8888 => skia_safe::AlphaType::Premul,
+            PixelFormat::Xrgb8888 => skia_safe::AlphaType::Opaque,
+        }
+    }
+}
+
+/// Typefaces with a glyph for characters the font being drawn lacks, such as emoji and CJK, by
+/// character. `None` when no installed font has one.
+static FALLBACK_TYPEFACES: LazyLock<Mutex<HashMap<char, Option<Typeface>>>> =
+    LazyLock::new(Default::default);
+
+/// A typeface to draw `c` in when `font` has no glyph for it, `None` to use `font`.
+fn fallback_typeface(font: &Font, c: char) -> Option<Typeface> {
+    if c.is_control() || font.unichar_to_glyph(c as i32) != 0 {
+        return None;
+    }
+
+    let mut typefaces = FALLBACK_TYPEFACES.lock().unwrap_or_else(|e| e.into_inner());
+    typefaces
+        .entry(c)
+        .or_insert_with(|| {
+            FontMgr::new().match_family_style_character(
+                "",
+                skia_safe::FontStyle::normal(),
+                &[],
+                c as i32,
+            )
+        })
+        .clone()
+}
+
+/// `font`, or `font` with its typeface swapped for `typeface`.
+fn font_with(font: &Font, typeface: Option<Typeface>) -> Font {
+    let mut font = font.clone();
+    if let Some(typeface) = typeface {
+        font.set_typeface(typeface);
+    }
+    font
+}
+
+/// `str` split into runs drawn in `font`, and in fallback fonts for the characters it has no
+/// glyph for.
+fn font_runs<'s>(str: &'s str, font: &Font) -> Vec<(&'s str, Font)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut run_typeface: Option<Typeface> = None;
+    for (i, c) in str.char_indices() {
+        // Joiners and variation selectors belong with the character before them.
+        if i > 0 && matches!(c, '\u{200d}' | '\u{fe00}'..='\u{fe0f}') {
+            continue;
+        }
+        let typeface = fallback_typeface(font, c);
+        if i > 0
+            && typeface.as_ref().map(Typeface::unique_id)
+                != run_typeface.as_ref().map(Typeface::unique_id)
+        {
+            runs.push((&str[start..i], font_with(font, run_typeface.take())));
+            start = i;
+        }
+        run_typeface = typeface;
+    }
+    if start < str.len() {
+        runs.push((&str[start..], font_with(font, run_typeface)));
+    }
+    runs
+}
+
+thread_local! {
+    /// Shared by every paragraph so the fonts it resolves are cached between frames.
+    static FONT_COLLECTION: FontCollection = {
+        let mut fonts = FontCollection::new();
+        fonts.set_default_font_manager(FontMgr::new(), None);
+        fonts
+    };
+}
+
+/// Decoded images by path, with the modification time they were read at.
+static IMAGES: LazyLock<Mutex<HashMap<String, (SystemTime, Image)>>> =
+    LazyLock::new(Default::default);
+
+/// Decodes the image at `path`, or reuses it if the file has not changed since.
+fn load_image(path: &str) -> Result<Image, String> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("failed to read {path}: {e}"))?;
+
+    let mut images = IMAGES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((at, image)) = images.get(path)
+        && *at == modified
+    {
+        return Ok(image.clone());
+    }
+
+    let bytes = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let image = Image::from_encoded(Data::new_copy(&bytes))
+        .ok_or_else(|| format!("failed to decode {path}"))?;
+    images.insert(path.to_string(), (modified, image.clone()));
+    Ok(image)
+}
+
+/// How long a frame without a delay of its own is shown, in milliseconds.
+const DEFAULT_FRAME_DELAY: u32 = 100;
+
+/// The frames of an animated image, each with how long it is shown in milliseconds.
+#[derive(Clone)]
+struct Animation {
+    frames: Vec<(Image, u32)>,
+}
+
+impl Animation {
+    /// The frame shown `time` seconds in, looping over the whole animation.
+    fn frame_at(&self, time: f64) -> &Image {
+        let total: u64 = self.frames.iter().map(|(_, delay)| *delay as u64).sum();
+        let mut elapsed = (time.max(0.0) * 1000.0) as u64 % total.max(1);
+        for (image, delay) in &self.frames {
+            if elapsed < *delay as u64 {
+                return image;
+            }
+            elapsed -= *delay as u64;
+        }
+        &self.frames[self.frames.len() - 1].0
+    }
+}
+
+/// Decoded animations by path, with the modification time they were read at.
+static ANIMATIONS: LazyLock<Mutex<HashMap<String, (SystemTime, Animation)>>> =
+    LazyLock::new(Default::default);
+
+/// Decodes every frame of the GIF or WEBP at `path`, or reuses them if the file has not
+/// changed since. A still image is an animation of one frame.
+fn load_animation(path: &str) -> Result<Animation, String> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("failed to read {path}: {e}"))?;
+
+    let mut animations = ANIMATIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((at, animation)) = animations.get(path)
+        && *at == modified
+    {
+        return Ok(animation.clone());
+    }
+
+    let bytes = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let mut codec = Codec::from_data(Data::new_copy(&bytes))
+        .ok_or_else(|| format!("failed to decode {path}"))?;
+    let info = codec
+        .info()
+        .with_color_type(skia_safe::ColorType::N32)
+        .with_alpha_type(skia_safe::AlphaType::Premul);
+
+    let mut frames = Vec::new();
+    for index in 0..codec.get_frame_count().max(1) {
+        // Without a prior frame, the codec decodes any the frame builds on itself.
+        let options = codec::Options {
+            zero_initialized: codec::ZeroInitialized::No,
+            subset: None,
+            frame_index: index,
+            prior_frame: None,
+        };
+        let image = codec.get_image(info.clone(), &options).map_err(|e| {
+            format!(
+                "failed to decode frame {} of {path}: {}",
+                index + 1,
+                codec::result_to_string(e)
+            )
+        })?;
+        let delay = codec
+            .get_frame_info(index)
+            .map_or(0, |frame| frame.duration.max(0) as u32);
+        frames.push((
+            image,
+            if delay == 0 {
+                DEFAULT_FRAME_DELAY
+            } else {
+                delay
+            },
+        ));
+    }
+
+    let animation = Animation { frames };
+    animations.insert(path.to_string(), (modified, animation.clone()));
+    Ok(animation)
+}
+
+/// The monospace font at `size`, or at its default size when unset.
+fn monospace(size: Option<f32>) -> Font {
+    size.and_then(|size| FONT_MONOSPACE.with_size(size))
+        .unwrap_or_else(|| FONT_MONOSPACE.clone())
+}
+
+/// Where text sits relative to the x it is drawn at.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl FromLua for Align {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        match String::from_lua(value, lua)?.as_str() {
+            "left" => Ok(Align::Left),
+            "center" => Ok(Align::Center),
+            "right" => Ok(Align::Right),
+            other => Err(mlua::Error::runtime(format!(
+                r#"unknown alignment "{other}", expected "left", "center" or "right""#
+            ))),
+        }
+    }
+}
+
+/// A run of `draw_paragraph`'s text, either a string or `{ text, size?, colour? }`.
+pub struct TextRun {
+    text: String,
+    /// Font size, defaulting to the monospace font's.
+    size: Option<f32>,
+    /// Defaults to the paint's colour.
+    colour: Option<u32>,
+}
+
+impl FromLua for TextRun {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        if let mlua::Value::String(s) = &value {
+            return Ok(TextRun {
+                text: s.to_str()?.to_string(),
+                size: None,
+                colour: None,
+            });
+        }
+
+        let t = mlua::Table::from_lua(value, lua)?;
+        Ok(TextRun {
+            text: t.get("text")?,
+            size: t.get("size")?,
+            colour: t.get::<Option<Colour>>("colour")?.map(|c| c.0),
+        })
+    }
+}
+
+/// The surface a canvas draws to, either wrapping borrowed pixels or owned by a renderer.
+enum Backing<'a> {
+    Pixels(Borrows<'a, Surface>),
+    Surface(&'a mut Surface),
+}
+
+impl Deref for Backing<'_> {
+    type Target = Surface;
+
+    fn deref(&self) -> &Surface {
+        match self {
+            Backing::Pixels(surface) => surface,
+            Backing::Surface(surface) => surface,
+        }
+    }
+}
+
+impl DerefMut for Backing<'_> {
+    fn deref_mut(&mut self) -> &mut Surface {
+        match self {
+            Backing::Pixels(surface) => surface,
+            Backing::Surface(surface) => surface,
+        }
+    }
+}
+
+/// An offscreen surface that lua draws into once and blits onto the bar each frame, to cache
+/// drawing that is expensive to repeat. Freed once lua stops referencing it.
+pub struct Layer {
+    surface: Surface,
+    /// In surface coordinates, the layer's pixels are this times `scale`.
+    width: i32,
+    height: i32,
+    scale: f32,
+}
+
+impl Layer {
+    /// A canvas over the layer's contents, at the scale the layer was created with.
+    fn canvas<'a>(&'a mut self, damage: &'a mut Vec<IRect>) -> Canvas<'a> {
+        let mut canvas = Canvas::from_surface(&mut self.surface, damage);
+        canvas.set_scale_factor(self.scale);
+        canvas
+    }
+}
+
+impl UserData for Layer {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("width", |_, this, ()| Ok(this.width));
+
+        methods.add_method("height", |_, this, ()| Ok(this.height));
+
+        // Draws over what the layer already holds, clear it first to redraw from scratch.
+        methods.add_method_mut("draw", |lua, this, f: mlua::Function| {
+            // Nothing is presented from a layer, so its damage goes unused.
+            let mut damage = Vec::new();
+            let canvas = this.canvas(&mut damage);
+            lua.scope(|scope| f.call::<()>(scope.create_userdata(canvas)?))
+        });
+
+        methods.add_method_mut("clear", |_, this, colour: Option<Colour>| {
+            this.surface
+                .canvas()
+                .clear(colour.map_or(Color::TRANSPARENT, |c| Color::new(c.0)));
+            Ok(())
+        });
+    }
+}
+
 pub struct Canvas<'a> {
-    surface: Borrows<'a, Surface>,
+    surface: Backing<'a>,
     path: Path,
     paint: Paint,
+    /// Extra space after each character of text, in pixels.
+    letter_spacing: f32,
+    /// How images are filtered when scaled.
+    sampling: SamplingOptions,
+    /// Which parts of a self-intersecting path count as inside when it is filled.
+    fill_rule: PathFillType,
+    /// Buffer pixels per surface coordinate, applied to everything drawn.
+    scale: f32,
+    /// Regions marked as changed during this draw.
+    damage: &'a mut Vec<IRect>,
+    /// Regions of the buffer left from older frames, in buffer pixels. `None` when all of it is.
+    stale: Option<Vec<IRect>>,
+    /// How many clips `push_clip` and `clip_round_rect` have pushed that `pop_clip` has yet to
+    /// pop.
+    clips: usize,
 }
 
 #[allow(unused)]
 impl Canvas<'_> {
-    pub fn new(width: i32, height: i32, canvas_data: &mut [u8]) -> Canvas<'_> {
+    pub fn new<'a>(
+        width: i32,
+        height: i32,
+        canvas_data: &'a mut [u8],
+        format: PixelFormat,
+        damage: &'a mut Vec<IRect>,
+    ) -> Canvas<'a> {
+        // BGRA is the in-memory layout of wl_shm's little endian Argb8888 and Xrgb8888.
         let image_info = ImageInfo::new(
             (width, height),
             skia_safe::ColorType::BGRA8888,
-            skia_safe::AlphaType::Premul,
+            skia_safe::AlphaType::from(format),
             None,
         );
 
         let stride = (width * 4) as usize;
 
-        let mut surface: Borrows<Surface> =
+        let surface: Borrows<Surface> =
             surfaces::wrap_pixels(&image_info, canvas_data, stride, None)
                 .expect("failed to create Skia surface");
 
-        let canvas = surface.canvas();
+        Canvas::with_backing(Backing::Pixels(surface), damage)
+    }
+
+    /// Draws to a surface owned elsewhere, such as the GPU renderer's.
+    pub fn from_surface<'a>(surface: &'a mut Surface, damage: &'a mut Vec<IRect>) -> Canvas<'a> {
+        Canvas::with_backing(Backing::Surface(surface), damage)
+    }
+
+    // The surface is left as it is, so a second canvas can draw over an earlier one's frame.
+    fn with_backing<'a>(mut surface: Backing<'a>, damage: &'a mut Vec<IRect>) -> Canvas<'a> {
+        // A renderer's surface keeps its canvas between frames, transforms included.
+        surface.canvas().restore_to_count(1);
+        surface.canvas().reset_matrix();
+
         let path = skia_safe::Path::default();
         let mut paint = Paint::default();
 
@@ -45,19 +404,103 @@ impl Canvas<'_> {
         paint.set_anti_alias(true);
         paint.set_stroke_width(1.0);
 
-        canvas.clear(Color::WHITE);
-
         Canvas {
             surface,
             path,
             paint,
+            letter_spacing: 0.0,
+            sampling: SamplingOptions::new(FilterMode::Linear, MipmapMode::None),
+            fill_rule: PathFillType::Winding,
+            scale: 1.0,
+            damage,
+            stale: None,
+            clips: 0,
         }
     }
 
+    /// Draws at `scale` buffer pixels per unit, so sizes and text stay the same physical size
+    /// on outputs of any scale.
+    pub fn set_scale_factor(&mut self, scale: f32) {
+        self.surface
+            .canvas()
+            .scale((scale / self.scale, scale / self.scale));
+        self.scale = scale;
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.scale
+    }
+
+    /// In surface coordinates, which are buffer pixels divided by the scale.
+    pub fn width(&self) -> i32 {
+        (self.surface.width() as f32 / self.scale) as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        (self.surface.height() as f32 / self.scale) as i32
+    }
+
+    /// Sets what of the buffer is out of date, for a renderer that knows which of its buffers
+    /// it reused.
+    pub fn set_stale_region(&mut self, stale: Option<Vec<IRect>>) {
+        self.stale = stale;
+    }
+
+    /// The regions of the buffer not brought up to date by the previous frame, in surface
+    /// coordinates. `None` when none of it can be relied on.
+    pub fn stale_region(&self) -> Option<Vec<Rect>> {
+        let stale = self.stale.as_ref()?;
+        Some(
+            stale
+                .iter()
+                .map(|r| {
+                    Rect::from_xywh(
+                        r.left as f32 / self.scale,
+                        r.top as f32 / self.scale,
+                        r.width() as f32 / self.scale,
+                        r.height() as f32 / self.scale,
+                    )
+                })
+                .collect(),
+        )
+    }
+
     pub fn clear(&mut self, colour: impl Into<Color4f>) {
         self.surface.canvas().clear(colour);
     }
 
+    /// Clears what `stale_region` covers, or all of the buffer when that is unknown, keeping
+    /// what a reused buffer still holds from earlier frames.
+    pub fn clear_stale(&mut self, colour: impl Into<Color4f>) {
+        let colour = colour.into();
+        let Some(stale) = &self.stale else {
+            self.clear(colour);
+            return;
+        };
+
+        // The stale region is in buffer pixels, unaffected by the scale.
+        let canvas = self.surface.canvas();
+        for rect in stale {
+            canvas.save();
+            canvas.reset_matrix();
+            canvas.clip_irect(rect, ClipOp::Intersect);
+            canvas.clear(colour);
+            canvas.restore();
+        }
+    }
+
+    /// Records a region as changed so only it is damaged when the frame is presented.
+    pub fn mark_dirty(&mut self, position: (i32, i32), scale: (i32, i32)) {
+        // Damage is in buffer pixels.
+        let rect = Rect::from_xywh(
+            position.0 as f32 * self.scale,
+            position.1 as f32 * self.scale,
+            scale.0 as f32 * self.scale,
+            scale.1 as f32 * self.scale,
+        );
+        self.damage.push(rect.round_out());
+    }
+
     // Draw
     pub fn draw_line(&mut self, from: (f32, f32), to: (f32, f32)) {
         self.surface.canvas().draw_line(from, to, &self.paint);
@@ -69,26 +512,331 @@ impl Canvas<'_> {
             .draw_rect(Rect::from_point_and_size(position, scale), &self.paint);
     }
 
+    /// Fills a rect with a linear gradient through `colours`, evenly spaced, running `angle`
+    /// degrees clockwise from left to right. The gradient spans the rect, so its first and last
+    /// colours land on opposite corners.
+    pub fn draw_rect_gradient(
+        &mut self,
+        position: (f32, f32),
+        scale: (f32, f32),
+        colours: &[Color],
+        angle: f32,
+    ) {
+        let rect = Rect::from_point_and_size(position, scale);
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let half = (rect.width() * cos.abs() + rect.height() * sin.abs()) / 2.0;
+        let (cx, cy) = (rect.center_x(), rect.center_y());
+        let shader = Shader::linear_gradient(
+            (
+                (cx - cos * half, cy - sin * half),
+                (cx + cos * half, cy + sin * half),
+            ),
+            colours,
+            None,
+            TileMode::Clamp,
+            None,
+            None,
+        );
+
+        // Filled whatever the paint's style, which is left as it was.
+        let mut paint = self.paint.clone();
+        paint.set_style(PaintStyle::Fill);
+        paint.set_shader(shader);
+        self.surface.canvas().draw_rect(rect, &paint);
+    }
+
     pub fn draw_circle(&mut self, center: (f32, f32), radius: f32) {
         self.surface
             .canvas()
             .draw_circle(center, radius, &self.paint);
     }
 
+    /// Draws straight lines between `points`, filled or stroked by the current paint style.
+    pub fn draw_polygon(&mut self, points: &[(f32, f32)], closed: bool) {
+        let points: Vec<Point> = points.iter().map(|&p| p.into()).collect();
+        let path = Path::polygon(&points, closed, None, None);
+        self.surface.canvas().draw_path(&path, &self.paint);
+    }
+
+    /// Draws `str` with its baseline at `position.1`. Characters `font` has no glyph for, such
+    /// as emoji, are drawn in an installed font that has one.
     pub fn draw_text(&mut self, position: (f32, f32), str: &str, font: &Font) {
-        self.surface
-            .canvas()
-            .draw_str(str, position, font, &self.paint);
+        let mut x = position.0;
+        for (run, font) in font_runs(str, font) {
+            if self.letter_spacing == 0.0 {
+                self.surface
+                    .canvas()
+                    .draw_str(run, (x, position.1), &font, &self.paint);
+                x += font.measure_str(run, Some(&self.paint)).0;
+                continue;
+            }
+
+            // Skia has no tracking, so each character is placed on its own.
+            let mut buf = [0; 4];
+            for c in run.chars() {
+                let c = c.encode_utf8(&mut buf);
+                self.surface
+                    .canvas()
+                    .draw_str(&*c, (x, position.1), &font, &self.paint);
+                x += font.measure_str(&*c, Some(&self.paint)).0 + self.letter_spacing;
+            }
+        }
+    }
+
+    /// Draws `str` with its left edge, centre or right edge at `position.0`, on the same
+    /// baseline as `draw_text`.
+    pub fn draw_text_aligned(
+        &mut self,
+        position: (f32, f32),
+        str: &str,
+        font: &Font,
+        align: Align,
+    ) {
+        let (width, _) = self.measure_text(str, font);
+        let x = match align {
+            Align::Left => position.0,
+            Align::Center => position.0 - width / 2.0,
+            Align::Right => position.0 - width,
+        };
+        self.draw_text((x, position.1), str, font);
     }
 
-    pub fn draw_image(&mut self, position: (f32, f32), scale: (f32, f32), path: &str) {
-        let i = fs::read(path).expect("failed to read file");
-        let data = Data::new_copy(&i);
-        let image = Image::from_encoded(data).expect("failed to decode file");
+    /// Draws `str` like `draw_text`, shaded from `from` at its left edge to `to` at its right.
+    pub fn draw_text_gradient(
+        &mut self,
+        position: (f32, f32),
+        str: &str,
+        font: &Font,
+        (from, to): (Color, Color),
+    ) {
+        let (width, _) = self.measure_text(str, font);
+        // Spans the text rather than the canvas, so the gradient moves with it.
+        let shader = Shader::linear_gradient(
+            (position, (position.0 + width, position.1)),
+            &[from, to][..],
+            None,
+            TileMode::Clamp,
+            None,
+            None,
+        );
+
+        let previous = self.paint.shader();
+        self.paint.set_shader(shader);
+        self.draw_text(position, str, font);
+        self.paint.set_shader(previous);
+    }
+
+    /// The advance width of `str` and the font's line spacing.
+    pub fn measure_text(&self, str: &str, font: &Font) -> (f32, f32) {
+        let width: f32 = font_runs(str, font)
+            .iter()
+            .map(|(run, font)| font.measure_str(run, Some(&self.paint)).0)
+            .sum();
+        let gaps = str.chars().count().saturating_sub(1) as f32;
+        (width + gaps * self.letter_spacing, font.spacing())
+    }
+
+    /// Lays out `runs` as one paragraph, wrapped to `max_width`, with its top left corner at
+    /// `position`. Returns the height it took.
+    pub fn draw_paragraph(
+        &mut self,
+        position: (f32, f32),
+        runs: &[TextRun],
+        max_width: f32,
+    ) -> f32 {
+        let fonts = FONT_COLLECTION.with(FontCollection::clone);
+        let mut builder = ParagraphBuilder::new(&ParagraphStyle::new(), fonts);
+        for run in runs {
+            let mut style = TextStyle::new();
+            style.set_font_families(&["monospace"]);
+            style.set_font_size(run.size.unwrap_or(FONT_MONOSPACE.size()));
+            style.set_color(run.colour.map_or(self.paint.color(), Color::new));
+            style.set_letter_spacing(self.letter_spacing);
+            builder.push_style(&style);
+            builder.add_text(&run.text);
+            builder.pop();
+        }
+
+        let mut paragraph = builder.build();
+        paragraph.layout(max_width);
+        paragraph.paint(self.surface.canvas(), position);
+        paragraph.height()
+    }
+
+    pub fn draw_image(
+        &mut self,
+        position: (f32, f32),
+        scale: (f32, f32),
+        path: &str,
+    ) -> Result<(), String> {
+        let image = load_image(path)?;
         let dst = Rect::from_point_and_size(position, scale);
-        self.surface
-            .canvas()
-            .draw_image_rect(image, None, dst, &self.paint);
+        self.surface.canvas().draw_image_rect_with_sampling_options(
+            image,
+            None,
+            dst,
+            self.sampling,
+            &self.paint,
+        );
+        Ok(())
+    }
+
+    /// Draws the frame of an animated image shown `time` seconds in, following the delays
+    /// stored in the file and looping.
+    pub fn draw_animated(
+        &mut self,
+        path: &str,
+        position: (f32, f32),
+        scale: (f32, f32),
+        time: f64,
+    ) -> Result<(), String> {
+        let animation = load_animation(path)?;
+        let dst = Rect::from_point_and_size(position, scale);
+        self.surface.canvas().draw_image_rect_with_sampling_options(
+            animation.frame_at(time),
+            None,
+            dst,
+            self.sampling,
+            &self.paint,
+        );
+        Ok(())
+    }
+
+    /// Draws the `src` rect (x, y, width, height) of an image, such as one icon of an atlas.
+    pub fn draw_image_region(
+        &mut self,
+        path: &str,
+        src: (f32, f32, f32, f32),
+        position: (f32, f32),
+        scale: (f32, f32),
+    ) -> Result<(), String> {
+        let image = load_image(path)?;
+        let src = Rect::from_xywh(src.0, src.1, src.2, src.3);
+        let dst = Rect::from_point_and_size(position, scale);
+        // Strict keeps filtering from bleeding in pixels from neighbouring regions.
+        self.surface.canvas().draw_image_rect_with_sampling_options(
+            image,
+            Some((&src, SrcRectConstraint::Strict)),
+            dst,
+            self.sampling,
+            &self.paint,
+        );
+        Ok(())
+    }
+
+    /// Draws raw ARGB32 pixels in network byte order, the format of tray icons.
+    pub fn draw_pixmap(
+        &mut self,
+        position: (f32, f32),
+        scale: (f32, f32),
+        size: (i32, i32),
+        argb: &[u8],
+    ) {
+        if size.0 <= 0 || size.1 <= 0 || argb.len() < (size.0 * size.1 * 4) as usize {
+            return;
+        }
+
+        let bgra: Vec<u8> = argb
+            .chunks_exact(4)
+            .flat_map(|p| [p[3], p[2], p[1], p[0]])
+            .collect();
+        let image_info = ImageInfo::new(
+            size,
+            skia_safe::ColorType::BGRA8888,
+            skia_safe::AlphaType::Unpremul,
+            None,
+        );
+        let Some(image) =
+            images::raster_from_data(&image_info, Data::new_copy(&bgra), size.0 as usize * 4)
+        else {
+            return;
+        };
+
+        let dst = Rect::from_point_and_size(position, scale);
+        self.surface.canvas().draw_image_rect_with_sampling_options(
+            image,
+            None,
+            dst,
+            self.sampling,
+            &self.paint,
+        );
+    }
+
+    /// The box `str` covers when drawn with its baseline at the origin.
+    fn text_bounds(&self, str: &str, font: &Font) -> Rect {
+        font.measure_str(str, Some(&self.paint)).1
+    }
+
+    /// Draws `str` centred on a rounded box that reaches `padding` beyond the text on every
+    /// side, with the box's top left corner at `position`. Returns the box's size.
+    pub fn draw_badge(
+        &mut self,
+        position: (f32, f32),
+        str: &str,
+        font: &Font,
+        (text_colour, background_colour): (Color, Color),
+        padding: f32,
+        radius: f32,
+    ) -> (f32, f32) {
+        let bounds = self.text_bounds(str, font);
+        let size = (
+            bounds.width() + padding * 2.0,
+            bounds.height() + padding * 2.0,
+        );
+
+        // Drawn with its own paint so the canvas's colour and style are left alone.
+        let mut paint = self.paint.clone();
+        paint.set_style(PaintStyle::Fill);
+        paint.set_color(background_colour);
+        self.surface.canvas().draw_round_rect(
+            Rect::from_point_and_size(position, size),
+            radius,
+            radius,
+            &paint,
+        );
+
+        paint.set_color(text_colour);
+        let origin = (
+            position.0 + padding - bounds.left,
+            position.1 + padding - bounds.top,
+        );
+        self.surface.canvas().draw_str(str, origin, font, &paint);
+        size
+    }
+
+    /// A transparent layer of `size` at this canvas's scale, so it stays sharp when drawn.
+    pub fn create_layer(&self, size: (i32, i32)) -> Result<Layer, String> {
+        if size.0 <= 0 || size.1 <= 0 {
+            return Err("layer width and height must be positive".to_string());
+        }
+
+        let pixels = (
+            (size.0 as f32 * self.scale).ceil() as i32,
+            (size.1 as f32 * self.scale).ceil() as i32,
+        );
+        let mut surface = surfaces::raster_n32_premul(pixels)
+            .ok_or_else(|| format!("failed to create a {}x{} layer", size.0, size.1))?;
+        surface.canvas().clear(Color::TRANSPARENT);
+
+        Ok(Layer {
+            surface,
+            width: size.0,
+            height: size.1,
+            scale: self.scale,
+        })
+    }
+
+    /// Draws `layer` at its size with its top left corner at `position`.
+    pub fn draw_layer(&mut self, layer: &mut Layer, position: (f32, f32)) {
+        let image = layer.surface.image_snapshot();
+        let dst = Rect::from_point_and_size(position, (layer.width as f32, layer.height as f32));
+        self.surface.canvas().draw_image_rect_with_sampling_options(
+            image,
+            None,
+            dst,
+            self.sampling,
+            &self.paint,
+        );
     }
 
     // TEMPORARY ---
@@ -98,17 +846,8 @@ impl Canvas<'_> {
         let outline_width = 2.0;
         let offset = padding + outline_width / 2.0;
 
-        let (
-            width,
-            Rect {
-                left,
-                top,
-                right,
-                bottom,
-            },
-        ) = FONT_MONOSPACE.measure_str(str, Some(&self.paint));
-        let height = bottom - top;
-        let width = right - left;
+        let bounds = self.text_bounds(str, &FONT_MONOSPACE);
+        let (width, height) = (bounds.width(), bounds.height());
 
         self.set_paint_colour(Color::YELLOW);
         self.draw_rect(
@@ -127,10 +866,64 @@ impl Canvas<'_> {
         self.paint.set_stroke_width(1.0);
         self.paint.set_style(PaintStyle::Fill);
 
-        self.draw_text((offset, offset + height - bottom), str, &FONT_MONOSPACE);
+        self.draw_text((offset, offset - bounds.top), str, &FONT_MONOSPACE);
     }
     // ---
 
+    /// Replaces the frame with `message`, one line per row until the canvas runs out.
+    pub fn draw_error(&mut self, message: &str) {
+        let height = self.height() as f32;
+        let (_, bounds) = FONT_MONOSPACE.measure_str("Ay", Some(&self.paint));
+        let line_height = bounds.height() + 2.0;
+
+        self.clear(0xFF1E1E1E);
+        self.set_paint_colour(0xFFFF5555);
+
+        let mut y = line_height;
+        for line in message.lines() {
+            if y > height + line_height {
+                break;
+            }
+            self.draw_text((4.0, y), line, &FONT_MONOSPACE);
+            y += line_height;
+        }
+    }
+
+    /// Limits drawing to a rect, within any clip already pushed, until the matching `pop_clip`.
+    pub fn push_clip(&mut self, position: (f32, f32), scale: (f32, f32)) {
+        let canvas = self.surface.canvas();
+        canvas.save();
+        canvas.clip_rect(
+            Rect::from_point_and_size(position, scale),
+            ClipOp::Intersect,
+            true,
+        );
+        self.clips += 1;
+    }
+
+    /// Like `push_clip`, with the rect's corners rounded to `radius`, so images and fills
+    /// within are cut to a card's shape.
+    pub fn clip_round_rect(&mut self, position: (f32, f32), scale: (f32, f32), radius: f32) {
+        let canvas = self.surface.canvas();
+        canvas.save();
+        canvas.clip_rrect(
+            RRect::new_rect_xy(Rect::from_point_and_size(position, scale), radius, radius),
+            ClipOp::Intersect,
+            true,
+        );
+        self.clips += 1;
+    }
+
+    /// Removes the last clip pushed, along with any transform made since.
+    pub fn pop_clip(&mut self) -> Result<(), String> {
+        if self.clips == 0 {
+            return Err("pop_clip without a matching push_clip".to_string());
+        }
+        self.surface.canvas().restore();
+        self.clips -= 1;
+        Ok(())
+    }
+
     pub fn translate(&mut self, d: (f32, f32)) {
         self.canvas().translate(d);
     }
@@ -140,8 +933,10 @@ impl Canvas<'_> {
     }
 
     // Path
+    /// Starts a new path at `point`, discarding the current one. Draw it first with
+    /// `draw_path_stroke` or `draw_path_fill` to keep it.
     pub fn path_begin_from(&mut self, point: (f32, f32)) {
-        self.path_begin();
+        self.clear_path();
         self.path.move_to(point);
     }
 
@@ -157,8 +952,16 @@ impl Canvas<'_> {
         self.path.cubic_to(cp1, cp2, to);
     }
 
+    /// Discards the current path without drawing it.
+    pub fn clear_path(&mut self) {
+        self.path = Path::new();
+    }
+
+    /// Draws the current path with the paint as it is, then starts a new one.
+    #[deprecated(note = "draw the path explicitly, then call `clear_path`")]
     pub fn path_begin(&mut self) {
         let new_path = Path::new();
+        self.path.set_fill_type(self.fill_rule);
         self.surface.canvas().draw_path(&self.path, &self.paint);
         let _ = mem::replace(&mut self.path, new_path);
     }
@@ -174,6 +977,7 @@ impl Canvas<'_> {
 
     pub fn draw_path_fill(&mut self) {
         self.paint.set_style(PaintStyle::Fill);
+        self.path.set_fill_type(self.fill_rule);
         self.surface.canvas().draw_path(&self.path, &self.paint);
     }
 
@@ -189,13 +993,51 @@ impl Canvas<'_> {
         self.paint.set_stroke_width(width);
     }
 
+    /// Space added between characters of text, 0 by default. Negative values tighten it.
+    pub fn set_letter_spacing(&mut self, px: f32) {
+        self.letter_spacing = px;
+    }
+
+    /// Linear filtering by default, nearest keeps pixel art sharp when scaled up.
+    pub fn set_image_sampling(&mut self, filter: FilterMode) {
+        self.sampling = SamplingOptions::new(filter, MipmapMode::None);
+    }
+
+    /// Winding by default, even-odd leaves the overlaps of a self-intersecting path unfilled.
+    pub fn set_fill_rule(&mut self, rule: PathFillType) {
+        self.fill_rule = rule;
+    }
+
+    /// Anti-aliasing is on by default, turn it off for crisp single pixel lines.
+    pub fn set_anti_alias(&mut self, on: bool) {
+        self.paint.set_anti_alias(on);
+    }
+
     // Other
-    pub fn data(&mut self) -> Data {
+    /// Encodes the canvas as it is so far as a PNG.
+    pub fn data(&mut self) -> Result<Data, String> {
+        self.encode("png", 100)
+    }
+
+    /// Encodes the canvas as it is so far as "png", "jpeg" or "webp". `quality` from 0 to 100
+    /// applies to the lossy formats.
+    pub fn encode(&mut self, format: &str, quality: u32) -> Result<Data, String> {
+        let (format, quality) = match format {
+            "png" => (EncodedImageFormat::PNG, None),
+            "jpeg" | "jpg" => (EncodedImageFormat::JPEG, Some(quality.min(100))),
+            "webp" => (EncodedImageFormat::WEBP, Some(quality.min(100))),
+            _ => {
+                return Err(format!(
+                    r#"unknown image format "{format}", expected "png", "jpeg" or "webp""#
+                ));
+            }
+        };
+
         let image = self.surface.image_snapshot();
         let mut context = self.surface.direct_context();
         image
-            .encode(context.as_mut(), EncodedImageFormat::PNG, None)
-            .unwrap()
+            .encode(context.as_mut(), format, quality)
+            .ok_or_else(|| format!("failed to encode canvas as {format:?}"))
     }
 
     fn canvas(&mut self) -> &skia_safe::Canvas {
@@ -205,8 +1047,30 @@ impl Canvas<'_> {
 
 impl<'a> UserData for Canvas<'a> {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_method_mut("clear", |_, this, col: u32| {
-            this.clear(col);
+        methods.add_method("width", |_, this, ()| Ok(this.width()));
+
+        methods.add_method("height", |_, this, ()| Ok(this.height()));
+
+        methods.add_method("scale_factor", |_, this, ()| Ok(this.scale_factor()));
+
+        methods.add_method("stale_region", |lua, this, ()| {
+            let Some(stale) = this.stale_region() else {
+                return Ok(None);
+            };
+            let rects = stale
+                .iter()
+                .map(|r| lua.create_sequence_from([r.left, r.top, r.width(), r.height()]))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Ok(Some(rects))
+        });
+
+        methods.add_method_mut("clear", |_, this, colour: Colour| {
+            this.clear(Color::new(colour.0));
+            Ok(())
+        });
+
+        methods.add_method_mut("mark_dirty", |_, this, (px, py, sx, sy)| {
+            this.mark_dirty((px, py), (sx, sy));
             Ok(())
         });
 
@@ -215,6 +1079,34 @@ impl<'a> UserData for Canvas<'a> {
             Ok(())
         });
 
+        methods.add_method_mut(
+            "draw_rect_gradient",
+            |_, this, (px, py, sx, sy, stops, angle): (f32, f32, f32, f32, Vec<Colour>, Option<f32>)| {
+                if stops.len() < 2 {
+                    return Err(mlua::Error::runtime(
+                        "draw_rect_gradient: expected at least two colours",
+                    ));
+                }
+                let colours: Vec<Color> = stops.into_iter().map(|c| Color::new(c.0)).collect();
+                this.draw_rect_gradient((px, py), (sx, sy), &colours, angle.unwrap_or(0.0));
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut("push_clip", |_, this, (px, py, sx, sy)| {
+            this.push_clip((px, py), (sx, sy));
+            Ok(())
+        });
+
+        methods.add_method_mut("clip_round_rect", |_, this, (px, py, sx, sy, radius)| {
+            this.clip_round_rect((px, py), (sx, sy), radius);
+            Ok(())
+        });
+
+        methods.add_method_mut("pop_clip", |_, this, ()| {
+            this.pop_clip().map_err(mlua::Error::runtime)
+        });
+
         methods.add_method_mut("draw_circle", |_, this, (px, py, radius)| {
             this.draw_circle((px, py), radius);
             Ok(())
@@ -225,16 +1117,130 @@ impl<'a> UserData for Canvas<'a> {
             Ok(())
         });
 
+        methods.add_method_mut(
+            "draw_polygon",
+            |_, this, (points, closed): (mlua::Table, Option<bool>)| {
+                let points = points
+                    .sequence_values::<mlua::Table>()
+                    .map(|p| {
+                        let p = p?;
+                        Ok((p.get(1)?, p.get(2)?))
+                    })
+                    .collect::<mlua::Result<Vec<(f32, f32)>>>()?;
+                this.draw_polygon(&points, closed.unwrap_or(true));
+                Ok(())
+            },
+        );
+
         methods.add_method_mut("draw_text", |_, this, (px, py, str): (f32, f32, String)| {
             // TODO implement UserData for Font
             this.draw_text((px, py), &str, &FONT_MONOSPACE);
             Ok(())
         });
 
+        methods.add_method_mut(
+            "draw_text_aligned",
+            |_, this, (px, py, str, size, align): (f32, f32, String, Option<f32>, Option<Align>)| {
+                this.draw_text_aligned((px, py), &str, &monospace(size), align.unwrap_or_default());
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_paragraph",
+            |_, this, (px, py, runs, max_width): (f32, f32, Vec<TextRun>, f32)| {
+                Ok(this.draw_paragraph((px, py), &runs, max_width))
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_text_gradient",
+            |_, this, (px, py, str, size, from, to): (f32, f32, String, Option<f32>, Colour, Colour)| {
+                this.draw_text_gradient(
+                    (px, py),
+                    &str,
+                    &monospace(size),
+                    (Color::new(from.0), Color::new(to.0)),
+                );
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_badge",
+            |_,
+             this,
+             (px, py, str, size, text_colour, background_colour, padding, radius): (
+                f32,
+                f32,
+                String,
+                Option<f32>,
+                Colour,
+                Colour,
+                Option<f32>,
+                Option<f32>,
+            )| {
+                Ok(this.draw_badge(
+                    (px, py),
+                    &str,
+                    &monospace(size),
+                    (Color::new(text_colour.0), Color::new(background_colour.0)),
+                    padding.unwrap_or(4.0),
+                    radius.unwrap_or(0.0),
+                ))
+            },
+        );
+
+        methods.add_method(
+            "measure_text",
+            |_, this, (str, size): (String, Option<f32>)| {
+                Ok(this.measure_text(&str, &monospace(size)))
+            },
+        );
+
         methods.add_method_mut(
             "draw_image",
             |_, this, (px, py, sx, sy, path): (f32, f32, f32, f32, String)| {
-                this.draw_image((px, py), (sx, sy), &path);
+                this.draw_image((px, py), (sx, sy), &path)
+                    .map_err(mlua::Error::runtime)
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_animated",
+            |_, this, (path, px, py, sx, sy, time): (String, f32, f32, f32, f32, f64)| {
+                this.draw_animated(&path, (px, py), (sx, sy), time)
+                    .map_err(mlua::Error::runtime)
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_image_region",
+            |_,
+             this,
+             (path, sx, sy, sw, sh, px, py, w, h): (
+                String,
+                f32,
+                f32,
+                f32,
+                f32,
+                f32,
+                f32,
+                f32,
+                f32,
+            )| {
+                this.draw_image_region(&path, (sx, sy, sw, sh), (px, py), (w, h))
+                    .map_err(mlua::Error::runtime)
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_pixmap",
+            |_, this, (px, py, sx, sy, pixmap): (f32, f32, f32, f32, mlua::Table)| {
+                let width = pixmap.get("width")?;
+                let height = pixmap.get("height")?;
+                let data: mlua::String = pixmap.get("data")?;
+                this.draw_pixmap((px, py), (sx, sy), (width, height), &data.as_bytes());
                 Ok(())
             },
         );
@@ -249,8 +1255,8 @@ impl<'a> UserData for Canvas<'a> {
             Ok(())
         });
 
-        methods.add_method_mut("set_paint_colour", |_, this, colour: u32| {
-            this.set_paint_colour(colour);
+        methods.add_method_mut("set_paint_colour", |_, this, colour: Colour| {
+            this.set_paint_colour(colour.0);
             Ok(())
         });
 
@@ -269,6 +1275,78 @@ impl<'a> UserData for Canvas<'a> {
             Ok(())
         });
 
+        methods.add_method_mut("set_anti_alias", |_, this, on| {
+            this.set_anti_alias(on);
+            Ok(())
+        });
+
+        methods.add_method_mut("set_letter_spacing", |_, this, px| {
+            this.set_letter_spacing(px);
+            Ok(())
+        });
+
+        methods.add_method_mut("set_image_sampling", |_, this, mode: String| {
+            let filter = match mode.as_str() {
+                "nearest" => FilterMode::Nearest,
+                "linear" => FilterMode::Linear,
+                _ => {
+                    return Err(mlua::Error::runtime(format!(
+                        r#"unknown image sampling "{mode}", expected "nearest" or "linear""#
+                    )));
+                }
+            };
+            this.set_image_sampling(filter);
+            Ok(())
+        });
+
+        methods.add_method_mut("set_fill_rule", |_, this, rule: String| {
+            let rule = match rule.as_str() {
+                "winding" => PathFillType::Winding,
+                "even-odd" => PathFillType::EvenOdd,
+                _ => {
+                    return Err(mlua::Error::runtime(format!(
+                        r#"unknown fill rule "{rule}", expected "winding" or "even-odd""#
+                    )));
+                }
+            };
+            this.set_fill_rule(rule);
+            Ok(())
+        });
+
+        methods.add_method("create_layer", |_, this, (width, height): (i32, i32)| {
+            this.create_layer((width, height))
+                .map_err(mlua::Error::runtime)
+        });
+
+        methods.add_method_mut(
+            "draw_layer",
+            |_, this, (mut layer, px, py): (mlua::UserDataRefMut<Layer>, f32, f32)| {
+                this.draw_layer(&mut layer, (px, py));
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut("snapshot_png", |lua, this, ()| {
+            let data = this.data().map_err(mlua::Error::runtime)?;
+            lua.create_string(data.as_bytes())
+        });
+
+        methods.add_method_mut(
+            "encode",
+            |lua, this, (format, quality): (Option<String>, Option<u32>)| {
+                let data = this
+                    .encode(format.as_deref().unwrap_or("png"), quality.unwrap_or(90))
+                    .map_err(mlua::Error::runtime)?;
+                lua.create_string(data.as_bytes())
+            },
+        );
+
+        methods.add_method_mut("save_png", |_, this, path: String| {
+            let data = this.data().map_err(mlua::Error::runtime)?;
+            fs::write(&path, data.as_bytes())
+                .map_err(|e| mlua::Error::runtime(format!("failed to write {path}: {e}")))
+        });
+
         methods.add_method_mut(
             "path_bezier_curve_to",
             |_, this, (cp1x, cp1y, cp2x, cp2y, px, py)| {
@@ -281,5 +1359,153 @@ impl<'a> UserData for Canvas<'a> {
             this.path_begin_from((px, py));
             Ok(())
         });
+
+        methods.add_method_mut("clear_path", |_, this, ()| {
+            this.clear_path();
+            Ok(())
+        });
+
+        // What `path_begin_from` did before `clear_path`, kept for configs relying on it.
+        methods.add_method_mut("path_begin", |_, this, ()| {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                log::warn!(
+                    "canvas:path_begin is deprecated, draw the path with draw_path_stroke or \
+                     draw_path_fill, then call clear_path"
+                )
+            });
+            #[allow(deprecated)]
+            this.path_begin();
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_each_format() {
+        let mut pixels = vec![0; 8 * 4 * 4];
+        let mut damage = Vec::new();
+        let mut canvas = Canvas::new(8, 4, &mut pixels, PixelFormat::Argb8888, &mut damage);
+        canvas.clear(Color::RED);
+
+        let png = canvas.data().unwrap();
+        assert!(png.as_bytes().starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        // Every JPEG starts with the start of image marker.
+        let jpeg = canvas.encode("jpeg", 80).unwrap();
+        assert!(jpeg.as_bytes().starts_with(&[0xFF, 0xD8]));
+
+        let error = canvas.encode("gif", 100).unwrap_err();
+        assert!(error.contains(r#""gif""#), "{error}");
+    }
+
+    #[test]
+    fn marks_only_the_drawn_region_dirty() {
+        let mut pixels = vec![0; 16 * 16 * 4];
+        let mut damage = Vec::new();
+        let mut canvas = Canvas::new(16, 16, &mut pixels, PixelFormat::Argb8888, &mut damage);
+        canvas.set_scale_factor(2.0);
+        canvas.draw_rect((1.0, 2.0), (3.0, 4.0));
+        canvas.mark_dirty((1, 2), (3, 4));
+        drop(canvas);
+        assert_eq!(damage, [IRect::from_xywh(2, 4, 6, 8)]);
+    }
+
+    /// Strokes a diagonal on an 8x8 canvas, with `between` run after the path is built, and
+    /// returns whether anything was drawn.
+    fn stroke_diagonal(between: impl FnOnce(&mut Canvas)) -> bool {
+        let mut pixels = vec![0; 8 * 8 * 4];
+        let mut damage = Vec::new();
+        let mut canvas = Canvas::new(8, 8, &mut pixels, PixelFormat::Argb8888, &mut damage);
+        canvas.set_stroke_width(2.0);
+        canvas.path_begin_from((0.0, 0.0));
+        canvas.path_line_to((8.0, 8.0));
+        between(&mut canvas);
+        canvas.draw_path_stroke();
+        drop(canvas);
+        pixels.iter().any(|&byte| byte != 0)
+    }
+
+    /// The pixel of a 1x1 canvas after running `body` with it as `canvas`, as BGRA.
+    fn draw_with(body: &str) -> mlua::Result<[u8; 4]> {
+        let lua = Lua::new();
+        crate::api::register(&lua)?;
+        let draw: mlua::Function = lua
+            .load(format!("return function(canvas) {body} end"))
+            .eval()?;
+
+        let mut pixels = vec![0; 4];
+        let mut damage = Vec::new();
+        let canvas = Canvas::new(1, 1, &mut pixels, PixelFormat::Argb8888, &mut damage);
+        lua.scope(|scope| draw.call::<()>(scope.create_userdata(canvas)?))?;
+        Ok(pixels.try_into().expect("4 bytes"))
+    }
+
+    /// The pixel a 1x1 canvas is filled with after `canvas:set_paint_colour(colour)`, as BGRA.
+    fn paint_with(colour: &str) -> mlua::Result<[u8; 4]> {
+        draw_with(&format!(
+            "canvas:set_paint_colour({colour}) canvas:draw_rect(0, 0, 1, 1)"
+        ))
+    }
+
+    #[test]
+    fn paint_colour_from_packed_or_named_colours() {
+        assert_eq!(
+            paint_with("colour(255, 0, 0, 255)").unwrap(),
+            [0, 0, 255, 255]
+        );
+        assert_eq!(paint_with("colour(0, 0, 255)").unwrap(), [255, 0, 0, 255]);
+        assert_eq!(paint_with("colours.green").unwrap(), [0, 255, 0, 255]);
+        assert_eq!(paint_with(r#""red""#).unwrap(), [0, 0, 255, 255]);
+        assert_eq!(paint_with(r##""#0000ff""##).unwrap(), [255, 0, 0, 255]);
+        assert!(paint_with(r#""crimson""#).is_err());
+    }
+
+    #[test]
+    fn colour_parameters_take_any_colour() {
+        assert_eq!(
+            draw_with(r#"canvas:clear("red")"#).unwrap(),
+            [0, 0, 255, 255]
+        );
+        assert_eq!(
+            draw_with(r##"canvas:clear("#0000ff")"##).unwrap(),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(
+            draw_with(r#"canvas:draw_rect_gradient(0, 0, 1, 1, { "green", colours.green })"#)
+                .unwrap(),
+            [0, 255, 0, 255]
+        );
+        assert!(draw_with(r#"canvas:draw_text_gradient(0, 1, "a", 8, "red", "blue")"#).is_ok());
+        assert!(draw_with(r#"canvas:draw_badge(0, 0, "1", 8, "white", "red")"#).is_ok());
+
+        assert!(draw_with(r#"canvas:clear("crimson")"#).is_err());
+        assert!(
+            draw_with(r#"canvas:draw_rect_gradient(0, 0, 1, 1, { "red", "crimson" })"#).is_err()
+        );
+        assert!(draw_with(r#"canvas:draw_badge(0, 0, "1", 8, "white", "crimson")"#).is_err());
+    }
+
+    #[test]
+    fn cleared_path_is_not_drawn() {
+        assert!(stroke_diagonal(|_| {}));
+        assert!(!stroke_diagonal(|canvas| canvas.clear_path()));
+        // Starting a path discards the old one too.
+        assert!(!stroke_diagonal(|canvas| canvas.path_begin_from((4.0, 4.0))));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn path_begin_draws_the_old_path() {
+        assert!(stroke_diagonal(|canvas| {
+            // path_begin draws with the paint as it is, and filling a line covers nothing.
+            canvas.set_paint_style(PaintStyle::Stroke);
+            canvas.path_begin();
+            canvas.clear_path();
+        }));
     }
 }
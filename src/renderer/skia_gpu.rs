@@ -0,0 +1,239 @@
+use std::ffi::c_void;
+
+use khronos_egl as egl;
+use skia_safe::{
+    ColorType, IRect, Surface,
+    gpu::{self, DirectContext, SurfaceOrigin, backend_render_targets, gl::FramebufferInfo},
+};
+use wayland_client::{Proxy, protocol::wl_surface::WlSurface};
+use wayland_egl::WlEglSurface;
+
+use super::skia_cpu::Canvas;
+
+const GL_RGBA8: u32 = 0x8058;
+
+/// Renders with Skia on OpenGL ES, presenting through an EGL window surface on the
+/// bar's `wl_surface`.
+pub struct GpuRenderer {
+    egl: egl::Instance<egl::Static>,
+    display: egl::Display,
+    context: egl::Context,
+    egl_surface: egl::Surface,
+    // Must outlive `egl_surface`, which draws into it.
+    window: WlEglSurface,
+    direct_context: DirectContext,
+    surface: Surface,
+}
+
+impl GpuRenderer {
+    /// `display` is the connection's `wl_display` pointer.
+    pub fn new(
+        display: *mut c_void,
+        wl_surface: &WlSurface,
+        size: (i32, i32),
+    ) -> Result<Self, String> {
+        let egl = egl::Instance::new(egl::Static);
+
+        let display = unsafe { egl.get_display(display) }
+            .ok_or_else(|| "no EGL display for the wayland connection".to_string())?;
+        egl.initialize(display)
+            .map_err(|e| format!("failed to initialise EGL: {e}"))?;
+        egl.bind_api(egl::OPENGL_ES_API)
+            .map_err(|e| format!("failed to bind OpenGL ES: {e}"))?;
+
+        let attributes = [
+            egl::RED_SIZE,
+            8,
+            egl::GREEN_SIZE,
+            8,
+            egl::BLUE_SIZE,
+            8,
+            egl::ALPHA_SIZE,
+            8,
+            egl::STENCIL_SIZE,
+            8,
+            egl::SURFACE_TYPE,
+            egl::WINDOW_BIT,
+            egl::RENDERABLE_TYPE,
+            egl::OPENGL_ES2_BIT,
+            egl::NONE,
+        ];
+        let config = egl
+            .choose_first_config(display, &attributes)
+            .map_err(|e| format!("failed to choose an EGL config: {e}"))?
+            .ok_or_else(|| "no EGL config supports RGBA8 windows".to_string())?;
+
+        let context = egl
+            .create_context(
+                display,
+                config,
+                None,
+                &[egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE],
+            )
+            .map_err(|e| format!("failed to create an EGL context: {e}"))?;
+
+        let window = WlEglSurface::new(wl_surface.id(), size.0, size.1)
+            .map_err(|e| format!("failed to create a wayland EGL window: {e}"))?;
+        let egl_surface = unsafe {
+            egl.create_window_surface(display, config, window.ptr() as egl::NativeWindowType, None)
+        }
+        .map_err(|e| format!("failed to create an EGL surface: {e}"))?;
+
+        egl.make_current(display, Some(egl_surface), Some(egl_surface), Some(context))
+            .map_err(|e| format!("failed to make the EGL context current: {e}"))?;
+        // Frames are paced by the compositor's frame callbacks, so swapping must not block.
+        egl.swap_interval(display, 0)
+            .map_err(|e| format!("failed to set the swap interval: {e}"))?;
+
+        let interface = gpu::gl::Interface::new_load_with(|name| {
+            egl.get_proc_address(name)
+                .map_or(std::ptr::null(), |f| f as *const c_void)
+        })
+        .ok_or_else(|| "failed to load OpenGL ES".to_string())?;
+        let mut direct_context = DirectContext::new_gl(interface, None)
+            .ok_or_else(|| "failed to create a Skia GL context".to_string())?;
+
+        let surface = wrap(&mut direct_context, size)?;
+
+        Ok(GpuRenderer {
+            egl,
+            display,
+            context,
+            egl_surface,
+            window,
+            direct_context,
+            surface,
+        })
+    }
+
+    /// Makes this renderer's context current, needed before drawing when there are several.
+    pub fn make_current(&self) -> Result<(), String> {
+        self.egl
+            .make_current(
+                self.display,
+                Some(self.egl_surface),
+                Some(self.egl_surface),
+                Some(self.context),
+            )
+            .map_err(|e| format!("failed to make the EGL context current: {e}"))
+    }
+
+    pub fn resize(&mut self, size: (i32, i32)) -> Result<(), String> {
+        if (self.surface.width(), self.surface.height()) == size {
+            return Ok(());
+        }
+
+        self.make_current()?;
+        self.window.resize(size.0, size.1, 0, 0);
+        self.surface = wrap(&mut self.direct_context, size)?;
+        Ok(())
+    }
+
+    pub fn canvas<'a>(&'a mut self, damage: &'a mut Vec<IRect>) -> Canvas<'a> {
+        Canvas::from_surface(&mut self.surface, damage)
+    }
+
+    /// Submits the frame and swaps it onto the surface, which attaches and commits it.
+    pub fn present(&mut self) -> Result<(), String> {
+        self.direct_context.flush_and_submit();
+        self.egl
+            .swap_buffers(self.display, self.egl_surface)
+            .map_err(|e| format!("failed to swap buffers: {e}"))
+    }
+}
+
+impl Drop for GpuRenderer {
+    fn drop(&mut self) {
+        // Skia's resources go while the context is current.
+        let _ = self.make_current();
+        self.direct_context.release_resources_and_abandon();
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_surface(self.display, self.egl_surface);
+        let _ = self.egl.destroy_context(self.display, self.context);
+    }
+}
+
+/// Wraps the window's default framebuffer in a Skia surface.
+fn wrap(context: &mut DirectContext, size: (i32, i32)) -> Result<Surface, String> {
+    let info = FramebufferInfo {
+        fboid: 0,
+        format: GL_RGBA8,
+        ..Default::default()
+    };
+    let target = backend_render_targets::make_gl(size, 0, 8, info);
+
+    gpu::surfaces::wrap_backend_render_target(
+        context,
+        &target,
+        SurfaceOrigin::BottomLeft,
+        ColorType::RGBA8888,
+        None,
+        None,
+    )
+    .ok_or_else(|| "failed to wrap the EGL framebuffer in a Skia surface".to_string())
+}
+
+#[cfg(all(test, feature = "gpu"))]
+mod tests {
+    use skia_safe::Color;
+    use wayland_client::{
+        Connection, Dispatch, QueueHandle, delegate_noop,
+        globals::{GlobalListContents, registry_queue_init},
+        protocol::{wl_compositor::WlCompositor, wl_registry::WlRegistry},
+    };
+
+    use super::*;
+
+    struct State;
+
+    impl Dispatch<WlRegistry, GlobalListContents> for State {
+        fn event(
+            _: &mut Self,
+            _: &WlRegistry,
+            _: <WlRegistry as Proxy>::Event,
+            _: &GlobalListContents,
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+        }
+    }
+
+    delegate_noop!(State: WlCompositor);
+    delegate_noop!(State: ignore WlSurface);
+
+    /// Draws on a surface with no role, skipped where there is no compositor or EGL display
+    /// to run on.
+    #[test]
+    fn clears_canvas() {
+        let Ok(conn) = Connection::connect_to_env() else {
+            eprintln!("skipped: no wayland display");
+            return;
+        };
+        let (globals, mut queue) = registry_queue_init::<State>(&conn).unwrap();
+        let qh = queue.handle();
+        let compositor: WlCompositor = globals.bind(&qh, 1..=4, ()).unwrap();
+        let surface = compositor.create_surface(&qh, ());
+        queue.roundtrip(&mut State).unwrap();
+
+        let display = conn.backend().display_ptr().cast();
+        let mut renderer = match GpuRenderer::new(display, &surface, (16, 16)) {
+            Ok(renderer) => renderer,
+            Err(e) if e.starts_with("no EGL display") || e.starts_with("failed to initialise") => {
+                eprintln!("skipped: {e}");
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+
+        let mut damage = Vec::new();
+        let mut canvas = renderer.canvas(&mut damage);
+        assert_eq!((canvas.width(), canvas.height()), (16, 16));
+        canvas.clear(Color::RED);
+        drop(canvas);
+        renderer.direct_context.flush_and_submit();
+
+        renderer.resize((32, 8)).unwrap();
+        let canvas = renderer.canvas(&mut damage);
+        assert_eq!((canvas.width(), canvas.height()), (32, 8));
+    }
+}
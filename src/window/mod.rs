@@ -1,20 +1,41 @@
-use std::str::FromStr;
+use std::{path::PathBuf, process::ExitCode, str::FromStr, time::Duration};
 
-use derive_more::{Display, FromStr};
-use mlua::{FromLua, Lua};
+use derive_more::Display;
+use mlua::{FromLua, IntoLua, Lua};
 use smithay_client_toolkit::{
     seat::{keyboard::KeyEvent, pointer::PointerEvent},
     shell::wlr_layer::Anchor,
 };
 
-use crate::widgets::Widget;
+use crate::{
+    api::{Callback, colour::Colour, process::Poller},
+    widgets::Widget,
+};
 
 pub mod wayland;
 
+/// Implements `FromLua` for an enum through its `FromStr`, so lua names a variant with a string
+/// and gets the parse error for any other value.
+macro_rules! from_lua_str {
+    ($ty:ty) => {
+        impl FromLua for $ty {
+            fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+                String::from_lua(value, lua)?
+                    .parse()
+                    .map_err(mlua::Error::runtime)
+            }
+        }
+    };
+}
+
 #[allow(dead_code, unused_variables)]
 pub trait Window {
-    fn new(opts: Opts, lua: Lua) -> Self;
-    fn run(&mut self);
+    /// Creates a bar for each of `bars`, which are all drawn from `lua`.
+    fn new(bars: Vec<Opts>, lua: Lua) -> Result<Self, String>
+    where
+        Self: Sized;
+    /// Runs until the bar exits, returning the status to exit the process with.
+    fn run(&mut self) -> ExitCode;
     fn exit(&mut self);
     // TODO
     fn set_height(&mut self, height: u32) {}
@@ -25,7 +46,10 @@ pub trait Window {
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Event {
+    // Events about a bar name it by its index in the list from `opts()`.
+    /// The compositor gave a bar a new size, in surface coordinates.
     Resized {
+        bar: usize,
         width: u32,
         height: u32,
     },
@@ -39,18 +63,38 @@ pub enum Event {
         modifiers: Modifiers,
     },
     PointerMoved {
+        bar: usize,
         x: f64,
         y: f64,
     },
     PointerEntered {
+        bar: usize,
         x: f64,
         y: f64,
     },
     PointerLeft,
+    /// A press and release of the same button without the pointer moving away.
+    Click {
+        bar: usize,
+        button: u32,
+        x: f64,
+        y: f64,
+        modifiers: Modifiers,
+    },
+    /// A second click of the same button soon after the first, sent after its `Click`.
+    DoubleClick {
+        bar: usize,
+        button: u32,
+        x: f64,
+        y: f64,
+        modifiers: Modifiers,
+    },
 
     KeyboardKeyPressed {
         key: KeyEvent,
         modifiers: Modifiers,
+        /// Re-emitted for a held key rather than pressed.
+        repeat: bool,
     },
     KeyboardKeyReleased {
         key: KeyEvent,
@@ -59,38 +103,376 @@ pub enum Event {
     KeyboardEntered,
     KeyboardLeft,
 
+    /// The user has been inactive for the bar's `idle_timeout`, so it stopped drawing.
+    Idle {
+        bar: usize,
+    },
+    /// The user is active again after `Idle`, and the bar draws again.
+    Resumed {
+        bar: usize,
+    },
+
+    AddInterval {
+        interval: Duration,
+        callback: Callback,
+    },
+    /// Runs the poller now and then every `interval`.
+    Poll {
+        interval: Duration,
+        poller: Poller,
+    },
+    /// Calls `callback` with the progress from 0 to 1 and redraws, every frame until `duration`
+    /// has passed.
+    Animate {
+        duration: Duration,
+        callback: Callback,
+    },
+    SetInputRegion {
+        bar: usize,
+        region: Option<(i32, i32, i32, i32)>,
+    },
+    SetKeyboardInteractivity {
+        bar: usize,
+        interactivity: KeyboardInteractivity,
+    },
+    SetVisible {
+        bar: usize,
+        visible: bool,
+    },
+    SetMargin {
+        bar: usize,
+        margin: Margin,
+    },
+    /// Moves a bar back past the edge it is anchored to, relative to its margin.
+    SlideOffset {
+        bar: usize,
+        offset: i32,
+    },
+    ShowTooltip {
+        bar: usize,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    HideTooltip,
+    SetCursor(CursorShape),
+    WatchFile {
+        path: PathBuf,
+        callback: Callback,
+    },
+    Redraw,
+    Reload,
+
     Exit,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Default)]
+/// Converts a key event into its xkb keysym name (e.g. `"Return"`, `"a"`) and the text it
+/// produced. Modifier-only presses yield empty strings for both.
+pub fn key_names(key: &KeyEvent) -> (String, String) {
+    if key.keysym.is_modifier_key() {
+        return (String::new(), String::new());
+    }
+
+    let name = key
+        .keysym
+        .name()
+        .map(|n| n.trim_start_matches("XK_").to_string())
+        .unwrap_or_default();
+    let text = key
+        .utf8
+        .clone()
+        .or_else(|| key.keysym.key_char().map(String::from))
+        .unwrap_or_default();
+
+    (name, text)
+}
+
+/// Names a linux button code, e.g. `"left"` for `BTN_LEFT`, or `"button<code>"` for the rest.
+pub fn button_name(button: u32) -> String {
+    match button {
+        0x110 => "left".to_string(),
+        0x111 => "right".to_string(),
+        0x112 => "middle".to_string(),
+        0x113 => "side".to_string(),
+        0x114 => "extra".to_string(),
+        _ => format!("button{button}"),
+    }
+}
+
+/// The modifier keys held, passed to lua as `{ ctrl, shift, alt, meta }`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Modifiers {
-    control: bool,
-    shift: bool,
-    alt: bool,
-    meta: bool,
+    pub control: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub meta: bool,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, Display, FromStr)]
+impl IntoLua for Modifiers {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        let t = lua.create_table()?;
+        t.set("ctrl", self.control)?;
+        t.set("shift", self.shift)?;
+        t.set("alt", self.alt)?;
+        t.set("meta", self.meta)?;
+        Ok(mlua::Value::Table(t))
+    }
+}
+
+impl FromLua for Modifiers {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        let t = mlua::Table::from_lua(value, lua)?;
+        let held = |name: &str| t.get::<Option<bool>>(name).map(Option::unwrap_or_default);
+        Ok(Modifiers {
+            control: held("ctrl")?,
+            shift: held("shift")?,
+            alt: held("alt")?,
+            meta: held("meta")?,
+        })
+    }
+}
+
+/// A connected monitor, as returned by `outputs()` in lua.
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Resolution of the current mode in pixels.
+    pub width: i32,
+    pub height: i32,
+    pub scale: i32,
+    /// How the output is rotated or flipped, "normal" when it is not.
+    pub transform: &'static str,
+    /// The layout of the output's subpixels, "unknown" when it does not say.
+    pub subpixel: &'static str,
+    /// Position in the compositor's logical space.
+    pub x: i32,
+    pub y: i32,
+}
+
+impl IntoLua for Output {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<mlua::Value> {
+        let t = lua.create_table()?;
+        t.set("name", self.name)?;
+        t.set("description", self.description)?;
+        t.set("width", self.width)?;
+        t.set("height", self.height)?;
+        t.set("scale", self.scale)?;
+        t.set("transform", self.transform)?;
+        t.set("subpixel", self.subpixel)?;
+        t.set("x", self.x)?;
+        t.set("y", self.y)?;
+        Ok(mlua::Value::Table(t))
+    }
+}
+
+/// The layer-shell layer the bar is placed on, from bottom to top.
+#[derive(Debug, Clone, Copy, Display)]
 #[display(rename_all = "lowercase")]
 pub enum Layer {
     Background,
     Bottom,
-    Overlay,
     Top,
+    Overlay,
+}
+
+impl FromStr for Layer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "background" => Ok(Self::Background),
+            "bottom" => Ok(Self::Bottom),
+            "top" => Ok(Self::Top),
+            "overlay" => Ok(Self::Overlay),
+            _ => Err(format!(
+                r#"unknown layer "{s}", expected "background", "bottom", "top" or "overlay""#
+            )),
+        }
+    }
+}
+
+from_lua_str!(Layer);
+
+/// Whether the bar takes keyboard focus.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum KeyboardInteractivity {
+    /// Never receive keyboard input.
+    None,
+    /// Take keyboard focus exclusively while mapped.
+    Exclusive,
+    /// Receive focus when the user selects the bar.
+    #[default]
+    OnDemand,
+}
+
+impl FromStr for KeyboardInteractivity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "exclusive" => Ok(Self::Exclusive),
+            "on_demand" => Ok(Self::OnDemand),
+            _ => Err(format!(
+                r#"unknown keyboard interactivity "{s}", expected "none", "exclusive" or "on_demand""#
+            )),
+        }
+    }
+}
+
+from_lua_str!(KeyboardInteractivity);
+
+/// The cursor shown while the pointer is over a bar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Default,
+    /// A hand, for things that can be clicked.
+    Pointer,
+    Text,
+}
+
+impl FromStr for CursorShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "pointer" => Ok(Self::Pointer),
+            "text" => Ok(Self::Text),
+            _ => Err(format!(
+                r#"unknown cursor "{s}", expected "default", "pointer" or "text""#
+            )),
+        }
+    }
+}
+
+from_lua_str!(CursorShape);
+
+/// What the bar is rendered with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Skia on the CPU into shared memory buffers.
+    #[default]
+    Cpu,
+    /// Skia on OpenGL ES through EGL, needs the `gpu` feature.
+    Gpu,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(Self::Cpu),
+            "gpu" => Ok(Self::Gpu),
+            _ => Err(format!(r#"unknown backend "{s}", expected "cpu" or "gpu""#)),
+        }
+    }
+}
+
+from_lua_str!(Backend);
+
+/// How the exclusive zone of a bar is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Exclusive {
+    /// `Opts::exclusive_zone` as given.
+    #[default]
+    Fixed,
+    /// The bar's height when anchored to the top or bottom, or its width when anchored to the
+    /// left or right, following the bar as it is resized.
+    Auto,
+}
+
+impl FromStr for Exclusive {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!(
+                r#"unknown exclusive mode "{s}", expected "fixed" or "auto""#
+            )),
+        }
+    }
+}
+
+from_lua_str!(Exclusive);
+
+/// The layout of a bar's shared memory buffers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Premultiplied alpha, letting the bar be translucent.
+    #[default]
+    Argb8888,
+    /// The alpha byte is ignored, for opaque bars.
+    Xrgb8888,
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "argb8888" => Ok(Self::Argb8888),
+            "xrgb8888" => Ok(Self::Xrgb8888),
+            _ => Err(format!(
+                r#"unknown pixel format "{s}", expected "argb8888" or "xrgb8888""#
+            )),
+        }
+    }
 }
 
+from_lua_str!(PixelFormat);
+
+/// The namespace of a bar whose config leaves it unset or blank.
+pub const DEFAULT_NAMESPACE: &str = "status-bar";
+
+/// The largest width or height of a bar or tooltip, which keeps its buffers to a size that can
+/// be allocated.
+pub const MAX_SIZE: u32 = 8192;
+
 #[derive(Debug, Clone)]
 pub struct Opts {
     pub width: u32,
     pub height: u32,
     pub exclusive_zone: i32,
+    /// Whether `exclusive_zone` is used or worked out from the bar's size.
+    pub exclusive: Exclusive,
     pub layer: Layer,
     // TODO use custom enum for anchor
     pub anchor: Option<Anchor>,
     pub margin: Margin,
-    pub namespace: Option<String>,
+    /// Identifies the bar to the compositor, for rules that match on it. Fixed once the
+    /// surface is created, so a reload cannot change it.
+    pub namespace: String,
+    /// Draw the debug fps counter over the bar.
+    pub show_fps: bool,
+    /// Colour the bar is cleared to before each draw, transparent when unset.
+    pub background_colour: Option<u32>,
+    /// Of the bar's buffers when drawn on the CPU.
+    pub pixel_format: PixelFormat,
+    /// Let pointer input pass through the bar to the windows below.
+    pub input_passthrough: bool,
+    pub keyboard_interactivity: KeyboardInteractivity,
+    /// Caps how often the bar is drawn, 0 draws every requested frame.
+    pub max_fps: u32,
+    pub backend: Backend,
+    /// Fill the output's width, ignoring `width`. Needs the bar anchored left and right.
+    pub stretch: bool,
+    /// The global lua function that draws the bar.
+    pub draw: String,
+    /// Seconds without input before the bar stops drawing until there is input again, 0 to
+    /// keep drawing.
+    pub idle_timeout: u32,
+    /// Repeat `on_key` for held keys, at the rate the seat asks for.
+    pub key_repeat: bool,
+    /// Milliseconds the draw function may run before it is interrupted with an error, 0 to let
+    /// it run.
+    pub draw_timeout: u32,
     // pub widgets: Vec<Widget>,
 }
 
@@ -100,38 +482,144 @@ impl Default for Opts {
             width: 100,
             height: 100,
             exclusive_zone: -1,
+            exclusive: Exclusive::default(),
             layer: Layer::Bottom,
             anchor: Some(Anchor::TOP),
-            namespace: None,
+            namespace: DEFAULT_NAMESPACE.to_string(),
             margin: Margin::default(),
+            show_fps: false,
+            background_colour: None,
+            pixel_format: PixelFormat::default(),
+            input_passthrough: false,
+            keyboard_interactivity: KeyboardInteractivity::default(),
+            max_fps: 0,
+            backend: Backend::default(),
+            stretch: false,
+            draw: "draw".to_string(),
+            idle_timeout: 0,
+            key_repeat: false,
+            draw_timeout: 1000,
             // widgets: Vec::new(),
         }
     }
 }
 
+impl Opts {
+    /// The size to ask the compositor for. A width of 0 has it fill in the output's width.
+    pub fn surface_size(&self) -> (u32, u32) {
+        let width = if self.stretch { 0 } else { self.width };
+        (width, self.height)
+    }
+
+    /// Reads the result of `opts()`, either a single bar's table or a list of them.
+    pub fn list(value: mlua::Value, lua: &Lua) -> mlua::Result<Vec<Opts>> {
+        let mlua::Value::Table(t) = value else {
+            return Opts::from_lua(value, lua).map(|opts| vec![opts]);
+        };
+        if t.raw_len() == 0 {
+            return Opts::try_from(t).map(|opts| vec![opts]);
+        }
+
+        t.sequence_values::<mlua::Value>()
+            .enumerate()
+            .map(|(i, value)| {
+                value
+                    .and_then(|v| Opts::from_lua(v, lua))
+                    .map_err(|e| prefix_error(format!("bar {}", i + 1), e))
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<mlua::Table> for Opts {
+    type Error = mlua::Error;
+
+    /// Reads the table returned by `opts()`, using the defaults for missing fields.
+    fn try_from(t: mlua::Table) -> mlua::Result<Self> {
+        let default = Opts::default();
+
+        let anchor = match field::<u32>(&t, "anchor")? {
+            Some(bits) => Some(Anchor::from_bits(bits).ok_or_else(|| {
+                mlua::Error::runtime(format!("opts.anchor: {bits} is not a valid anchor"))
+            })?),
+            None => default.anchor,
+        };
+
+        let stretch = field(&t, "stretch")?.unwrap_or(default.stretch);
+        if stretch && !anchor.is_some_and(|a| a.contains(Anchor::LEFT | Anchor::RIGHT)) {
+            return Err(mlua::Error::runtime(
+                "opts.stretch: the bar must be anchored to both left and right",
+            ));
+        }
+
+        Ok(Opts {
+            width: size_field(&t, "width", default.width)?,
+            height: size_field(&t, "height", default.height)?,
+            exclusive_zone: field(&t, "exclusive_zone")?.unwrap_or(default.exclusive_zone),
+            exclusive: field(&t, "exclusive")?.unwrap_or(default.exclusive),
+            layer: field(&t, "layer")?.unwrap_or(default.layer),
+            anchor,
+            margin: field(&t, "margin")?.unwrap_or(default.margin),
+            namespace: field::<String>(&t, "namespace")?
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .unwrap_or(default.namespace),
+            show_fps: field(&t, "show_fps")?.unwrap_or(default.show_fps),
+            background_colour: field::<Colour>(&t, "background_colour")?.map(|c| c.0),
+            pixel_format: field(&t, "pixel_format")?.unwrap_or(default.pixel_format),
+            input_passthrough: field(&t, "input_passthrough")?.unwrap_or(default.input_passthrough),
+            keyboard_interactivity: field(&t, "keyboard_interactivity")?
+                .unwrap_or(default.keyboard_interactivity),
+            max_fps: field(&t, "max_fps")?.unwrap_or(default.max_fps),
+            backend: field(&t, "backend")?.unwrap_or(default.backend),
+            stretch,
+            draw: field(&t, "draw")?.unwrap_or(default.draw),
+            idle_timeout: field(&t, "idle_timeout")?.unwrap_or(default.idle_timeout),
+            key_repeat: field(&t, "key_repeat")?.unwrap_or(default.key_repeat),
+            draw_timeout: field(&t, "draw_timeout")?.unwrap_or(default.draw_timeout),
+            // widgets: Vec::new(),
+        })
+    }
+}
+
 impl FromLua for Opts {
     fn from_lua(value: mlua::Value, _: &Lua) -> mlua::Result<Self> {
-        if let mlua::Value::Table(t) = value {
-            let bits = t.get::<Option<u32>>("anchor").unwrap();
-            let anchor = bits.and_then(Anchor::from_bits);
-
-            return Ok(Opts {
-                width: t.get("width").expect("a window must have a set width"),
-                height: t.get("height").expect("a window must have a set height"),
-                layer: match t.get::<String>("layer") {
-                    Ok(s) => Layer::from_str(&s).expect("unexpected layer type"),
-                    Err(_) => panic!("a window must have a set layer"),
-                },
-                anchor,
-                namespace: t.get("namespace")?,
-                margin: t.get::<Option<Margin>>("margin")?.unwrap_or_default(),
-                exclusive_zone: t.get::<Option<i32>>("exclusive_zone")?.unwrap_or(-1),
-                // widgets: Vec::new(),
-            });
+        match value {
+            mlua::Value::Table(t) => Opts::try_from(t),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "WindowOpts".to_string(),
+                message: Some("opts() must return a table".to_string()),
+            }),
         }
+    }
+}
 
-        panic!("WindowOpts is not a table")
+/// Gets an optional field of the opts table, naming the field if it has the wrong type.
+fn field<T: FromLua>(t: &mlua::Table, name: &str) -> mlua::Result<Option<T>> {
+    t.get::<Option<T>>(name)
+        .map_err(|e| prefix_error(format!("opts.{name}"), e))
+}
+
+/// Prefixes `e` with `context`, unwrapping runtime errors so the message is only labelled as
+/// a runtime error once.
+fn prefix_error(context: String, e: mlua::Error) -> mlua::Error {
+    let message = match e {
+        mlua::Error::RuntimeError(message) => message,
+        e => e.to_string(),
+    };
+    mlua::Error::runtime(format!("{context}: {message}"))
+}
+
+/// Reads a width or height, rejecting sizes too large to allocate buffers for.
+fn size_field(t: &mlua::Table, name: &str, default: u32) -> mlua::Result<u32> {
+    let size = field(t, name)?.unwrap_or(default);
+    if size > MAX_SIZE {
+        return Err(mlua::Error::runtime(format!(
+            "opts.{name}: {size} is larger than the maximum of {MAX_SIZE}"
+        )));
     }
+    Ok(size)
 }
 
 // TODO lua
@@ -162,32 +650,214 @@ impl From<Margin> for (i32, i32, i32, i32) {
 
 // Maybe add the ability to use css like syntax
 impl FromLua for Margin {
+    /// Accepts `{ top = 4, left = 8 }`, with unset sides 0, or `{ top, right, bottom, left }`.
     fn from_lua(value: mlua::Value, _: &Lua) -> mlua::Result<Self> {
         if let mlua::Value::Table(t) = value {
-            return Ok(Margin {
-                top: t.get("top").unwrap_or_default(),
-                right: t.get("right").unwrap_or_default(),
-                bottom: t.get("bottom").unwrap_or_default(),
-                left: t.get("left").unwrap_or_default(),
-            });
+            if t.raw_len() == 0 {
+                return Ok(Margin {
+                    top: t.get::<Option<i32>>("top")?.unwrap_or_default(),
+                    right: t.get::<Option<i32>>("right")?.unwrap_or_default(),
+                    bottom: t.get::<Option<i32>>("bottom")?.unwrap_or_default(),
+                    left: t.get::<Option<i32>>("left")?.unwrap_or_default(),
+                });
+            }
+
+            let sides = t
+                .sequence_values::<i32>()
+                .collect::<mlua::Result<Vec<_>>>()?;
+            return match sides[..] {
+                [top, right, bottom, left] => Ok(Margin::from((top, right, bottom, left))),
+                _ => Err(mlua::Error::runtime(format!(
+                    "a margin list needs 4 sides (top, right, bottom, left), got {}",
+                    sides.len()
+                ))),
+            };
         }
 
-        // TODO
         Err(mlua::Error::FromLuaConversionError {
             from: value.type_name(),
             to: "Margin".to_string(),
             message: Some(
-                r#"
-                    cannot convert type to Margin
-                    expected { 
-                      top? = number, 
-                      right? = number, 
-                      bottom? = number,
-                      left? = number,
-                    }
-                "#
-                .to_string(),
+                "expected { top?, right?, bottom?, left? } or { top, right, bottom, left }"
+                    .to_string(),
             ),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay_client_toolkit::seat::keyboard::Keysym;
+
+    fn eval<T: FromLua>(lua: &Lua, source: &str) -> mlua::Result<T> {
+        lua.load(source).eval()
+    }
+
+    fn key(keysym: Keysym, utf8: Option<&str>) -> KeyEvent {
+        KeyEvent {
+            time: 0,
+            raw_code: 0,
+            keysym,
+            utf8: utf8.map(String::from),
+        }
+    }
+
+    #[test]
+    fn names_keys_by_keysym() {
+        assert_eq!(
+            key_names(&key(Keysym::a, Some("a"))),
+            ("a".to_string(), "a".to_string())
+        );
+        assert_eq!(
+            key_names(&key(Keysym::A, Some("A"))),
+            ("A".to_string(), "A".to_string())
+        );
+        assert_eq!(
+            key_names(&key(Keysym::a, None)),
+            ("a".to_string(), "a".to_string())
+        );
+        assert_eq!(key_names(&key(Keysym::Return, None)).0, "Return");
+        assert_eq!(
+            key_names(&key(Keysym::Shift_L, None)),
+            (String::new(), String::new())
+        );
+    }
+
+    #[test]
+    fn parses_enums_from_strings() {
+        assert!(matches!("overlay".parse::<Layer>(), Ok(Layer::Overlay)));
+        assert!(matches!(
+            "on_demand".parse::<KeyboardInteractivity>(),
+            Ok(KeyboardInteractivity::OnDemand)
+        ));
+        assert_eq!("pointer".parse::<CursorShape>(), Ok(CursorShape::Pointer));
+        assert_eq!("gpu".parse::<Backend>(), Ok(Backend::Gpu));
+        assert_eq!("auto".parse::<Exclusive>(), Ok(Exclusive::Auto));
+        assert_eq!("xrgb8888".parse::<PixelFormat>(), Ok(PixelFormat::Xrgb8888));
+    }
+
+    #[test]
+    fn rejects_unknown_enum_values() {
+        assert_eq!(
+            "middle".parse::<Layer>().unwrap_err(),
+            r#"unknown layer "middle", expected "background", "bottom", "top" or "overlay""#
+        );
+        assert!("Top".parse::<Layer>().is_err());
+        assert!("ondemand".parse::<KeyboardInteractivity>().is_err());
+        assert!("hand".parse::<CursorShape>().is_err());
+        assert!("vulkan".parse::<Backend>().is_err());
+        assert!("".parse::<Exclusive>().is_err());
+        assert!("rgb565".parse::<PixelFormat>().is_err());
+    }
+
+    #[test]
+    fn reads_enums_from_lua() {
+        let lua = Lua::new();
+        assert!(matches!(eval::<Layer>(&lua, r#""top""#), Ok(Layer::Top)));
+        assert!(matches!(
+            eval::<KeyboardInteractivity>(&lua, r#""none""#),
+            Ok(KeyboardInteractivity::None)
+        ));
+        assert_eq!(
+            eval::<CursorShape>(&lua, r#""text""#).unwrap(),
+            CursorShape::Text
+        );
+        assert_eq!(eval::<Backend>(&lua, r#""cpu""#).unwrap(), Backend::Cpu);
+        assert_eq!(
+            eval::<Exclusive>(&lua, r#""fixed""#).unwrap(),
+            Exclusive::Fixed
+        );
+        assert_eq!(
+            eval::<PixelFormat>(&lua, r#""argb8888""#).unwrap(),
+            PixelFormat::Argb8888
+        );
+
+        assert!(eval::<Layer>(&lua, r#""side""#).is_err());
+        assert!(eval::<Backend>(&lua, "{}").is_err());
+    }
+
+    #[test]
+    fn reads_margin_by_side_or_list() {
+        let lua = Lua::new();
+        let margin = eval::<Margin>(&lua, "{ top = 4, left = 8 }").unwrap();
+        assert_eq!(<(i32, i32, i32, i32)>::from(margin), (4, 0, 0, 8));
+
+        let margin = eval::<Margin>(&lua, "{ 1, 2, 3, 4 }").unwrap();
+        assert_eq!(<(i32, i32, i32, i32)>::from(margin), (1, 2, 3, 4));
+
+        let margin = eval::<Margin>(&lua, "{}").unwrap();
+        assert_eq!(<(i32, i32, i32, i32)>::from(margin), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn rejects_oversized_bars() {
+        let lua = Lua::new();
+        let opts = |source: &str| Opts::list(lua.load(source).eval().unwrap(), &lua);
+
+        let bars = opts(&format!("{{ width = {MAX_SIZE}, height = {MAX_SIZE} }}")).unwrap();
+        assert_eq!((bars[0].width, bars[0].height), (MAX_SIZE, MAX_SIZE));
+
+        let error = opts(&format!("{{ width = {} }}", MAX_SIZE + 1)).unwrap_err();
+        assert!(error.to_string().contains("opts.width"), "{error}");
+        let error = opts(&format!("{{ {{}}, {{ height = {} }} }}", MAX_SIZE + 1)).unwrap_err();
+        assert!(error.to_string().contains("bar 2: opts.height"), "{error}");
+        assert_eq!(
+            error.to_string().matches("runtime error").count(),
+            1,
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn names_the_field_with_a_bad_value() {
+        let lua = Lua::new();
+        let opts = |source: &str| Opts::try_from(lua.load(source).eval::<mlua::Table>().unwrap());
+
+        let error = opts(r#"{ height = "tall" }"#).unwrap_err().to_string();
+        assert!(error.starts_with("runtime error: opts.height: "), "{error}");
+
+        let error = opts(r#"{ layer = "sideways" }"#).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            r#"runtime error: opts.layer: unknown layer "sideways", expected "background", "bottom", "top" or "overlay""#
+        );
+    }
+
+    #[test]
+    fn reads_one_or_several_bars() {
+        let lua = Lua::new();
+        let opts = |source: &str| Opts::list(lua.load(source).eval().unwrap(), &lua);
+
+        let bars = opts("{ height = 20 }").unwrap();
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].height, 20);
+
+        let bars = opts("{ { height = 20 }, { height = 30 }, {} }").unwrap();
+        let heights: Vec<u32> = bars.iter().map(|bar| bar.height).collect();
+        assert_eq!(heights, [20, 30, Opts::default().height]);
+
+        let error = opts(r#"{ {}, {}, { layer = "side" } }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("runtime error: bar 3: opts.layer: unknown layer"),
+            "{error}"
+        );
+        assert!(
+            opts("{ {}, 4 }")
+                .unwrap_err()
+                .to_string()
+                .contains("bar 2: ")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_margins() {
+        let lua = Lua::new();
+        assert!(eval::<Margin>(&lua, "{ 1, 2, 3 }").is_err());
+        assert!(eval::<Margin>(&lua, "{ 1, 2, 3, 4, 5 }").is_err());
+        assert!(eval::<Margin>(&lua, r#"{ top = "wide" }"#).is_err());
+        assert!(eval::<Margin>(&lua, "4").is_err());
+    }
+}
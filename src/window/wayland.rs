@@ -1,18 +1,56 @@
-use std::{convert::TryInto, num::NonZeroU32, time::Instant};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant, SystemTime},
+};
 
-use mlua::Lua;
+use calloop::{
+    LoopHandle, RegistrationToken, channel,
+    signals::{Signal, Signals},
+    timer::{TimeoutAction, Timer},
+};
+use mlua::{HookTriggers, IntoLuaMulti, Lua, VmState};
+use skia_safe::IRect;
 use smithay_client_toolkit::{
-    compositor::{CompositorHandler, CompositorState},
+    compositor::{CompositorHandler, CompositorState, Region},
     delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
-    delegate_registry, delegate_seat, delegate_shm,
-    output::{OutputHandler, OutputState},
-    reexports::calloop_wayland_source::WaylandSource,
+    delegate_registry, delegate_seat, delegate_shm, delegate_xdg_popup,
+    error::GlobalError,
+    globals::{GlobalData, ProvidesBoundGlobal},
+    output::{OutputHandler, OutputInfo, OutputState},
+    reexports::{
+        calloop_wayland_source::WaylandSource,
+        client::delegate_dispatch,
+        protocols::{
+            ext::idle_notify::v1::client::{
+                ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+                ext_idle_notifier_v1::ExtIdleNotifierV1,
+            },
+            wp::{
+                cursor_shape::v1::client::wp_cursor_shape_device_v1::{
+                    Shape, WpCursorShapeDeviceV1,
+                },
+                fractional_scale::v1::client::{
+                    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+                    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+                },
+                viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+            },
+            xdg::shell::client::{xdg_positioner, xdg_wm_base},
+        },
+    },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
         Capability, SeatHandler, SeatState,
-        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
-        pointer::{PointerEvent, PointerEventKind, PointerHandler},
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers, RepeatInfo},
+        pointer::{
+            PointerEvent, PointerEventKind, PointerHandler, cursor_shape::CursorShapeManager,
+        },
     },
     shell::{
         WaylandSurface,
@@ -20,24 +58,84 @@ use smithay_client_toolkit::{
             Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
             LayerSurfaceConfigure,
         },
+        xdg::{
+            XdgPositioner, XdgShell,
+            popup::{Popup, PopupConfigure, PopupHandler},
+        },
+    },
+    shm::{
+        Shm, ShmHandler,
+        slot::{Buffer, SlotPool},
     },
-    shm::{Shm, ShmHandler, slot::SlotPool},
 };
 use wayland_client::{
-    Connection, QueueHandle,
+    Connection, Dispatch, Proxy, QueueHandle, delegate_noop,
     globals::registry_queue_init,
     protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
 };
 
-use crate::window::{Event, Margin, Opts};
+#[cfg(feature = "gpu")]
+use crate::renderer::skia_gpu::GpuRenderer;
+use crate::{
+    api, config,
+    ipc::{self, Command},
+    renderer::skia_cpu::Canvas,
+    window::{Backend, CursorShape, Event, Exclusive, MAX_SIZE, Margin, Opts, Output, PixelFormat},
+};
+
+/// How often the config file is checked for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(5);
+/// How often files watched from lua are checked for changes.
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often `animate` advances, about once a frame at 60Hz.
+const ANIMATION_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// Longest press in milliseconds that still counts as a click.
+const CLICK_TIME: u32 = 500;
+/// Longest gap in milliseconds between the clicks of a double click.
+const DOUBLE_CLICK_TIME: u32 = 400;
+/// How far in pixels the pointer may move between a press and its release, or between the
+/// clicks of a double click.
+const CLICK_DISTANCE: f64 = 8.0;
+/// Frames of damage kept to bring a reused buffer up to date, older buffers are redrawn in full.
+const MAX_BUFFER_AGE: usize = 4;
+/// Lua instructions run between checks of `Opts::draw_timeout`.
+const DRAW_TIMEOUT_CHECK_INSTRUCTIONS: u32 = 10_000;
+/// Frames whose draw times `perf_stats()` summarises.
+const FRAME_TIMES_KEPT: usize = 120;
 
 #[allow(dead_code)]
 pub struct SimpleLayer {
     state: LayerState,
-    layer: Layer,
-    anchor: Option<Anchor>,
-    margin: Margin,
     event_loop: calloop::EventLoop<'static, LayerState>,
+    qh: QueueHandle<LayerState>,
+    config_path: Option<PathBuf>,
+    /// The ipc socket, removed on exit.
+    ipc_path: Option<PathBuf>,
+    /// Event sources calling into lua, removed when the config is reloaded.
+    lua_sources: Vec<RegistrationToken>,
+    /// The raw code of the key being repeated and the timer repeating it.
+    key_repeat: Option<(u32, RegistrationToken)>,
+}
+
+impl From<super::KeyboardInteractivity> for KeyboardInteractivity {
+    fn from(val: super::KeyboardInteractivity) -> Self {
+        match val {
+            super::KeyboardInteractivity::None => KeyboardInteractivity::None,
+            super::KeyboardInteractivity::Exclusive => KeyboardInteractivity::Exclusive,
+            super::KeyboardInteractivity::OnDemand => KeyboardInteractivity::OnDemand,
+        }
+    }
+}
+
+impl From<CursorShape> for Shape {
+    fn from(val: CursorShape) -> Self {
+        match val {
+            CursorShape::Default => Shape::Default,
+            CursorShape::Pointer => Shape::Pointer,
+            CursorShape::Text => Shape::Text,
+        }
+    }
 }
 
 impl From<super::Layer> for Layer {
@@ -51,69 +149,630 @@ impl From<super::Layer> for Layer {
     }
 }
 
+impl From<PixelFormat> for wl_shm::Format {
+    fn from(format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::Argb8888 => wl_shm::Format::Argb8888,
+            PixelFormat::Xrgb8888 => wl_shm::Format::Xrgb8888,
+        }
+    }
+}
+
+impl From<Modifiers> for super::Modifiers {
+    fn from(modifiers: Modifiers) -> Self {
+        super::Modifiers {
+            control: modifiers.ctrl,
+            shift: modifiers.shift,
+            alt: modifiers.alt,
+            meta: modifiers.logo,
+        }
+    }
+}
+
+impl From<OutputInfo> for Output {
+    fn from(info: OutputInfo) -> Self {
+        let (width, height) = info
+            .modes
+            .iter()
+            .find(|m| m.current)
+            .map_or((0, 0), |m| m.dimensions);
+        let (x, y) = info.logical_position.unwrap_or(info.location);
+
+        Output {
+            name: info.name,
+            description: info.description,
+            width,
+            height,
+            scale: info.scale_factor,
+            transform: transform_name(info.transform),
+            subpixel: subpixel_name(info.subpixel),
+            x,
+            y,
+        }
+    }
+}
+
+fn transform_name(transform: wl_output::Transform) -> &'static str {
+    match transform {
+        wl_output::Transform::_90 => "90",
+        wl_output::Transform::_180 => "180",
+        wl_output::Transform::_270 => "270",
+        wl_output::Transform::Flipped => "flipped",
+        wl_output::Transform::Flipped90 => "flipped-90",
+        wl_output::Transform::Flipped180 => "flipped-180",
+        wl_output::Transform::Flipped270 => "flipped-270",
+        _ => "normal",
+    }
+}
+
+fn subpixel_name(subpixel: wl_output::Subpixel) -> &'static str {
+    match subpixel {
+        wl_output::Subpixel::None => "none",
+        wl_output::Subpixel::HorizontalRgb => "horizontal-rgb",
+        wl_output::Subpixel::HorizontalBgr => "horizontal-bgr",
+        wl_output::Subpixel::VerticalRgb => "vertical-rgb",
+        wl_output::Subpixel::VerticalBgr => "vertical-bgr",
+        _ => "unknown",
+    }
+}
+
 #[allow(dead_code)]
 struct LayerState {
     should_exit: bool,
-    first_configure: bool,
-    width: u32,
-    height: u32,
-    exclusive_zone: i32,
+    /// One per entry of `opts()`, in order. Lua refers to them by their position from 1.
+    bars: Vec<Bar>,
+    /// Kept for creating bars after startup, such as when a reload adds one.
+    connection: Connection,
+    compositor: CompositorState,
+    layer_shell: LayerShell,
+    /// Unset when the compositor lacks fractional scaling or viewports, which leaves the bars
+    /// with integer buffer scales.
+    fractional_scale_manager: Option<(WpFractionalScaleManagerV1, WpViewporter)>,
+    /// Unset when the compositor cannot tell us the user is idle, so bars draw regardless.
+    idle_notifier: Option<ExtIdleNotifierV1>,
     shm: Shm,
+    /// Shared by the buffers of every bar and the tooltip, it grows as they need.
     pool: SlotPool,
-    layer: LayerSurface,
-    pointer: Option<wl_pointer::WlPointer>,
-    keyboard: Option<wl_keyboard::WlKeyboard>,
-    keyboard_focus: bool,
+    /// Unset when the compositor has no xdg shell, which leaves tooltips unavailable.
+    wm_base: Option<WmBase>,
+    tooltip: Option<Tooltip>,
+    /// The input devices of every seat, released along with their capability or seat.
+    seats: HashMap<wl_seat::WlSeat, Seat>,
+    pointer_position: api::Pointer,
+    /// Unset when the compositor cannot set cursors by name, which leaves `set_cursor` without
+    /// effect.
+    cursor_shape_manager: Option<CursorShapeManager>,
+    /// Chosen by lua, back to the default whenever every pointer has left the bars.
+    cursor: CursorShape,
+    /// The bar with keyboard focus.
+    keyboard_focus: Option<usize>,
+    /// How held keys repeat, as the seat last told us.
+    repeat_info: RepeatInfo,
     registry_state: RegistryState,
     seat_state: SeatState,
     output_state: OutputState,
     events: Vec<Event>,
     dispatched_events: bool,
     modifiers: crate::window::Modifiers,
-    last_frame: Instant,
+    /// The button held down, for recognising clicks on its release.
+    press: Option<Click>,
+    /// The last click, for recognising a double click.
+    last_click: Option<Click>,
+    draw_errors: ErrorLog,
+    /// How long the last `FRAME_TIMES_KEPT` frames took to draw, oldest first.
+    frame_times: VecDeque<Duration>,
+    /// When the bar started, for `uptime()`.
+    started: Instant,
     lua: Lua,
 }
 
-impl crate::Window for SimpleLayer {
-    fn new(opts: Opts, lua: Lua) -> Self {
-        env_logger::init();
+/// A layer surface made from one entry of `opts()`, with the buffers it is drawn to.
+struct Bar {
+    layer: LayerSurface,
+    /// The global lua function that draws the bar, from `Opts::draw`.
+    draw: String,
+    /// When the bar may next be drawn.
+    schedule: Schedule,
+    width: u32,
+    height: u32,
+    /// The size from `Opts`, used for any side the compositor leaves to us.
+    requested_size: (u32, u32),
+    /// Set when `Opts::exclusive` is auto, to the edges the bar is anchored to.
+    auto_exclusive: Option<Anchor>,
+    anchor: Anchor,
+    /// From `Opts` or the latest `set_margin`, which `slide_offset` slides the bar from.
+    margin: Margin,
+    /// From `Opts` at creation, the compositor offers no way to change it after.
+    namespace: String,
+    /// Buffer pixels per surface coordinate, the compositor's preferred fractional scale when
+    /// it has one, otherwise the highest scale of the outputs the bar is on.
+    scale: f64,
+    fractional_scale: Option<FractionalScale>,
+    /// How the output the bar is on is rotated or flipped. The compositor applies it to the
+    /// bar's buffers, which are left untransformed, so it is only tracked for lua.
+    transform: wl_output::Transform,
+    show_fps: bool,
+    background_colour: u32,
+    /// Of `buffers`, which are dropped when it changes.
+    pixel_format: PixelFormat,
+    /// Drawn to in turn so a frame never overwrites the buffer the compositor is still reading,
+    /// reused until the size changes.
+    buffers: [Option<Buffer>; 2],
+    /// Index into `buffers` of the one to draw next.
+    back_buffer: usize,
+    /// What each of `buffers` has missed since it was drawn.
+    damage: DamageHistory,
+    /// Draws in place of the shm buffers when `Opts::backend` is gpu.
+    #[cfg(feature = "gpu")]
+    gpu: Option<GpuRenderer>,
+    /// Seconds of inactivity before the bar stops drawing, from `Opts`. 0 never stops.
+    idle_timeout: u32,
+    /// Watches for `idle_timeout`, which it was created with, on the first seat.
+    idle_notification: Option<(u32, ExtIdleNotificationV1)>,
+    /// Repeat held keys while the bar has keyboard focus, from `Opts`.
+    key_repeat: bool,
+    /// How long `draw` may run before it is interrupted, from `Opts`. `None` lets it run.
+    draw_timeout: Option<Duration>,
+}
 
-        let conn = Connection::connect_to_env().unwrap();
+/// Whether a bar should be drawn. Frames are drawn on demand, once the bar is configured, while
+/// it is shown and the user is active, and no more often than its frame cap allows.
+#[derive(Debug)]
+struct Schedule {
+    /// Cleared by the first configure, before which the bar must not be drawn. Set again when
+    /// a hidden bar is shown, which needs configuring anew.
+    first_configure: bool,
+    /// Set by the compositor when the bar's output goes away, after which it stays blank.
+    closed: bool,
+    /// Cleared by `set_visible`, which unmaps the bar until it is shown again.
+    visible: bool,
+    /// Set when the next frame should be drawn, cleared by `drawn`.
+    needs_redraw: bool,
+    /// A frame callback has been requested and not yet received.
+    frame_pending: bool,
+    /// Set while the user is idle, which holds back redraws until they are active again.
+    idle: bool,
+    /// Minimum time between draws, from `Opts::max_fps`.
+    frame_interval: Option<Duration>,
+    last_frame: Instant,
+}
 
-        let (globals, mut event_queue) = registry_queue_init(&conn).unwrap();
-        let qh: QueueHandle<LayerState> = event_queue.handle();
+impl Schedule {
+    fn new() -> Self {
+        Schedule {
+            first_configure: true,
+            closed: false,
+            visible: true,
+            needs_redraw: true,
+            frame_pending: false,
+            idle: false,
+            frame_interval: None,
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Whether the bar should be drawn at `now`.
+    fn ready(&self, now: Instant) -> bool {
+        self.needs_redraw
+            && !self.frame_pending
+            && !self.first_configure
+            && !self.closed
+            && self.visible
+            && !self.idle
+            && self.frame_delay(now).is_none()
+    }
+
+    /// How long the frame cap holds back the requested redraw at `now`, `None` if it can be
+    /// drawn now or nothing needs drawing.
+    fn frame_delay(&self, now: Instant) -> Option<Duration> {
+        let interval = self.frame_interval?;
+        if !self.needs_redraw || self.closed || !self.visible || self.idle {
+            return None;
+        }
+        interval
+            .checked_sub(now.duration_since(self.last_frame))
+            .filter(|d| !d.is_zero())
+    }
 
-        let compositor =
-            CompositorState::bind(&globals, &qh).expect("wl_compositor is not available");
-        let layer_shell = LayerShell::bind(&globals, &qh).expect("layer shell is not available");
+    /// Stops drawing a hidden bar. A shown one is drawn again once the compositor configures
+    /// it. Returns whether the visibility changed.
+    fn set_visible(&mut self, visible: bool) -> bool {
+        if self.visible == visible {
+            return false;
+        }
+        self.visible = visible;
+        if visible {
+            self.first_configure = true;
+            // The frame callback of the last frame before hiding may never come.
+            self.frame_pending = false;
+            self.needs_redraw = true;
+        }
+        true
+    }
 
-        // Since we are not using the GPU in this example, we use wl_shm to allow software rendering to a buffer
-        // we share with the compositor process.
-        let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
+    /// Pauses drawing while the user is idle. Once they are back the bar is redrawn, having
+    /// missed whatever changed meanwhile.
+    fn set_idle(&mut self, idle: bool) {
+        self.idle = idle;
+        if !idle {
+            self.needs_redraw = true;
+        }
+    }
 
-        let surface = compositor.create_surface(&qh);
+    /// Records a frame drawn at `now`, returning the time since the one before.
+    fn drawn(&mut self, now: Instant) -> Duration {
+        let delta = now.duration_since(self.last_frame);
+        self.needs_redraw = false;
+        self.last_frame = now;
+        delta
+    }
+}
 
-        let layer = layer_shell.create_layer_surface(
-            &qh,
-            surface,
-            opts.layer.into(),
-            opts.namespace.clone(),
-            None,
+/// Tracks what each of a bar's two buffers has missed, so a reused one is brought up to date
+/// by redrawing only what frames drawn since have changed.
+#[derive(Debug, Default)]
+struct DamageHistory {
+    /// Frames presented since each buffer was drawn, 0 when its contents are unknown.
+    ages: [usize; 2],
+    /// Damage of the latest frames, newest first, `None` for a frame damaged in full.
+    frames: VecDeque<Option<Vec<IRect>>>,
+}
+
+impl DamageHistory {
+    /// The regions of `buffer` that frames drawn since have changed, which must be redrawn to
+    /// bring it up to date. `None` when all of it must.
+    fn stale_region(&self, buffer: usize) -> Option<Vec<IRect>> {
+        let age = self.ages[buffer];
+        if age == 0 || age > self.frames.len() + 1 {
+            return None;
+        }
+
+        self.frames
+            .iter()
+            .take(age - 1)
+            .try_fold(Vec::new(), |mut region, damage| {
+                region.extend_from_slice(damage.as_ref()?);
+                Some(region)
+            })
+    }
+
+    /// Ages the buffers once `buffer` is presented with `damage`.
+    fn record(&mut self, buffer: usize, damage: Option<Vec<IRect>>) {
+        for age in &mut self.ages {
+            if *age > 0 {
+                *age += 1;
+            }
+        }
+        self.ages[buffer] = 1;
+        self.frames.push_front(damage);
+        self.frames.truncate(MAX_BUFFER_AGE);
+    }
+
+    /// Marks the contents of `buffer` as unknown, for one just created.
+    fn replace(&mut self, buffer: usize) {
+        self.ages[buffer] = 0;
+    }
+
+    /// Marks the contents of every buffer as unknown.
+    fn forget(&mut self) {
+        self.ages = [0, 0];
+        self.frames.clear();
+    }
+}
+
+/// How long `run` waits for events: in time for the first redraw held back by a frame cap, or
+/// not at all when events raised while drawing are still to be handled.
+fn dispatch_timeout(events_pending: bool, frame_delay: Option<Duration>) -> Option<Duration> {
+    if events_pending {
+        Some(Duration::ZERO)
+    } else {
+        frame_delay
+    }
+}
+
+/// Lets the config clean up and removes the command socket, however the bar exits.
+fn shut_down(lua: &Lua, ipc_path: Option<&Path>) {
+    if let Err(e) = api::call_global(lua, "on_exit", ()) {
+        log::error!("on_exit failed: {e}");
+    }
+    if let Some(path) = ipc_path {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// How long a key is held before it repeats and the time between repeats, `None` when the
+/// seat has key repeat disabled.
+fn repeat_timing(info: RepeatInfo) -> Option<(Duration, Duration)> {
+    let RepeatInfo::Repeat { rate, delay } = info else {
+        return None;
+    };
+    Some((
+        Duration::from_millis(delay as u64),
+        Duration::from_micros(1_000_000 / rate.get() as u64),
+    ))
+}
+
+/// How far through an animation lasting `duration` it is after `elapsed`, from 0 to 1. It is
+/// over once this reaches 1.
+fn animation_progress(elapsed: Duration, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        1.0
+    } else {
+        (elapsed.as_secs_f64() / duration.as_secs_f64()).min(1.0)
+    }
+}
+
+/// What a signal the bar handles asks of it.
+fn signal_event(signal: Signal) -> Event {
+    match signal {
+        Signal::SIGUSR1 => Event::Reload,
+        _ => Event::Exit,
+    }
+}
+
+/// Notices edits to the config by its modification time.
+struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    fn new(path: &Path) -> Self {
+        let path = path.to_path_buf();
+        let modified = Self::modified(&path);
+        Self { path, modified }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Whether the config was written since the last call. A missing file, such as while an
+    /// editor replaces it, is not a change.
+    fn changed(&mut self) -> bool {
+        let modified = Self::modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+/// The size to draw a bar at, given the one the compositor configured it with and the one it
+/// asked for. A 0 leaves that side to the bar, and sizes are clamped like those lua can ask
+/// for, in case the compositor offers an absurd one.
+fn configured_size(new_size: (u32, u32), requested: (u32, u32)) -> (u32, u32) {
+    let side = |new, requested| {
+        NonZeroU32::new(new)
+            .map_or(requested, NonZeroU32::get)
+            .min(MAX_SIZE)
+    };
+    (side(new_size.0, requested.0), side(new_size.1, requested.1))
+}
+
+/// The size a bar of `size` changes to when configured with `new_size`, `None` if it stays
+/// the same.
+fn resize(size: (u32, u32), new_size: (u32, u32), requested: (u32, u32)) -> Option<(u32, u32)> {
+    let new_size = configured_size(new_size, requested);
+    (new_size != size).then_some(new_size)
+}
+
+/// The size in buffer pixels of a surface of `size` at `scale`.
+fn buffer_size(size: (u32, u32), scale: f64) -> (i32, i32) {
+    (
+        (size.0 as f64 * scale).round() as i32,
+        (size.1 as f64 * scale).round() as i32,
+    )
+}
+
+/// The scale a `preferred_scale` event asks for, which is sent in 120ths.
+fn preferred_scale(scale: u32) -> f64 {
+    scale as f64 / 120.0
+}
+
+/// The regions of a frame to damage, or None when the whole surface has to be.
+fn surface_damage(damage: Vec<IRect>, full: bool) -> Option<Vec<IRect>> {
+    (!full && !damage.is_empty()).then_some(damage)
+}
+
+/// Which of a bar's two buffers to draw into, given which of them the compositor has released:
+/// the back buffer, or the front one if only it is free. The second value is false when neither
+/// is, and a fresh buffer has to take the back one's place.
+fn pick_buffer(back: usize, released: [bool; 2]) -> (usize, bool) {
+    [back, back ^ 1]
+        .into_iter()
+        .find(|&i| released[i])
+        .map_or((back, false), |i| (i, true))
+}
+
+/// Room for both buffers of every bar, the pool grows if the compositor picks a larger size.
+/// At least a byte, as an empty pool is a protocol error when every bar's width is left to
+/// the compositor.
+fn initial_pool_size(bars: &[Opts]) -> usize {
+    bars.iter()
+        .map(|opts| opts.width as usize * opts.height as usize * 4 * 2)
+        .sum::<usize>()
+        .max(1)
+}
+
+/// Logs an error, but repeats of the same message at most once per `ERROR_LOG_INTERVAL`.
+#[derive(Default)]
+struct ErrorLog {
+    last: Option<(String, Instant)>,
+}
+
+/// The one edge a bar anchored to `anchor` sits along, `None` when it is anchored to both
+/// opposite edges, or neither, of each axis.
+fn anchored_edge(anchor: Anchor) -> Option<Anchor> {
+    let vertical = anchor & (Anchor::TOP | Anchor::BOTTOM);
+    let horizontal = anchor & (Anchor::LEFT | Anchor::RIGHT);
+    if vertical == Anchor::TOP || vertical == Anchor::BOTTOM {
+        Some(vertical)
+    } else if horizontal == Anchor::LEFT || horizontal == Anchor::RIGHT {
+        Some(horizontal)
+    } else {
+        None
+    }
+}
+
+/// The time between frames at `max_fps`, or `None` when uncapped.
+fn frame_interval(max_fps: u32) -> Option<Duration> {
+    (max_fps > 0).then(|| Duration::from_secs_f64(1.0 / max_fps as f64))
+}
+
+impl ErrorLog {
+    fn report(&mut self, message: &str) {
+        let now = Instant::now();
+        let repeated = matches!(
+            &self.last,
+            Some((last, at)) if last == message && now.duration_since(*at) < ERROR_LOG_INTERVAL
         );
 
-        if let Some(a) = opts.anchor {
-            layer.set_anchor(a);
+        if !repeated {
+            log::error!("draw failed: {message}");
+            self.last = Some((message.to_string(), now));
         }
-        let margin = opts.margin;
+    }
+}
 
-        layer.set_margin(margin.top, margin.right, margin.bottom, margin.left);
-        layer.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
-        layer.set_size(opts.width, opts.height);
-        layer.set_exclusive_zone(opts.exclusive_zone);
-        layer.commit();
+/// A press or click of a button on a bar, in surface coordinates and compositor milliseconds.
+#[derive(Debug, Clone, Copy)]
+struct Click {
+    bar: usize,
+    button: u32,
+    position: (f64, f64),
+    time: u32,
+}
+
+impl Click {
+    /// Whether `later` is of the same button on the same bar, within `interval` and
+    /// `CLICK_DISTANCE` of this.
+    fn followed_by(&self, later: &Click, interval: u32) -> bool {
+        let (dx, dy) = (
+            later.position.0 - self.position.0,
+            later.position.1 - self.position.1,
+        );
+        self.bar == later.bar
+            && self.button == later.button
+            && later.time.wrapping_sub(self.time) <= interval
+            && dx.hypot(dy) <= CLICK_DISTANCE
+    }
+}
+
+/// A popup over a bar drawn by the config's `draw_popup`.
+struct Tooltip {
+    /// The bar it is attached to, and drawn along with.
+    bar: usize,
+    popup: Popup,
+    /// From the popup's configure, zero until the compositor has placed it.
+    width: u32,
+    height: u32,
+    buffer: Option<Buffer>,
+}
+
+/// Where to anchor a tooltip shown at `position` on a bar of `size`: the anchor has to lie on
+/// the bar, so a tooltip off the bar is reached by an offset from the nearest point on it.
+/// `None` while the bar has no area, before its first configure or while it is hidden.
+fn tooltip_anchor(position: (i32, i32), size: (u32, u32)) -> Option<(i32, i32)> {
+    if size.0 == 0 || size.1 == 0 {
+        return None;
+    }
+    Some((
+        position.0.clamp(0, size.0 as i32 - 1),
+        position.1.clamp(0, size.1 as i32 - 1),
+    ))
+}
+
+/// A seat's keyboard and pointer, each present while the seat has the capability.
+#[derive(Default)]
+struct Seat {
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    pointer: Option<wl_pointer::WlPointer>,
+    /// Sets the cursor of `pointer`.
+    cursor_shape_device: Option<WpCursorShapeDeviceV1>,
+    /// The serial of `pointer` entering a bar, needed to set its cursor. Unset while it is
+    /// elsewhere.
+    pointer_enter_serial: Option<u32>,
+}
 
-        let pool = SlotPool::new((opts.width * opts.height * 4) as usize, &shm)
-            .expect("failed to create pool");
+impl Seat {
+    fn release_keyboard(&mut self) {
+        if let Some(keyboard) = self.keyboard.take() {
+            keyboard.release();
+        }
+    }
+
+    fn release_pointer(&mut self) {
+        if let Some(device) = self.cursor_shape_device.take() {
+            device.destroy();
+        }
+        self.pointer_enter_serial = None;
+        if let Some(pointer) = self.pointer.take() {
+            pointer.release();
+        }
+    }
+}
+
+/// A bar's fractional scale object and the viewport that maps its buffer, which is larger
+/// than the surface by the scale, back onto the surface.
+struct FractionalScale {
+    scale: WpFractionalScaleV1,
+    viewport: WpViewport,
+}
+
+/// `xdg_wm_base`, bound on its own for popups since sctk's `XdgShell` also expects the state
+/// to handle toplevel windows.
+struct WmBase(xdg_wm_base::XdgWmBase);
+
+impl ProvidesBoundGlobal<xdg_wm_base::XdgWmBase, 5> for WmBase {
+    fn bound_global(&self) -> Result<xdg_wm_base::XdgWmBase, GlobalError> {
+        Ok(self.0.clone())
+    }
+}
+
+impl ProvidesBoundGlobal<xdg_wm_base::XdgWmBase, { XdgShell::API_VERSION_MAX }> for WmBase {
+    fn bound_global(&self) -> Result<xdg_wm_base::XdgWmBase, GlobalError> {
+        Ok(self.0.clone())
+    }
+}
+
+impl crate::Window for SimpleLayer {
+    fn new(bars: Vec<Opts>, lua: Lua) -> Result<Self, String> {
+        let conn = Connection::connect_to_env()
+            .map_err(|e| format!("failed to connect to the compositor: {e}"))?;
+
+        let (globals, mut event_queue) = registry_queue_init(&conn)
+            .map_err(|e| format!("failed to list the compositor's globals: {e}"))?;
+        let qh: QueueHandle<LayerState> = event_queue.handle();
+
+        let compositor = CompositorState::bind(&globals, &qh)
+            .map_err(|e| format!("wl_compositor is not available: {e}"))?;
+        let layer_shell = LayerShell::bind(&globals, &qh)
+            .map_err(|e| format!("layer shell is not available: {e}"))?;
+
+        // wl_shm allows software rendering to a buffer we share with the compositor process, and is
+        // the fallback when the gpu backend is unavailable.
+        let shm = Shm::bind(&globals, &qh).map_err(|e| format!("wl_shm is not available: {e}"))?;
+
+        let wm_base = globals
+            .bind(&qh, 1..=XdgShell::API_VERSION_MAX, GlobalData)
+            .map(WmBase)
+            .ok();
+
+        // Both are needed for fractional scales.
+        let fractional_scale_manager = match (
+            globals.bind::<WpFractionalScaleManagerV1, _, _>(&qh, 1..=1, ()),
+            globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()),
+        ) {
+            (Ok(manager), Ok(viewporter)) => Some((manager, viewporter)),
+            _ => None,
+        };
+        let idle_notifier = globals.bind(&qh, 1..=1, ()).ok();
+        let cursor_shape_manager = CursorShapeManager::bind(&globals, &qh).ok();
+
+        let pool = SlotPool::new(initial_pool_size(&bars), &shm)
+            .map_err(|e| format!("failed to create the buffer pool: {e}"))?;
 
         let event_loop = calloop::EventLoop::<LayerState>::try_new().unwrap();
 
@@ -125,28 +784,46 @@ impl crate::Window for SimpleLayer {
             output_state: OutputState::new(&globals, &qh),
 
             should_exit: false,
-            first_configure: true,
-            width: opts.width,
-            height: opts.height,
-            exclusive_zone: opts.exclusive_zone,
-            layer,
+            bars: Vec::new(),
+            connection: conn.clone(),
+            compositor,
+            layer_shell,
+            fractional_scale_manager,
+            idle_notifier,
             events: Vec::new(),
 
             pool,
+            wm_base,
+            tooltip: None,
             shm,
             modifiers: crate::window::Modifiers::default(),
+            press: None,
+            last_click: None,
 
-            keyboard: None,
-            keyboard_focus: false,
+            keyboard_focus: None,
+            repeat_info: RepeatInfo::Disable,
 
-            pointer: None,
+            seats: HashMap::new(),
+            pointer_position: api::Pointer::default(),
+            cursor_shape_manager,
+            cursor: CursorShape::Default,
             dispatched_events: false,
 
-            last_frame: Instant::now(),
+            draw_errors: ErrorLog::default(),
+            frame_times: VecDeque::new(),
+            started: Instant::now(),
             lua,
         };
 
-        event_queue.roundtrip(&mut layer_state).unwrap();
+        for opts in &bars {
+            let bar = layer_state.create_bar(&qh, opts);
+            layer_state.bars.push(bar);
+        }
+        layer_state.sync_bars();
+        layer_state.sync_started();
+        event_queue
+            .roundtrip(&mut layer_state)
+            .map_err(|e| format!("failed to create the bars: {e}"))?;
         let wayland_source = WaylandSource::new(conn, event_queue);
 
         event_loop
@@ -162,29 +839,66 @@ impl crate::Window for SimpleLayer {
             })
             .unwrap();
 
-        SimpleLayer {
+        // SIGTERM and SIGINT exit through the event loop so lua gets a chance to clean up,
+        // SIGUSR1 reloads the config.
+        let signals = Signals::new(&[Signal::SIGTERM, Signal::SIGINT, Signal::SIGUSR1]).unwrap();
+        event_loop
+            .handle()
+            .insert_source(signals, |event, _, state| {
+                state.events.push(signal_event(event.signal()));
+            })
+            .unwrap();
+
+        let mut simple_layer = SimpleLayer {
             state: layer_state,
-            layer: opts.layer.into(),
-            anchor: opts.anchor,
-            margin: opts.margin,
             event_loop,
-        }
+            qh,
+            config_path: None,
+            ipc_path: None,
+            lua_sources: Vec::new(),
+            key_repeat: None,
+        };
+        simple_layer.insert_task_channel();
+        Ok(simple_layer)
     }
 
-    fn run(&mut self) {
+    fn run(&mut self) -> ExitCode {
         loop {
-            self.event_loop
-                .dispatch(Some(std::time::Duration::from_millis(16)), &mut self.state)
-                .unwrap();
+            let timeout = dispatch_timeout(!self.state.events.is_empty(), self.state.frame_delay());
+            if let Err(e) = self.event_loop.dispatch(timeout, &mut self.state) {
+                // Usually the compositor going away, after which nothing can be shown.
+                log::error!("lost the wayland connection: {e}");
+                SimpleLayer::handle_event(self, Event::Exit);
+                return ExitCode::FAILURE;
+            }
 
-            let mut tmp_events = Vec::new();
-            std::mem::swap(&mut tmp_events, &mut self.state.events);
-            for event in tmp_events.drain(..) {
-                SimpleLayer::handle_event(self, event);
-                if self.state.should_exit {
-                    return;
+            // Handling an event may call into lua, which can raise further events.
+            loop {
+                self.state.collect_lua_events();
+                if self.state.events.is_empty() {
+                    break;
+                }
+
+                let mut tmp_events = Vec::new();
+                std::mem::swap(&mut tmp_events, &mut self.state.events);
+                for event in tmp_events.drain(..) {
+                    SimpleLayer::handle_event(self, event);
+                    if self.state.should_exit {
+                        return ExitCode::SUCCESS;
+                    }
+                }
+            }
+
+            // The only place frames are drawn, at most once per bar and iteration.
+            for index in 0..self.state.bars.len() {
+                if self.state.bars[index].ready() {
+                    self.state.draw(index, &self.qh);
                 }
             }
+
+            // Setters called from `draw` are held back until every bar is drawn, so a surface
+            // is never changed partway through a frame.
+            self.state.collect_lua_events();
         }
     }
 
@@ -196,60 +910,484 @@ impl crate::Window for SimpleLayer {
 impl SimpleLayer {
     fn handle_event(&mut self, event: Event) {
         match event {
-            Event::Resized { width, height } => {
-                println!("Resized w: {} h: {}", width, height);
+            Event::Resized { bar, width, height } => {
+                log::debug!("bar {} resized to {width}x{height}", bar + 1);
+                if let Err(e) = self.state.call_lua("on_resize", (width, height, bar + 1)) {
+                    log::error!("on_resize failed: {e}");
+                }
             }
             Event::PointerButtonPressed { button, modifiers } => {
-                println!("Button {:?} pressed with {:?}", button, modifiers);
+                log::debug!("button {button:?} pressed with {modifiers:?}");
             }
             Event::PointerButtonReleased { button, modifiers } => {
-                println!("Button {:?} released with {:?}", button, modifiers);
+                log::debug!("button {button:?} released with {modifiers:?}");
+            }
+            Event::PointerMoved { bar, x, y } => {
+                log::trace!("pointer at {x}, {y} on bar {bar}");
+                self.state.set_pointer_position(api::Pointer {
+                    x,
+                    y,
+                    inside: true,
+                    bar,
+                });
+            }
+            Event::PointerEntered { bar, x, y } => {
+                log::trace!("pointer entered bar {bar} at {x}, {y}");
+                self.state.set_pointer_position(api::Pointer {
+                    x,
+                    y,
+                    inside: true,
+                    bar,
+                });
+                if let Err(e) = self.state.call_lua("on_pointer_enter", (x, y, bar + 1)) {
+                    log::error!("on_pointer_enter failed: {e}");
+                }
+            }
+            Event::PointerLeft => {
+                let pointer = api::Pointer {
+                    inside: false,
+                    ..self.state.pointer_position
+                };
+                self.state.set_pointer_position(pointer);
+                if let Err(e) = self.state.call_lua("on_pointer_leave", ()) {
+                    log::error!("on_pointer_leave failed: {e}");
+                }
+            }
+            Event::Click {
+                bar,
+                button,
+                x,
+                y,
+                modifiers,
+            } => {
+                let button = crate::window::button_name(button);
+                if let Err(e) = self
+                    .state
+                    .call_lua("on_click", (button, x, y, bar + 1, modifiers))
+                {
+                    log::error!("on_click failed: {e}");
+                }
+            }
+            Event::DoubleClick {
+                bar,
+                button,
+                x,
+                y,
+                modifiers,
+            } => {
+                let button = crate::window::button_name(button);
+                if let Err(e) = self
+                    .state
+                    .call_lua("on_double_click", (button, x, y, bar + 1, modifiers))
+                {
+                    log::error!("on_double_click failed: {e}");
+                }
+            }
+            Event::KeyboardKeyPressed {
+                key,
+                modifiers,
+                repeat,
+            } => {
+                log::debug!("key {key:?} pressed with {modifiers:?}");
+                let (name, text) = crate::window::key_names(&key);
+                // Modifiers on their own have no name, and are not repeated.
+                let repeats = !repeat && !name.is_empty();
+                if let Err(e) = self.state.call_lua("on_key", (name, text, modifiers)) {
+                    log::error!("on_key failed: {e}");
+                }
+                if repeats {
+                    self.start_key_repeat(key);
+                }
             }
-            Event::PointerMoved { x, y } => {
-                println!("Mouse moved at {}, {}", x, y);
+            Event::KeyboardEntered => {
+                self.state.sync_keyboard_focus();
+                if let Err(e) = self.state.call_lua("on_keyboard_enter", ()) {
+                    log::error!("on_keyboard_enter failed: {e}");
+                }
             }
-            Event::KeyboardKeyPressed { key, modifiers } => {
-                println!("Key pressed: {:?} with {:?}", key, modifiers);
+            Event::KeyboardLeft => {
+                self.stop_key_repeat();
+                self.state.sync_keyboard_focus();
+                if let Err(e) = self.state.call_lua("on_keyboard_leave", ()) {
+                    log::error!("on_keyboard_leave failed: {e}");
+                }
             }
             Event::KeyboardKeyReleased { key, modifiers } => {
-                println!("Key {:?}: {:?}", key, modifiers);
+                log::debug!("key {key:?} released with {modifiers:?}");
+                if self
+                    .key_repeat
+                    .is_some_and(|(raw_code, _)| raw_code == key.raw_code)
+                {
+                    self.stop_key_repeat();
+                }
+            }
+            Event::AddInterval { interval, callback } => {
+                let token = insert_interval(&self.event_loop.handle(), interval, move |state| {
+                    if let Err(e) = callback.call::<()>(&state.lua, ()) {
+                        log::error!("interval callback failed: {e}");
+                    }
+                });
+                self.lua_sources.push(token);
+            }
+            Event::Poll { interval, poller } => {
+                poller.run(&self.state.lua);
+                let token = insert_interval(&self.event_loop.handle(), interval, move |state| {
+                    poller.run(&state.lua);
+                });
+                self.lua_sources.push(token);
+            }
+            Event::Animate { duration, callback } => {
+                let started = Instant::now();
+                let token = self
+                    .event_loop
+                    .handle()
+                    .insert_source(Timer::immediate(), move |_, _, state| {
+                        let progress = animation_progress(started.elapsed(), duration);
+                        state.redraw_all();
+                        if let Err(e) = callback.call::<()>(&state.lua, progress) {
+                            log::error!("animate callback failed: {e}");
+                            return TimeoutAction::Drop;
+                        }
+                        // Stops once the last frame is requested, leaving redraws on demand.
+                        if progress < 1.0 {
+                            TimeoutAction::ToDuration(ANIMATION_FRAME_INTERVAL)
+                        } else {
+                            TimeoutAction::Drop
+                        }
+                    })
+                    .expect("failed to insert timer");
+                self.lua_sources.push(token);
+            }
+            Event::WatchFile { path, callback } => {
+                // Polled rather than watched with inotify, which misses changes to sysfs files.
+                let mut last = fs::read(&path).ok();
+                let token = self
+                    .event_loop
+                    .handle()
+                    .insert_source(
+                        Timer::from_duration(FILE_POLL_INTERVAL),
+                        move |_, _, state| {
+                            let contents = fs::read(&path).ok();
+                            if contents != last {
+                                let text = contents
+                                    .as_deref()
+                                    .map(|c| String::from_utf8_lossy(c).into_owned());
+                                if let Err(e) = callback.call::<()>(&state.lua, text) {
+                                    log::error!("watch_file callback failed: {e}");
+                                }
+                                last = contents;
+                            }
+                            TimeoutAction::ToDuration(FILE_POLL_INTERVAL)
+                        },
+                    )
+                    .expect("failed to insert timer");
+                self.lua_sources.push(token);
+            }
+            Event::SetCursor(shape) => {
+                if self.state.cursor_shape_manager.is_none() {
+                    log::debug!("set_cursor: the compositor does not support cursor shapes");
+                }
+                self.state.cursor = shape;
+                self.state.apply_cursor();
+            }
+            Event::Reload => {
+                self.reload();
+            }
+            Event::SetInputRegion { bar, region } => {
+                let compositor = &self.state.compositor;
+                if let Some(bar) = self.state.bars.get(bar) {
+                    bar.set_input_region(compositor, region);
+                } else {
+                    log::warn!("set_input_region: there is no bar {}", bar + 1);
+                }
+            }
+            Event::SetKeyboardInteractivity { bar, interactivity } => {
+                if let Some(bar) = self.state.bars.get(bar) {
+                    bar.layer.set_keyboard_interactivity(interactivity.into());
+                    bar.layer.commit();
+                } else {
+                    log::warn!("set_keyboard_interactivity: there is no bar {}", bar + 1);
+                }
+            }
+            Event::SetVisible {
+                bar: index,
+                visible,
+            } => {
+                let Some(bar) = self.state.bars.get_mut(index) else {
+                    log::warn!("set_visible: there is no bar {}", index + 1);
+                    return;
+                };
+                bar.set_visible(visible);
+                if !visible && self.state.tooltip.as_ref().is_some_and(|t| t.bar == index) {
+                    self.state.tooltip = None;
+                }
+            }
+            Event::SetMargin { bar, margin } => {
+                if let Some(bar) = self.state.bars.get_mut(bar) {
+                    bar.margin = margin;
+                    bar.layer
+                        .set_margin(margin.top, margin.right, margin.bottom, margin.left);
+                    bar.layer.commit();
+                } else {
+                    log::warn!("set_margin: there is no bar {}", bar + 1);
+                }
+            }
+            Event::SlideOffset { bar: index, offset } => {
+                let Some(bar) = self.state.bars.get(index) else {
+                    log::warn!("slide_offset: there is no bar {}", index + 1);
+                    return;
+                };
+                if let Some(margin) = bar.slid_margin(offset) {
+                    bar.layer
+                        .set_margin(margin.top, margin.right, margin.bottom, margin.left);
+                    bar.layer.commit();
+                } else {
+                    log::warn!(
+                        "slide_offset: bar {} is not anchored to a single edge",
+                        index + 1
+                    );
+                }
+            }
+            Event::ShowTooltip {
+                bar,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                self.state
+                    .show_tooltip(&self.qh, bar, (x, y), (width, height));
+            }
+            Event::HideTooltip => {
+                self.state.tooltip = None;
+            }
+            Event::Idle { bar } => {
+                if let Err(e) = self.state.call_lua("on_idle", bar + 1) {
+                    log::error!("on_idle failed: {e}");
+                }
+            }
+            Event::Resumed { bar } => {
+                if let Err(e) = self.state.call_lua("on_resume", bar + 1) {
+                    log::error!("on_resume failed: {e}");
+                }
+            }
+            Event::Redraw => {
+                self.state.redraw_all();
             }
             Event::Exit => {
-                println!("Exiting");
+                log::info!("exiting");
+                shut_down(&self.state.lua, self.ipc_path.as_deref());
                 self.state.should_exit = true;
             }
             _ => {}
         }
     }
-}
 
-impl CompositorHandler for LayerState {
-    fn scale_factor_changed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
-    ) {
-    }
+    /// Reloads the config whenever the file at `path` changes.
+    pub fn watch_config(&mut self, path: &Path) {
+        let mut watcher = ConfigWatcher::new(path);
+        self.config_path = Some(path.to_path_buf());
 
-    fn transform_changed(
+        self.event_loop
+            .handle()
+            .insert_source(
+                Timer::from_duration(CONFIG_POLL_INTERVAL),
+                move |_, _, state| {
+                    if watcher.changed() {
+                        state.events.push(Event::Reload);
+                    }
+                    TimeoutAction::ToDuration(CONFIG_POLL_INTERVAL)
+                },
+            )
+            .expect("failed to watch config");
+    }
+
+    /// Accepts commands from scripts on the socket at `ipc::socket_path()`.
+    pub fn listen_ipc(&mut self) {
+        let Some(path) = ipc::socket_path() else {
+            log::warn!("XDG_RUNTIME_DIR is not set, not listening for commands");
+            return;
+        };
+
+        let receiver = match ipc::listen(&path) {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                log::error!("failed to listen on {}: {e}", path.display());
+                return;
+            }
+        };
+
+        self.event_loop
+            .handle()
+            .insert_source(receiver, |event, _, state| {
+                let channel::Event::Msg(request) = event else {
+                    return;
+                };
+                let reply = match request.command {
+                    Command::Reload => {
+                        state.events.push(Event::Reload);
+                        "ok".to_string()
+                    }
+                    Command::Quit => {
+                        state.events.push(Event::Exit);
+                        "ok".to_string()
+                    }
+                    Command::Redraw => {
+                        state.redraw_all();
+                        "ok".to_string()
+                    }
+                    Command::Eval(code) => state.eval(&code),
+                };
+                let _ = request.reply.send(reply);
+            })
+            .expect("failed to insert ipc channel");
+        self.ipc_path = Some(path);
+    }
+
+    /// Presses `key` again while it is held, at the seat's rate, if the bar with keyboard focus
+    /// asks for key repeat.
+    fn start_key_repeat(&mut self, key: KeyEvent) {
+        self.stop_key_repeat();
+
+        let state = &self.state;
+        let enabled = state
+            .keyboard_focus
+            .and_then(|index| state.bars.get(index))
+            .is_some_and(|bar| bar.key_repeat);
+        let Some((delay, interval)) = repeat_timing(state.repeat_info) else {
+            return;
+        };
+        if !enabled {
+            return;
+        }
+
+        let raw_code = key.raw_code;
+        let token = self
+            .event_loop
+            .handle()
+            .insert_source(Timer::from_duration(delay), move |_, _, state| {
+                state.events.push(Event::KeyboardKeyPressed {
+                    key: key.clone(),
+                    modifiers: state.modifiers.clone(),
+                    repeat: true,
+                });
+                TimeoutAction::ToDuration(interval)
+            })
+            .expect("failed to insert timer");
+        self.key_repeat = Some((raw_code, token));
+    }
+
+    fn stop_key_repeat(&mut self) {
+        if let Some((_, token)) = self.key_repeat.take() {
+            self.event_loop.handle().remove(token);
+        }
+    }
+
+    /// Re-runs the config in a fresh lua state and swaps it in, keeping the old one if the
+    /// new config fails to load. Triggered by changes to the file and by SIGUSR1.
+    fn reload(&mut self) {
+        let Some(path) = &self.config_path else {
+            return;
+        };
+
+        let (bars, lua) = match config::load_config(path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                log::error!("failed to reload config: {e}");
+                return;
+            }
+        };
+
+        // Timers call into the old lua state, the new config registers its own. Dropping the
+        // old task channel also stops the background services started by the old config.
+        for token in self.lua_sources.drain(..) {
+            self.event_loop.handle().remove(token);
+        }
+        self.state.lua = lua;
+        self.state.apply_bars(&self.qh, &bars);
+        self.state.sync_outputs(None);
+        self.state.set_pointer_position(self.state.pointer_position);
+        self.state.sync_keyboard_focus();
+        self.state.sync_bars();
+        self.state.sync_started();
+        self.insert_task_channel();
+        log::info!("reloaded config");
+    }
+
+    /// Runs tasks sent by background services on the main thread, against the current lua.
+    fn insert_task_channel(&mut self) {
+        let Some(channel) = self
+            .state
+            .lua
+            .app_data_mut::<api::Context>()
+            .and_then(|mut ctx| ctx.task_channel.take())
+        else {
+            return;
+        };
+
+        let token = self
+            .event_loop
+            .handle()
+            .insert_source(channel, |event, _, state| {
+                if let channel::Event::Msg(task) = event {
+                    task(&state.lua);
+                }
+            })
+            .expect("failed to insert task channel");
+        self.lua_sources.push(token);
+    }
+}
+
+impl CompositorHandler for LayerState {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
+    ) {
+        let Some(index) = self.bar_of(surface) else {
+            return;
+        };
+        // Buffer scales need wl_surface version 3, older compositors only get scale 1. A
+        // fractional scale takes the place of the integer one.
+        if surface.version() < 3 || self.bars[index].fractional_scale.is_some() {
+            return;
+        }
+
+        // Applied with the next buffer, which `draw` makes at the new scale.
+        surface.set_buffer_scale(new_factor);
+        self.set_scale(index, new_factor as f64);
+    }
+
+    fn transform_changed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
+        let Some(index) = self.bar_of(surface) else {
+            return;
+        };
+
+        self.bars[index].transform = new_transform;
+        self.bars[index].schedule.needs_redraw = true;
+        self.sync_bars();
     }
 
     fn frame(
         &mut self,
         _conn: &Connection,
-        qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
+        _qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
-        self.draw(qh);
+        // Drawn by `run` once the events of this iteration are handled, so every redraw they
+        // request collapses into a single frame.
+        if let Some(index) = self.bar_of(surface) {
+            self.bars[index].schedule.frame_pending = false;
+        }
     }
 }
 
@@ -264,6 +1402,7 @@ impl OutputHandler for LayerState {
         _qh: &QueueHandle<Self>,
         _output: wl_output::WlOutput,
     ) {
+        self.sync_outputs(None);
     }
 
     fn update_output(
@@ -272,38 +1411,101 @@ impl OutputHandler for LayerState {
         _qh: &QueueHandle<Self>,
         _output: wl_output::WlOutput,
     ) {
+        self.sync_outputs(None);
     }
 
     fn output_destroyed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
+        // The output is only removed from `output_state` after this returns.
+        self.sync_outputs(Some(&output));
     }
 }
 
 impl LayerShellHandler for LayerState {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        self.events.push(Event::Exit);
-        self.should_exit = true;
+    /// The compositor closes a bar when its output goes away. The others carry on, and a
+    /// reload recreates it.
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        let Some(index) = self.bar_of(layer.wl_surface()) else {
+            return;
+        };
+        log::info!("bar {} was closed", index + 1);
+        self.bars[index].close();
+        if self.tooltip.as_ref().is_some_and(|t| t.bar == index) {
+            self.tooltip = None;
+        }
+
+        if self.bars.iter().all(|bar| bar.schedule.closed) {
+            self.events.push(Event::Exit);
+            self.should_exit = true;
+        }
     }
 
     fn configure(
         &mut self,
         _conn: &Connection,
-        qh: &QueueHandle<Self>,
-        _layer: &LayerSurface,
+        _qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        self.width = NonZeroU32::new(configure.new_size.0).map_or(256, NonZeroU32::get);
-        self.height = NonZeroU32::new(configure.new_size.1).map_or(256, NonZeroU32::get);
+        let Some(index) = self.bar_of(layer.wl_surface()) else {
+            return;
+        };
+        let bar = &mut self.bars[index];
+
+        if let Some((width, height)) = resize(
+            (bar.width, bar.height),
+            configure.new_size,
+            bar.requested_size,
+        ) {
+            bar.width = width;
+            bar.height = height;
+            bar.resize_buffers();
+            // Applied by the commit presenting the next frame.
+            if let Some(zone) = bar.auto_exclusive_zone() {
+                bar.layer.set_exclusive_zone(zone);
+            }
+            // Handled before the bar is next drawn.
+            self.events.push(Event::Resized {
+                bar: index,
+                width,
+                height,
+            });
+        }
+        bar.schedule.needs_redraw = true;
+
+        // Allows `run` to start drawing.
+        bar.schedule.first_configure = false;
+    }
+}
+
+impl PopupHandler for LayerState {
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        popup: &Popup,
+        config: PopupConfigure,
+    ) {
+        if let Some(tooltip) = &mut self.tooltip
+            && tooltip.popup == *popup
+        {
+            tooltip.width = config.width.clamp(1, MAX_SIZE as i32) as u32;
+            tooltip.height = config.height.clamp(1, MAX_SIZE as i32) as u32;
+            tooltip.buffer = None;
+            if let Some(bar) = self.bars.get_mut(tooltip.bar) {
+                bar.schedule.needs_redraw = true;
+            }
+        }
+    }
 
-        // Initiate the first draw.
-        if self.first_configure {
-            self.first_configure = false;
-            self.draw(qh);
+    fn done(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, popup: &Popup) {
+        if self.tooltip.as_ref().is_some_and(|t| t.popup == *popup) {
+            self.tooltip = None;
         }
     }
 }
@@ -313,7 +1515,12 @@ impl SeatHandler for LayerState {
         &mut self.seat_state
     }
 
-    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+    fn new_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        self.seats.entry(seat).or_default();
+        for index in 0..self.bars.len() {
+            self.watch_idle(index, qh);
+        }
+    }
 
     fn new_capability(
         &mut self,
@@ -322,22 +1529,28 @@ impl SeatHandler for LayerState {
         seat: wl_seat::WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_none() {
-            println!("Set keyboard capability");
+        let entry = self.seats.entry(seat.clone()).or_default();
+
+        if capability == Capability::Keyboard && entry.keyboard.is_none() {
+            log::info!("seat gained a keyboard");
             let keyboard = self
                 .seat_state
                 .get_keyboard(qh, &seat, None)
                 .expect("failed to create keyboard");
-            self.keyboard = Some(keyboard);
+            entry.keyboard = Some(keyboard);
         }
 
-        if capability == Capability::Pointer && self.pointer.is_none() {
-            println!("Set pointer capability");
+        if capability == Capability::Pointer && entry.pointer.is_none() {
+            log::info!("seat gained a pointer");
             let pointer = self
                 .seat_state
                 .get_pointer(qh, &seat)
                 .expect("failed to create pointer");
-            self.pointer = Some(pointer);
+            entry.cursor_shape_device = self
+                .cursor_shape_manager
+                .as_ref()
+                .map(|manager| manager.get_shape_device(&pointer, qh));
+            entry.pointer = Some(pointer);
         }
     }
 
@@ -345,21 +1558,31 @@ impl SeatHandler for LayerState {
         &mut self,
         _conn: &Connection,
         _: &QueueHandle<Self>,
-        _: wl_seat::WlSeat,
+        seat: wl_seat::WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_some() {
-            println!("Unset keyboard capability");
-            self.keyboard.take().unwrap().release();
+        let Some(entry) = self.seats.get_mut(&seat) else {
+            return;
+        };
+
+        if capability == Capability::Keyboard && entry.keyboard.is_some() {
+            log::info!("seat lost its keyboard");
+            entry.release_keyboard();
         }
 
-        if capability == Capability::Pointer && self.pointer.is_some() {
-            println!("Unset pointer capability");
-            self.pointer.take().unwrap().release();
+        if capability == Capability::Pointer && entry.pointer.is_some() {
+            log::info!("seat lost its pointer");
+            entry.release_pointer();
         }
     }
 
-    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        if let Some(mut entry) = self.seats.remove(&seat) {
+            log::info!("seat removed");
+            entry.release_keyboard();
+            entry.release_pointer();
+        }
+    }
 }
 
 impl KeyboardHandler for LayerState {
@@ -373,9 +1596,13 @@ impl KeyboardHandler for LayerState {
         _: &[u32],
         keysyms: &[Keysym],
     ) {
-        if self.layer.wl_surface() == surface {
-            println!("Keyboard focus on window with pressed syms: {keysyms:?}");
-            self.keyboard_focus = true;
+        if let Some(index) = self.bar_of(surface) {
+            log::debug!(
+                "keyboard focus gained by bar {} with {keysyms:?} held",
+                index + 1
+            );
+            self.keyboard_focus = Some(index);
+            self.events.push(Event::KeyboardEntered);
         }
     }
 
@@ -387,9 +1614,10 @@ impl KeyboardHandler for LayerState {
         surface: &wl_surface::WlSurface,
         _: u32,
     ) {
-        if self.layer.wl_surface() == surface {
-            println!("Release keyboard focus on window");
-            self.keyboard_focus = false;
+        if self.bar_of(surface).is_some() {
+            log::debug!("keyboard focus lost");
+            self.keyboard_focus = None;
+            self.events.push(Event::KeyboardLeft);
         }
     }
 
@@ -403,7 +1631,8 @@ impl KeyboardHandler for LayerState {
     ) {
         self.events.push(Event::KeyboardKeyPressed {
             key: event,
-            modifiers: crate::window::Modifiers::default(),
+            modifiers: self.modifiers.clone(),
+            repeat: false,
         });
     }
 
@@ -417,10 +1646,21 @@ impl KeyboardHandler for LayerState {
     ) {
         self.events.push(Event::KeyboardKeyReleased {
             key: event,
-            modifiers: crate::window::Modifiers::default(),
+            modifiers: self.modifiers.clone(),
         });
     }
 
+    fn update_repeat_info(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        info: RepeatInfo,
+    ) {
+        log::debug!("key repeat set to {info:?}");
+        self.repeat_info = info;
+    }
+
     fn update_modifiers(
         &mut self,
         _: &Connection,
@@ -429,7 +1669,8 @@ impl KeyboardHandler for LayerState {
         _serial: u32,
         modifiers: Modifiers,
     ) {
-        println!("Update modifiers: {modifiers:?}");
+        log::trace!("modifiers changed to {modifiers:?}");
+        self.modifiers = modifiers.into();
     }
 }
 
@@ -438,40 +1679,70 @@ impl PointerHandler for LayerState {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _pointer: &wl_pointer::WlPointer,
+        pointer: &wl_pointer::WlPointer,
         events: &[PointerEvent],
     ) {
         use PointerEventKind::*;
         for event in events {
             // Ignore events for other surfaces
-            if &event.surface != self.layer.wl_surface() {
+            let Some(bar) = self.bar_of(&event.surface) else {
                 continue;
-            }
+            };
             match event.kind {
-                Enter { .. } => self.events.push(Event::PointerEntered {
-                    x: event.position.0,
-                    y: event.position.1,
-                }),
+                Enter { serial } => {
+                    if let Some(seat) = self.seat_of(pointer) {
+                        seat.pointer_enter_serial = Some(serial);
+                    }
+                    self.apply_cursor();
+                    self.events.push(Event::PointerEntered {
+                        bar,
+                        x: event.position.0,
+                        y: event.position.1,
+                    });
+                }
                 Leave { .. } => {
+                    if let Some(seat) = self.seat_of(pointer) {
+                        seat.pointer_enter_serial = None;
+                    }
+                    if self
+                        .seats
+                        .values()
+                        .all(|seat| seat.pointer_enter_serial.is_none())
+                    {
+                        self.cursor = CursorShape::Default;
+                    }
                     self.events.push(Event::PointerLeft);
                 }
                 Motion { .. } => {
                     self.events.push(Event::PointerMoved {
+                        bar,
                         x: event.position.0,
                         y: event.position.1,
                     });
                 }
-                Press { .. } => {
+                Press { button, time, .. } => {
                     self.events.push(Event::PointerButtonPressed {
                         button: event.clone(),
                         modifiers: self.modifiers.clone(),
                     });
+                    self.press = Some(Click {
+                        bar,
+                        button,
+                        position: event.position,
+                        time,
+                    });
                 }
-                Release { .. } => {
+                Release { button, time, .. } => {
                     self.events.push(Event::PointerButtonReleased {
                         button: event.clone(),
                         modifiers: self.modifiers.clone(),
                     });
+                    self.release(Click {
+                        bar,
+                        button,
+                        position: event.position,
+                        time,
+                    });
                 }
                 Axis { .. } => {}
             }
@@ -486,73 +1757,975 @@ impl ShmHandler for LayerState {
 }
 
 impl LayerState {
-    pub fn draw(&mut self, qh: &QueueHandle<Self>) {
-        let width = self.width;
-        let height = self.height;
-        let stride = width as i32 * 4;
+    /// Calls the global lua function `name` if the config defines one.
+    fn call_lua(&self, name: &str, args: impl IntoLuaMulti) -> mlua::Result<()> {
+        api::call_global(&self.lua, name, args)
+    }
 
-        let now = Instant::now();
-        let frametime = now.duration_since(self.last_frame);
-        self.last_frame = Instant::now();
-        let fps = 1.0 / frametime.as_secs_f32();
-
-        let (buffer, canvas_data) = self
-            .pool
-            .create_buffer(
-                width as i32,
-                height as i32,
-                stride,
-                wl_shm::Format::Argb8888,
-            )
-            .expect("create buffer");
+    /// Shows `cursor` while the pointer is over a bar.
+    fn apply_cursor(&self) {
+        for seat in self.seats.values() {
+            if let (Some(device), Some(serial)) =
+                (&seat.cursor_shape_device, seat.pointer_enter_serial)
+            {
+                device.set_shape(serial, self.cursor.into());
+            }
+        }
+    }
 
-        // Draw to the window:
+    /// The seat `pointer` belongs to.
+    fn seat_of(&mut self, pointer: &wl_pointer::WlPointer) -> Option<&mut Seat> {
+        self.seats
+            .values_mut()
+            .find(|seat| seat.pointer.as_ref() == Some(pointer))
+    }
+
+    /// Turns a release following its press into a click, and a second click into a double
+    /// click.
+    fn release(&mut self, release: Click) {
+        let Some(press) = self.press.take() else {
+            return;
+        };
+        if !press.followed_by(&release, CLICK_TIME) {
+            return;
+        }
+
+        let (x, y) = release.position;
+        self.events.push(Event::Click {
+            bar: release.bar,
+            button: release.button,
+            x,
+            y,
+            modifiers: self.modifiers.clone(),
+        });
+
+        match self.last_click.take() {
+            // A third click starts over rather than making a second double click.
+            Some(first) if first.followed_by(&release, DOUBLE_CLICK_TIME) => {
+                self.events.push(Event::DoubleClick {
+                    bar: release.bar,
+                    button: release.button,
+                    x,
+                    y,
+                    modifiers: self.modifiers.clone(),
+                });
+            }
+            _ => self.last_click = Some(release),
+        }
+    }
+
+    /// Runs `code` against the live config and returns its results, or the error, as text.
+    /// Expressions are returned as if prefixed with `return`.
+    fn eval(&self, code: &str) -> String {
+        let chunk = self
+            .lua
+            .load(format!("return {code}"))
+            .set_name("=ipc")
+            .into_function()
+            .or_else(|_| self.lua.load(code).set_name("=ipc").into_function());
+
+        match chunk.and_then(|f| f.call::<mlua::MultiValue>(())) {
+            Ok(values) => values
+                .iter()
+                .map(|v| v.to_string().unwrap_or_else(|e| e.to_string()))
+                .collect::<Vec<_>>()
+                .join("\t"),
+            Err(e) => format!("error: {e}"),
+        }
+    }
+
+    /// How long the frame caps hold back the first requested redraw, `None` if one can be drawn
+    /// now or nothing needs drawing.
+    fn frame_delay(&self) -> Option<Duration> {
+        self.bars.iter().filter_map(Bar::frame_delay).min()
+    }
+
+    /// Moves events raised from lua into the window's queue.
+    fn collect_lua_events(&mut self) {
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            self.events.append(&mut ctx.events);
+        }
+    }
+
+    /// The index of the bar drawn on `surface`.
+    fn bar_of(&self, surface: &wl_surface::WlSurface) -> Option<usize> {
+        self.bars
+            .iter()
+            .position(|bar| bar.layer.wl_surface() == surface)
+    }
+
+    fn redraw_all(&mut self) {
+        for bar in &mut self.bars {
+            bar.schedule.needs_redraw = true;
+        }
+    }
+
+    /// Creates the layer surface for `opts`, which is drawn once the compositor configures it.
+    fn create_bar(&self, qh: &QueueHandle<Self>, opts: &Opts) -> Bar {
+        let surface = self.compositor.create_surface(qh);
+        let layer = self.layer_shell.create_layer_surface(
+            qh,
+            surface,
+            opts.layer.into(),
+            Some(opts.namespace.clone()),
+            None,
+        );
+
+        let fractional_scale =
+            self.fractional_scale_manager
+                .as_ref()
+                .map(|(manager, viewporter)| FractionalScale {
+                    scale: manager.get_fractional_scale(layer.wl_surface(), qh, ()),
+                    viewport: viewporter.get_viewport(layer.wl_surface(), qh, ()),
+                });
+
+        if opts.input_passthrough {
+            // An empty input region lets every click through to the windows below.
+            let region = Region::new(&self.compositor).expect("failed to create region");
+            layer
+                .wl_surface()
+                .set_input_region(Some(region.wl_region()));
+        }
+
+        #[cfg(feature = "gpu")]
+        let gpu = (opts.backend == Backend::Gpu)
+            .then(|| {
+                let size = (opts.width.max(1) as i32, opts.height.max(1) as i32);
+                GpuRenderer::new(
+                    self.connection.backend().display_ptr().cast(),
+                    layer.wl_surface(),
+                    size,
+                )
+                .inspect_err(|e| log::warn!("{e}, falling back to the cpu backend"))
+                .ok()
+            })
+            .flatten();
+        #[cfg(not(feature = "gpu"))]
+        if opts.backend == Backend::Gpu {
+            log::warn!("built without the gpu feature, falling back to the cpu backend");
+        }
+
+        let mut bar = Bar {
+            layer,
+            draw: opts.draw.clone(),
+            schedule: Schedule::new(),
+            width: opts.width,
+            height: opts.height,
+            requested_size: (opts.width.max(1), opts.height.max(1)),
+            auto_exclusive: None,
+            anchor: Anchor::empty(),
+            margin: Margin::default(),
+            namespace: opts.namespace.clone(),
+            scale: 1.0,
+            fractional_scale,
+            transform: wl_output::Transform::Normal,
+            show_fps: opts.show_fps,
+            background_colour: 0,
+            pixel_format: PixelFormat::default(),
+            buffers: [None, None],
+            back_buffer: 0,
+            damage: DamageHistory::default(),
+            #[cfg(feature = "gpu")]
+            gpu,
+            idle_timeout: 0,
+            idle_notification: None,
+            key_repeat: false,
+            draw_timeout: None,
+        };
+        bar.apply_opts(opts);
+        bar
+    }
+
+    /// Applies the opts of a reloaded config, creating the bars it adds and those the compositor
+    /// closed, and removing the bars it no longer lists.
+    fn apply_bars(&mut self, qh: &QueueHandle<Self>, bars: &[Opts]) {
+        self.bars.truncate(bars.len());
+
+        for (index, opts) in bars.iter().enumerate() {
+            if let Some(bar) = self.bars.get_mut(index)
+                && !bar.schedule.closed
+            {
+                if opts.namespace != bar.namespace {
+                    log::warn!(
+                        "opts.namespace only applies at startup, keeping \"{}\" for bar {}",
+                        bar.namespace,
+                        index + 1
+                    );
+                }
+                bar.apply_opts(opts);
+                self.watch_idle(index, qh);
+                continue;
+            }
+
+            let bar = self.create_bar(qh, opts);
+            match self.bars.get_mut(index) {
+                Some(closed) => *closed = bar,
+                None => self.bars.push(bar),
+            }
+            self.watch_idle(index, qh);
+        }
+
+        if self
+            .tooltip
+            .as_ref()
+            .is_some_and(|t| t.bar >= self.bars.len())
         {
-            let mut canvas = crate::renderer::skia_cpu::Canvas::new(
-                width.try_into().unwrap(),
-                height.try_into().unwrap(),
-                canvas_data,
-            );
+            self.tooltip = None;
+        }
+        if self.pointer_position.bar >= self.bars.len() {
+            self.pointer_position.inside = false;
+        }
+    }
+
+    /// Watches for the user going idle for bar `index`'s `idle_timeout`, replacing the watch for
+    /// an earlier timeout. Needs a seat, so bars created before one appears are watched from
+    /// `new_seat`.
+    fn watch_idle(&mut self, index: usize, qh: &QueueHandle<Self>) {
+        let seat = self.seat_state.seats().next();
+        let bar = &mut self.bars[index];
+        if bar
+            .idle_notification
+            .as_ref()
+            .is_some_and(|(timeout, _)| *timeout == bar.idle_timeout)
+        {
+            return;
+        }
+        if let Some((_, notification)) = bar.idle_notification.take() {
+            notification.destroy();
+        }
+        bar.schedule.idle = false;
 
-            canvas.clear(0xFF707070);
-            canvas.draw_fps(fps as u32);
+        if bar.idle_timeout == 0 {
+            return;
+        }
+        let Some(notifier) = &self.idle_notifier else {
+            log::warn!("opts.idle_timeout: the compositor does not support ext-idle-notify");
+            return;
+        };
+        let Some(seat) = seat else {
+            return;
+        };
+        let notification =
+            notifier.get_idle_notification(bar.idle_timeout.saturating_mul(1000), &seat, qh, ());
+        bar.idle_notification = Some((bar.idle_timeout, notification));
+    }
+
+    /// Copies the connected outputs, except `removed`, into lua for `outputs()`.
+    fn sync_outputs(&mut self, removed: Option<&wl_output::WlOutput>) {
+        let outputs = self
+            .output_state
+            .outputs()
+            .filter(|o| Some(o) != removed)
+            .filter_map(|o| self.output_state.info(&o))
+            .map(Output::from)
+            .collect();
+
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.outputs = outputs;
+        }
+        self.redraw_all();
+    }
 
-            let g = self.lua.globals();
+    /// Records the pointer position and makes it available to lua.
+    fn set_pointer_position(&mut self, pointer: api::Pointer) {
+        self.pointer_position = pointer;
+        let hover_used = self
+            .lua
+            .app_data_mut::<api::Context>()
+            .is_some_and(|mut ctx| {
+                ctx.pointer = pointer;
+                ctx.hover_used
+            });
+        // Hover effects depend on the position, and moving between bars changes both.
+        if hover_used {
+            self.redraw_all();
+        }
+    }
 
-            let d: mlua::Function = g.get("draw").unwrap();
-            self.lua
-                .scope(|scope| {
-                    let canvas = scope.create_userdata(canvas)?;
-                    d.call::<()>(canvas)
+    /// Makes each bar's namespace and output transform available to lua.
+    fn sync_bars(&self) {
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.bars = self
+                .bars
+                .iter()
+                .map(|bar| api::Bar {
+                    namespace: bar.namespace.clone(),
+                    transform: transform_name(bar.transform),
                 })
-                .unwrap();
+                .collect();
+        }
+    }
+
+    /// Keeps `uptime()` counting from startup rather than from the last reload.
+    fn sync_started(&self) {
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.started = self.started;
+        }
+    }
+
+    /// Makes the keyboard focus available to lua, redrawing so the bars can reflect it.
+    fn sync_keyboard_focus(&mut self) {
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.keyboard_focus = self.keyboard_focus.is_some();
+        }
+        self.redraw_all();
+    }
+
+    /// Opens a tooltip with its top left corner at `position` on bar `index`, replacing any
+    /// already shown. The compositor may slide or flip it to keep it on screen.
+    fn show_tooltip(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        index: usize,
+        position: (i32, i32),
+        size: (i32, i32),
+    ) {
+        let Some(wm_base) = &self.wm_base else {
+            log::warn!("show_tooltip: the compositor does not support xdg popups");
+            return;
+        };
+        let Some(bar) = self.bars.get(index).filter(|bar| !bar.schedule.closed) else {
+            log::warn!("show_tooltip: there is no bar {}", index + 1);
+            return;
+        };
+        let Some(anchor) = tooltip_anchor(position, (bar.width, bar.height)) else {
+            log::warn!("show_tooltip: bar {} is not shown", index + 1);
+            return;
+        };
+        // Replaced rather than repositioned, which needs a newer xdg_wm_base.
+        self.tooltip = None;
+
+        let positioner = XdgPositioner::new(wm_base).expect("failed to create positioner");
+        positioner.set_size(size.0, size.1);
+        positioner.set_anchor_rect(anchor.0, anchor.1, 1, 1);
+        positioner.set_offset(position.0 - anchor.0, position.1 - anchor.1);
+        positioner.set_anchor(xdg_positioner::Anchor::TopLeft);
+        positioner.set_gravity(xdg_positioner::Gravity::BottomRight);
+        positioner.set_constraint_adjustment(
+            (xdg_positioner::ConstraintAdjustment::SlideX
+                | xdg_positioner::ConstraintAdjustment::SlideY
+                | xdg_positioner::ConstraintAdjustment::FlipY)
+                .bits(),
+        );
+
+        let surface = self.compositor.create_surface(qh);
+        let popup = Popup::from_surface(None, &positioner, qh, surface, wm_base)
+            .expect("failed to create popup");
+        bar.layer.get_popup(popup.xdg_popup());
+        popup.wl_surface().commit();
+
+        self.tooltip = Some(Tooltip {
+            bar: index,
+            popup,
+            width: 0,
+            height: 0,
+            buffer: None,
+        });
+    }
+
+    fn set_scale(&mut self, index: usize, scale: f64) {
+        let bar = &mut self.bars[index];
+        if scale == bar.scale {
+            return;
+        }
+
+        bar.scale = scale;
+        bar.resize_buffers();
+        bar.schedule.needs_redraw = true;
+        if let Some(tooltip) = &mut self.tooltip
+            && tooltip.bar == index
+        {
+            tooltip.buffer = None;
+        }
+    }
+
+    /// Draws the tooltip, if one is shown on bar `index` and configured, with the config's
+    /// `draw_popup`.
+    fn draw_tooltip(&mut self, index: usize, delta: f32) {
+        let Some(tooltip) = &mut self.tooltip else {
+            return;
+        };
+        if tooltip.bar != index || tooltip.width == 0 || tooltip.height == 0 {
+            return;
+        }
+        let bar = &self.bars[index];
+        // Popups have no viewport, so fractional scales round up to the next buffer scale.
+        let scale = if tooltip.popup.wl_surface().version() >= 3 {
+            bar.scale.ceil() as i32
+        } else {
+            1
+        };
+        let (width, height) = (tooltip.width as i32 * scale, tooltip.height as i32 * scale);
+
+        if tooltip
+            .buffer
+            .as_ref()
+            .is_none_or(|b| b.slot().has_active_buffers())
+        {
+            let buffer =
+                match self
+                    .pool
+                    .create_buffer(width, height, width * 4, bar.pixel_format.into())
+                {
+                    Ok((buffer, _)) => buffer,
+                    Err(e) => {
+                        log::error!("failed to create a {width}x{height} tooltip buffer: {e}");
+                        return;
+                    }
+                };
+            tooltip.buffer = Some(buffer);
         }
+        let buffer = tooltip.buffer.as_ref().expect("buffer was just created");
+        let canvas_data = buffer
+            .canvas(&mut self.pool)
+            .expect("buffer has been released");
+
+        render(
+            &self.lua,
+            "draw_popup",
+            &mut Target::Pixels {
+                width,
+                height,
+                data: canvas_data,
+                format: bar.pixel_format,
+                stale: None,
+            },
+            &mut Vec::new(),
+            scale as f32,
+            bar.background_colour,
+            index,
+            delta,
+            None,
+            bar.draw_timeout,
+            &mut self.draw_errors,
+        );
+
+        let surface = tooltip.popup.wl_surface();
+        if surface.version() >= 3 {
+            surface.set_buffer_scale(scale);
+        }
+        surface.damage_buffer(0, 0, width, height);
+        buffer.attach_to(surface).expect("buffer attach");
+        surface.commit();
+    }
+
+    /// Draws bar `index`, and the tooltip if it is shown on the bar.
+    fn draw(&mut self, index: usize, qh: &QueueHandle<Self>) {
+        // Lets `hover` compare the pointer with the bar being drawn.
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.drawing = Some(index);
+        }
+        let started = Instant::now();
+        self.draw_bar(index, qh);
+        self.record_frame_time(started.elapsed());
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.drawing = None;
+        }
+    }
 
-        // Damage the entire window
-        self.layer
-            .wl_surface()
-            .damage_buffer(0, 0, width as i32, height as i32);
+    /// Adds a frame's draw time to those `perf_stats()` summarises.
+    fn record_frame_time(&mut self, time: Duration) {
+        if self.frame_times.len() == FRAME_TIMES_KEPT {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(time);
+
+        if let Some(mut ctx) = self.lua.app_data_mut::<api::Context>() {
+            ctx.perf = api::PerfStats::new(self.frame_times.iter().copied());
+        }
+    }
+
+    fn draw_bar(&mut self, index: usize, qh: &QueueHandle<Self>) {
+        let delta = self.bars[index]
+            .schedule
+            .drawn(Instant::now())
+            .as_secs_f32();
+
+        self.draw_tooltip(index, delta);
+        draw_hook(&self.lua, "before_draw", index, &mut self.draw_errors);
+
+        let bar = &mut self.bars[index];
+        let (width, height) = bar.buffer_size();
+        let stride = width * 4;
+        let fps = bar.show_fps.then_some((1.0 / delta) as u32);
+
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &mut bar.gpu {
+            // Each bar has its own context, which must be current to draw with it.
+            if let Err(e) = gpu.make_current() {
+                log::error!("{e}");
+                return;
+            }
+
+            // Swapping buffers damages the whole surface, so lua's damage goes unused.
+            let mut damage = Vec::new();
+            render(
+                &self.lua,
+                &bar.draw,
+                &mut Target::Gpu(&mut *gpu),
+                &mut damage,
+                bar.scale as f32,
+                bar.background_colour,
+                index,
+                delta,
+                fps,
+                bar.draw_timeout,
+                &mut self.draw_errors,
+            );
+            draw_hook(&self.lua, "after_draw", index, &mut self.draw_errors);
+
+            // The swap commits the surface, so the frame must be requested before it.
+            bar.layer
+                .wl_surface()
+                .frame(qh, bar.layer.wl_surface().clone());
+            bar.schedule.frame_pending = true;
+
+            if let Err(e) = gpu.present() {
+                log::error!("{e}");
+            }
+            return;
+        }
+
+        let mut full_damage = false;
+        let mut damage = Vec::new();
+
+        // Prefer the back buffer, but take the front one if the compositor let go of it first.
+        let released = |buffer: &Option<Buffer>| {
+            buffer
+                .as_ref()
+                .is_some_and(|b| !b.slot().has_active_buffers())
+        };
+        let (buffer_index, reused) = pick_buffer(
+            bar.back_buffer,
+            [released(&bar.buffers[0]), released(&bar.buffers[1])],
+        );
+        bar.back_buffer = buffer_index ^ 1;
+
+        if !reused {
+            // Either there is no buffer yet or both are still busy, so draw into a fresh one. The
+            // pool keeps a busy buffer's memory until the compositor releases it.
+            let buffer =
+                match self
+                    .pool
+                    .create_buffer(width, height, stride, bar.pixel_format.into())
+                {
+                    Ok((buffer, _)) => buffer,
+                    Err(e) => {
+                        // Left undrawn until the next resize or redraw tries again.
+                        log::error!("failed to create a {width}x{height} buffer: {e}");
+                        return;
+                    }
+                };
+            bar.buffers[buffer_index] = Some(buffer);
+            bar.damage.replace(buffer_index);
+            // A fresh buffer has no previous contents, so it must be damaged in full.
+            full_damage = true;
+        }
+        let stale = bar.damage.stale_region(buffer_index);
+
+        let buffer = bar.buffers[buffer_index]
+            .as_ref()
+            .expect("buffer was just created");
+        let canvas_data = buffer
+            .canvas(&mut self.pool)
+            .expect("buffer has been released");
+
+        // Draw to the window:
+        full_damage |= render(
+            &self.lua,
+            &bar.draw,
+            &mut Target::Pixels {
+                width,
+                height,
+                data: canvas_data,
+                format: bar.pixel_format,
+                stale,
+            },
+            &mut damage,
+            bar.scale as f32,
+            bar.background_colour,
+            index,
+            delta,
+            fps,
+            bar.draw_timeout,
+            &mut self.draw_errors,
+        );
+        draw_hook(&self.lua, "after_draw", index, &mut self.draw_errors);
+
+        // Damage only what lua marked as dirty, or the entire window if it marked nothing.
+        let surface = bar.layer.wl_surface();
+        let damage = surface_damage(damage, full_damage);
+        match &damage {
+            Some(damage) => {
+                for rect in damage {
+                    surface.damage_buffer(rect.left, rect.top, rect.width(), rect.height());
+                }
+            }
+            None => surface.damage_buffer(0, 0, width, height),
+        }
 
         // Request our next frame
-        self.layer
-            .wl_surface()
-            .frame(qh, self.layer.wl_surface().clone());
+        surface.frame(qh, surface.clone());
+        bar.schedule.frame_pending = true;
 
         // Attach and commit to present.
-        buffer
-            .attach_to(self.layer.wl_surface())
-            .expect("buffer attach");
+        buffer.attach_to(surface).expect("buffer attach");
+        bar.layer.commit();
+        bar.damage.record(buffer_index, damage);
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        // The layer surface destroys itself, the objects made for it have to go first.
+        if let Some((_, notification)) = &self.idle_notification {
+            notification.destroy();
+        }
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale.viewport.destroy();
+            fractional_scale.scale.destroy();
+        }
+    }
+}
+
+impl Bar {
+    /// Applies everything in `opts` that can change after the surface is created.
+    fn apply_opts(&mut self, opts: &Opts) {
+        self.auto_exclusive =
+            (opts.exclusive == Exclusive::Auto).then(|| opts.anchor.unwrap_or(Anchor::empty()));
+        let zone = self.auto_exclusive_zone().unwrap_or(opts.exclusive_zone);
+
+        let layer = &self.layer;
+        if let Some(a) = opts.anchor {
+            layer.set_anchor(a);
+            self.anchor = a;
+        }
+        let margin = opts.margin;
+        self.margin = margin;
+        layer.set_margin(margin.top, margin.right, margin.bottom, margin.left);
+        let (width, height) = opts.surface_size();
+        layer.set_size(width, height);
+        layer.set_exclusive_zone(zone);
+        layer.set_keyboard_interactivity(opts.keyboard_interactivity.into());
+        layer.commit();
+
+        self.draw = opts.draw.clone();
+        self.requested_size = (opts.width.max(1), opts.height.max(1));
+        self.show_fps = opts.show_fps;
+        self.background_colour = opts.background_colour.unwrap_or(0x00000000);
+        if self.pixel_format != opts.pixel_format {
+            self.pixel_format = opts.pixel_format;
+            self.buffers = [None, None];
+            self.damage.forget();
+        }
+        self.schedule.frame_interval = frame_interval(opts.max_fps);
+        self.idle_timeout = opts.idle_timeout;
+        self.key_repeat = opts.key_repeat;
+        self.draw_timeout =
+            (opts.draw_timeout > 0).then(|| Duration::from_millis(opts.draw_timeout.into()));
+        self.schedule.needs_redraw = true;
+    }
+
+    /// The exclusive zone covering the bar along the edge it is anchored to, `None` unless
+    /// `Opts::exclusive` is auto.
+    fn auto_exclusive_zone(&self) -> Option<i32> {
+        let anchor = self.auto_exclusive?;
+        // Without an edge of its own, the bar has nowhere to reserve space.
+        Some(match anchored_edge(anchor) {
+            Some(edge) if edge == Anchor::TOP || edge == Anchor::BOTTOM => self.height as i32,
+            Some(_) => self.width as i32,
+            None => 0,
+        })
+    }
+
+    /// `margin` with the edge the bar is anchored to moved back by `offset`, so an offset of
+    /// the bar's height, or width when anchored left or right, hides it past the edge. `None`
+    /// when the bar has no edge of its own.
+    fn slid_margin(&self, offset: i32) -> Option<Margin> {
+        let edge = anchored_edge(self.anchor)?;
+        let mut margin = self.margin;
+        if edge == Anchor::TOP {
+            margin.top -= offset;
+        } else if edge == Anchor::BOTTOM {
+            margin.bottom -= offset;
+        } else if edge == Anchor::LEFT {
+            margin.left -= offset;
+        } else {
+            margin.right -= offset;
+        }
+        Some(margin)
+    }
+
+    /// Whether `run` should draw the bar now.
+    fn ready(&self) -> bool {
+        self.schedule.ready(Instant::now())
+    }
+
+    /// How long the frame cap holds back the requested redraw, `None` if it can be drawn now
+    /// or nothing needs drawing.
+    fn frame_delay(&self) -> Option<Duration> {
+        self.schedule.frame_delay(Instant::now())
+    }
+
+    /// Stops drawing the bar once the compositor has closed it.
+    fn close(&mut self) {
+        self.schedule.closed = true;
+        self.buffers = [None, None];
+        self.damage.forget();
+        #[cfg(feature = "gpu")]
+        {
+            self.gpu = None;
+        }
+    }
+
+    /// Unmaps the bar to hide it. Showing it maps it again like a new surface, with a commit
+    /// without a buffer and a frame once the compositor configures it.
+    fn set_visible(&mut self, visible: bool) {
+        if !self.schedule.set_visible(visible) {
+            return;
+        }
+
+        let surface = self.layer.wl_surface();
+        if visible {
+            self.damage.forget();
+        } else {
+            surface.attach(None, 0, 0);
+        }
+        surface.commit();
+    }
+
+    /// Limits pointer input to `region` (x, y, width, height), or accepts it on the whole
+    /// surface when `None`.
+    fn set_input_region(&self, compositor: &CompositorState, region: Option<(i32, i32, i32, i32)>) {
+        let surface = self.layer.wl_surface();
+        match region {
+            Some((x, y, width, height)) => {
+                let r = Region::new(compositor).expect("failed to create region");
+                r.add(x, y, width, height);
+                surface.set_input_region(Some(r.wl_region()));
+            }
+            None => surface.set_input_region(None),
+        }
         self.layer.commit();
+    }
+
+    /// The bar's size in buffer pixels.
+    fn buffer_size(&self) -> (i32, i32) {
+        buffer_size((self.width, self.height), self.scale)
+    }
+
+    /// Drops buffers of the old size or scale, the shared pool grows to fit the new ones.
+    fn resize_buffers(&mut self) {
+        self.buffers = [None, None];
+        self.damage.forget();
+        if let Some(fractional_scale) = &self.fractional_scale {
+            fractional_scale
+                .viewport
+                .set_destination(self.width as i32, self.height as i32);
+        }
+
+        #[cfg(feature = "gpu")]
+        let size = self.buffer_size();
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &mut self.gpu
+            && let Err(e) = gpu.resize(size)
+        {
+            log::warn!("{e}, falling back to the cpu backend");
+            self.gpu = None;
+        }
+    }
+}
+
+/// Calls `f` every `interval`, the first time one interval from now.
+fn insert_interval<D>(
+    handle: &LoopHandle<'static, D>,
+    interval: Duration,
+    mut f: impl FnMut(&mut D) + 'static,
+) -> RegistrationToken {
+    handle
+        .insert_source(Timer::from_duration(interval), move |_, _, data| {
+            f(data);
+            TimeoutAction::ToDuration(interval)
+        })
+        .expect("failed to insert timer")
+}
+
+/// Where a frame is drawn, handing out a fresh canvas for each pass over it.
+enum Target<'a> {
+    Pixels {
+        width: i32,
+        height: i32,
+        data: &'a mut [u8],
+        format: PixelFormat,
+        /// What of `data` is left from older frames, see `DamageHistory::stale_region`.
+        stale: Option<Vec<IRect>>,
+    },
+    #[cfg(feature = "gpu")]
+    Gpu(&'a mut GpuRenderer),
+}
+
+impl Target<'_> {
+    fn canvas<'b>(&'b mut self, damage: &'b mut Vec<IRect>, scale: f32) -> Canvas<'b> {
+        let mut canvas = match self {
+            Target::Pixels {
+                width,
+                height,
+                data,
+                format,
+                stale,
+            } => {
+                let mut canvas = Canvas::new(*width, *height, data, *format, damage);
+                canvas.set_stale_region(stale.clone());
+                canvas
+            }
+            #[cfg(feature = "gpu")]
+            Target::Gpu(gpu) => gpu.canvas(damage),
+        };
+        canvas.set_scale_factor(scale);
+        canvas
+    }
+}
+
+/// Clears `target` and runs the config's draw function `name` on it for `bar`, then the fps counter over
+/// it, or the error in its place if it failed. Returns whether the whole surface must be damaged.
+#[allow(clippy::too_many_arguments)]
+fn render(
+    lua: &Lua,
+    name: &str,
+    target: &mut Target,
+    damage: &mut Vec<IRect>,
+    scale: f32,
+    background_colour: u32,
+    bar: usize,
+    delta: f32,
+    fps: Option<u32>,
+    timeout: Option<Duration>,
+    errors: &mut ErrorLog,
+) -> bool {
+    let result = {
+        let mut canvas = target.canvas(damage, scale);
+        canvas.clear_stale(background_colour);
+
+        lua.globals().get::<mlua::Function>(name).and_then(|d| {
+            if let Some(timeout) = timeout {
+                set_draw_timeout(lua, name, timeout)?;
+            }
+            let result = lua.scope(|scope| {
+                let canvas = scope.create_userdata(canvas)?;
+                d.call::<()>((canvas, delta, bar + 1))
+            });
+            lua.remove_hook();
+            result
+        })
+    };
+
+    match (result, fps) {
+        // Show the error on the bar rather than taking it down.
+        (Err(e), _) => {
+            let message = e.to_string();
+            errors.report(&message);
+            target.canvas(damage, scale).draw_error(&message);
+            true
+        }
+        (Ok(()), Some(fps)) => {
+            target.canvas(damage, scale).draw_fps(fps);
+            true
+        }
+        (Ok(()), None) => false,
+    }
+}
 
-        // TODO save and reuse buffer when the window size is unchanged.  This is especially
-        // useful if you do damage tracking, since you don't need to redraw the undamaged parts
-        // of the canvas.
+/// Makes lua raise an error once `timeout` has passed, so a draw function stuck in a loop shows
+/// the error on the bar instead of freezing it. LuaJIT does not run hooks in code it has already
+/// compiled, so a loop that got hot in an earlier frame can still escape it.
+fn set_draw_timeout(lua: &Lua, name: &str, timeout: Duration) -> mlua::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let message = format!("{name} took longer than {}ms", timeout.as_millis());
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(DRAW_TIMEOUT_CHECK_INSTRUCTIONS),
+        move |_, _| {
+            if Instant::now() < deadline {
+                Ok(VmState::Continue)
+            } else {
+                Err(mlua::Error::runtime(message.clone()))
+            }
+        },
+    )
+}
+
+/// Calls the config's `before_draw` or `after_draw` if it defines one. Errors are logged like
+/// those of `draw`, so a failing hook does not flood the log every frame.
+fn draw_hook(lua: &Lua, name: &str, bar: usize, errors: &mut ErrorLog) {
+    if let Err(e) = api::call_global(lua, name, bar + 1) {
+        errors.report(&format!("{name}: {e}"));
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for LayerState {
+    fn event(
+        state: &mut Self,
+        proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(index) = state.bars.iter().position(|bar| {
+            bar.fractional_scale
+                .as_ref()
+                .is_some_and(|f| f.scale == *proxy)
+        }) else {
+            return;
+        };
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            state.set_scale(index, preferred_scale(scale));
+        }
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, ()> for LayerState {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some(index) = state.bars.iter().position(|bar| {
+            bar.idle_notification
+                .as_ref()
+                .is_some_and(|(_, n)| n == proxy)
+        }) else {
+            return;
+        };
+        let bar = &mut state.bars[index];
+
+        match event {
+            ext_idle_notification_v1::Event::Idled => {
+                log::debug!("pausing bar {} while idle", index + 1);
+                bar.schedule.set_idle(true);
+                state.events.push(Event::Idle { bar: index });
+            }
+            ext_idle_notification_v1::Event::Resumed => {
+                log::debug!("resuming bar {}", index + 1);
+                bar.schedule.set_idle(false);
+                state.events.push(Event::Resumed { bar: index });
+            }
+            _ => {}
+        }
     }
 }
 
 delegate_compositor!(LayerState);
 delegate_output!(LayerState);
 delegate_shm!(LayerState);
+delegate_xdg_popup!(LayerState);
+delegate_dispatch!(LayerState: [xdg_wm_base::XdgWmBase: GlobalData] => XdgShell);
+delegate_noop!(LayerState: WpFractionalScaleManagerV1);
+delegate_noop!(LayerState: WpViewporter);
+delegate_noop!(LayerState: WpViewport);
+delegate_noop!(LayerState: ExtIdleNotifierV1);
 
 delegate_seat!(LayerState);
 delegate_keyboard!(LayerState);
@@ -568,3 +2741,502 @@ impl ProvidesRegistryState for LayerState {
     }
     registry_handlers![OutputState, SeatState];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_convert_to_layer_shell() {
+        let layers = ["background", "bottom", "top", "overlay"]
+            .map(|name| Layer::from(name.parse::<super::super::Layer>().unwrap()));
+        assert_eq!(
+            layers,
+            [Layer::Background, Layer::Bottom, Layer::Top, Layer::Overlay]
+        );
+    }
+
+    fn rect(x: i32) -> IRect {
+        IRect::from_xywh(x, 0, 10, 10)
+    }
+
+    #[test]
+    fn stale_region_covers_frames_a_buffer_missed() {
+        let mut history = DamageHistory::default();
+        assert_eq!(history.stale_region(0), None);
+        assert_eq!(history.stale_region(1), None);
+
+        history.record(0, Some(vec![rect(0)]));
+        history.record(1, Some(vec![rect(20)]));
+        assert_eq!(history.stale_region(0), Some(vec![rect(20)]));
+        assert_eq!(history.stale_region(1), Some(vec![]));
+
+        history.record(0, Some(vec![rect(40)]));
+        assert_eq!(history.stale_region(0), Some(vec![]));
+        assert_eq!(history.stale_region(1), Some(vec![rect(40)]));
+    }
+
+    #[test]
+    fn stale_region_is_unknown_after_full_damage() {
+        let mut history = DamageHistory::default();
+        history.record(0, Some(vec![rect(0)]));
+        history.record(1, None);
+        assert_eq!(history.stale_region(0), None);
+
+        history.record(0, Some(vec![rect(20)]));
+        assert_eq!(history.stale_region(1), Some(vec![rect(20)]));
+    }
+
+    #[test]
+    fn stale_region_is_unknown_for_old_or_replaced_buffers() {
+        let mut history = DamageHistory::default();
+        history.record(0, Some(vec![rect(0)]));
+        for _ in 0..=MAX_BUFFER_AGE {
+            history.record(1, Some(vec![rect(20)]));
+        }
+        assert_eq!(history.stale_region(0), None);
+        assert_eq!(history.stale_region(1), Some(vec![]));
+
+        history.replace(1);
+        assert_eq!(history.stale_region(1), None);
+
+        history.record(1, Some(vec![rect(20)]));
+        history.forget();
+        assert_eq!(history.stale_region(1), None);
+    }
+
+    #[test]
+    fn pool_fits_every_bar_and_is_never_empty() {
+        let bar = |width, height| Opts {
+            width,
+            height,
+            ..Opts::default()
+        };
+        assert_eq!(initial_pool_size(&[bar(100, 10), bar(20, 5)]), 8800);
+        assert_eq!(initial_pool_size(&[bar(0, 30)]), 1);
+        assert_eq!(initial_pool_size(&[]), 1);
+        assert_eq!(
+            initial_pool_size(&[bar(MAX_SIZE, MAX_SIZE)]),
+            MAX_SIZE as usize * MAX_SIZE as usize * 8
+        );
+    }
+
+    #[test]
+    fn shuts_down_cleanly() {
+        let lua = Lua::new();
+        lua.load("function on_exit() exited = true end")
+            .exec()
+            .unwrap();
+        let socket =
+            std::env::temp_dir().join(format!("status-bar-shut-down-{}.sock", std::process::id()));
+        fs::write(&socket, "").unwrap();
+
+        shut_down(&lua, Some(&socket));
+        assert!(lua.globals().get::<bool>("exited").unwrap());
+        assert!(!socket.exists());
+
+        // A failing on_exit, or none at all, still shuts down.
+        lua.load(r#"function on_exit() error("oops") end"#)
+            .exec()
+            .unwrap();
+        shut_down(&lua, None);
+        shut_down(&Lua::new(), None);
+    }
+
+    #[test]
+    fn closed_bars_are_never_drawn() {
+        let (mut schedule, now) = configured();
+        schedule.closed = true;
+        assert!(!schedule.ready(now + Duration::from_secs(1)));
+        assert_eq!(schedule.frame_delay(now), None);
+    }
+
+    #[test]
+    fn held_keys_repeat_at_the_seat_rate() {
+        let info = RepeatInfo::Repeat {
+            rate: NonZeroU32::new(25).unwrap(),
+            delay: 600,
+        };
+        assert_eq!(
+            repeat_timing(info),
+            Some((Duration::from_millis(600), Duration::from_millis(40)))
+        );
+        assert_eq!(repeat_timing(RepeatInfo::Disable), None);
+    }
+
+    #[test]
+    fn animations_stop_at_the_end() {
+        let duration = Duration::from_millis(50);
+        let progress = |ms| animation_progress(Duration::from_millis(ms), duration);
+        assert_eq!(progress(0), 0.0);
+        assert_eq!(progress(25), 0.5);
+        assert_eq!(progress(50), 1.0);
+        assert_eq!(progress(80), 1.0);
+        assert_eq!(animation_progress(Duration::ZERO, Duration::ZERO), 1.0);
+    }
+
+    #[test]
+    fn termination_signals_exit() {
+        for signal in [Signal::SIGTERM, Signal::SIGINT] {
+            assert!(matches!(signal_event(signal), Event::Exit), "{signal:?}");
+        }
+    }
+
+    #[test]
+    fn sigusr1_reloads() {
+        assert!(matches!(signal_event(Signal::SIGUSR1), Event::Reload));
+    }
+
+    #[test]
+    fn reloads_an_edited_config() {
+        let path =
+            std::env::temp_dir().join(format!("status-bar-watch-{}.lua", std::process::id()));
+        let write = |version: u32, modified: SystemTime| {
+            fs::write(
+                &path,
+                format!("function opts() return {{}} end function draw() return {version} end"),
+            )
+            .unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+
+        let start = SystemTime::now();
+        write(1, start);
+        let mut watcher = ConfigWatcher::new(&path);
+        assert!(!watcher.changed());
+
+        write(2, start + Duration::from_secs(1));
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+        let (_, lua) = crate::config::load_config(&path).unwrap();
+        let draw: mlua::Function = lua.globals().get("draw").unwrap();
+        assert_eq!(draw.call::<u32>(()).unwrap(), 2);
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+    }
+
+    #[test]
+    fn configured_size_falls_back_to_the_requested_one() {
+        assert_eq!(configured_size((0, 0), (300, 30)), (300, 30));
+        assert_eq!(configured_size((1920, 0), (0, 30)), (1920, 30));
+        assert_eq!(configured_size((1920, 40), (0, 30)), (1920, 40));
+        assert_eq!(configured_size((u32::MAX, 30), (0, 30)), (MAX_SIZE, 30));
+    }
+
+    #[test]
+    fn resizes_only_on_a_new_size() {
+        let mut size = (0, 0);
+        let mut resizes = 0;
+        for new_size in [(1920, 30), (1920, 30), (0, 30), (1280, 30)] {
+            if let Some(new_size) = resize(size, new_size, (1920, 30)) {
+                size = new_size;
+                resizes += 1;
+            }
+        }
+        assert_eq!(resizes, 2);
+        assert_eq!(size, (1280, 30));
+    }
+
+    #[test]
+    fn buffers_are_sized_for_fractional_scales() {
+        let scale = preferred_scale(180);
+        assert_eq!(scale, 1.5);
+        assert_eq!(buffer_size((200, 30), scale), (300, 45));
+        // Odd sizes round to the nearest pixel.
+        assert_eq!(buffer_size((201, 31), preferred_scale(150)), (251, 39));
+    }
+
+    #[test]
+    fn names_output_transforms_for_lua() {
+        assert_eq!(transform_name(wl_output::Transform::Normal), "normal");
+        assert_eq!(transform_name(wl_output::Transform::_90), "90");
+        assert_eq!(
+            transform_name(wl_output::Transform::Flipped270),
+            "flipped-270"
+        );
+        assert_eq!(
+            subpixel_name(wl_output::Subpixel::HorizontalRgb),
+            "horizontal-rgb"
+        );
+    }
+
+    #[test]
+    fn damages_only_the_marked_regions() {
+        let rect = IRect::from_xywh(2, 2, 4, 4);
+        assert_eq!(surface_damage(vec![rect], false), Some(vec![rect]));
+        assert_eq!(surface_damage(vec![rect], true), None);
+        assert_eq!(surface_damage(Vec::new(), false), None);
+    }
+
+    #[test]
+    fn tooltip_anchor_lies_on_the_bar() {
+        assert_eq!(tooltip_anchor((10, 5), (100, 20)), Some((10, 5)));
+        assert_eq!(tooltip_anchor((150, 30), (100, 20)), Some((99, 19)));
+        assert_eq!(tooltip_anchor((-5, -5), (100, 20)), Some((0, 0)));
+        assert_eq!(tooltip_anchor((10, 5), (0, 20)), None);
+        assert_eq!(tooltip_anchor((10, 5), (100, 0)), None);
+    }
+
+    #[test]
+    fn intervals_fire_repeatedly() {
+        let mut event_loop = calloop::EventLoop::<Vec<Instant>>::try_new().unwrap();
+        let started = Instant::now();
+        insert_interval(
+            &event_loop.handle(),
+            Duration::from_millis(10),
+            |ticks: &mut Vec<Instant>| ticks.push(Instant::now()),
+        );
+
+        let mut ticks = Vec::new();
+        while ticks.len() < 3 && started.elapsed() < Duration::from_secs(5) {
+            event_loop
+                .dispatch(Some(Duration::from_millis(50)), &mut ticks)
+                .unwrap();
+        }
+        assert!(ticks.len() >= 3);
+        assert!(ticks[0] >= started + Duration::from_millis(10));
+        assert!(ticks[2] >= started + Duration::from_millis(30));
+    }
+
+    /// A schedule for a bar the compositor has configured, with its last frame at `now`.
+    fn configured() -> (Schedule, Instant) {
+        let mut schedule = Schedule::new();
+        schedule.first_configure = false;
+        let now = schedule.last_frame;
+        (schedule, now)
+    }
+
+    #[test]
+    fn draws_only_when_a_redraw_is_requested() {
+        let schedule = Schedule::new();
+        assert!(!schedule.ready(schedule.last_frame));
+
+        let (mut schedule, now) = configured();
+        assert!(schedule.ready(now));
+        let later = now + Duration::from_millis(20);
+        assert_eq!(schedule.drawn(later), Duration::from_millis(20));
+        assert!(!schedule.ready(later));
+        assert!(!schedule.ready(later + Duration::from_secs(10)));
+
+        schedule.needs_redraw = true;
+        assert!(schedule.ready(later));
+    }
+
+    #[test]
+    fn redraw_requests_coalesce_into_one_draw() {
+        let (mut schedule, now) = configured();
+        let mut draws = 0;
+        for _ in 0..3 {
+            // Ten motion events in one batch.
+            for _ in 0..10 {
+                schedule.needs_redraw = true;
+            }
+            // As `run` does once per iteration.
+            if schedule.ready(now) {
+                schedule.drawn(now);
+                schedule.frame_pending = true;
+                draws += 1;
+            }
+        }
+        assert_eq!(draws, 1);
+
+        // The next frame waits for the compositor's frame callback.
+        schedule.frame_pending = false;
+        assert!(schedule.ready(now));
+    }
+
+    #[test]
+    fn idle_pauses_drawing_until_resumed() {
+        let (mut schedule, now) = configured();
+        schedule.drawn(now);
+        schedule.set_idle(true);
+        schedule.needs_redraw = true;
+        assert!(!schedule.ready(now));
+
+        schedule.set_idle(false);
+        assert!(schedule.ready(now));
+        schedule.drawn(now);
+
+        // Resuming redraws even when nothing asked to while idle.
+        schedule.set_idle(true);
+        schedule.set_idle(false);
+        assert!(schedule.ready(now));
+    }
+
+    #[test]
+    fn hidden_bars_are_not_drawn() {
+        let (mut schedule, now) = configured();
+        schedule.frame_pending = true;
+        assert!(schedule.set_visible(false));
+        assert!(!schedule.set_visible(false));
+        schedule.needs_redraw = true;
+        assert!(!schedule.ready(now));
+
+        // Shown again, the bar waits to be configured, but not for the lost frame callback.
+        assert!(schedule.set_visible(true));
+        assert!(!schedule.ready(now));
+        schedule.first_configure = false;
+        assert!(schedule.ready(now));
+    }
+
+    #[test]
+    fn released_buffers_are_reused() {
+        // The first frame has no buffer to reuse.
+        assert_eq!(pick_buffer(0, [false, false]), (0, false));
+        // Once the compositor releases it, the next frame draws into it again rather than
+        // allocating the second buffer.
+        assert_eq!(pick_buffer(1, [true, false]), (0, true));
+        assert_eq!(pick_buffer(1, [true, false]), (0, true));
+    }
+
+    #[test]
+    fn buffers_alternate_while_the_compositor_holds_one() {
+        let mut back = 0;
+        let mut drawn = Vec::new();
+        for _ in 0..4 {
+            let (index, reused) = pick_buffer(back, [true, true]);
+            assert!(reused);
+            drawn.push(index);
+            back = index ^ 1;
+        }
+        assert_eq!(drawn, [0, 1, 0, 1]);
+
+        // The front buffer is taken while the back one is still being read.
+        assert_eq!(pick_buffer(0, [false, true]), (1, true));
+        // With both busy, a fresh buffer replaces the back one.
+        assert_eq!(pick_buffer(1, [false, false]), (1, false));
+    }
+
+    /// Runs the config `conf`, then renders its `draw` for the first bar on a 4x4 target.
+    /// Returns the lua state, whether the whole surface was to be damaged and the pixels.
+    fn render_config(
+        conf: &str,
+        timeout: Option<Duration>,
+        errors: &mut ErrorLog,
+    ) -> (Lua, bool, Vec<u8>) {
+        let lua = Lua::new();
+        api::register(&lua).unwrap();
+        lua.load(conf).exec().unwrap();
+
+        let mut data = vec![0; 4 * 4 * 4];
+        let mut target = Target::Pixels {
+            width: 4,
+            height: 4,
+            data: &mut data,
+            format: PixelFormat::Argb8888,
+            stale: None,
+        };
+        let full = render(
+            &lua,
+            "draw",
+            &mut target,
+            &mut Vec::new(),
+            1.0,
+            0,
+            0,
+            0.5,
+            None,
+            timeout,
+            errors,
+        );
+        (lua, full, data)
+    }
+
+    #[test]
+    fn draw_errors_are_shown_on_the_bar() {
+        let mut errors = ErrorLog::default();
+        let (_, full, data) =
+            render_config(r#"function draw() error("oops") end"#, None, &mut errors);
+        assert!(full);
+        let (message, _) = errors.last.expect("the error is logged");
+        assert!(message.contains("oops"), "{message}");
+        // The error's background.
+        assert_eq!(data[..4], [0x1E, 0x1E, 0x1E, 0xFF]);
+
+        // The next frame draws as usual.
+        let (_, full, _) = render_config("function draw() end", None, &mut errors);
+        assert!(!full);
+    }
+
+    #[test]
+    fn draw_gets_the_frame_delta_and_bar() {
+        let conf = "function draw(canvas, delta, bar) last_delta, last_bar = delta, bar end";
+        let (lua, _, _) = render_config(conf, None, &mut ErrorLog::default());
+        assert_eq!(lua.globals().get::<f32>("last_delta").unwrap(), 0.5);
+        assert_eq!(lua.globals().get::<usize>("last_bar").unwrap(), 1);
+
+        // The delta is the time between two frames.
+        let (mut schedule, now) = configured();
+        schedule.drawn(now + Duration::from_millis(16));
+        let delta = schedule.drawn(now + Duration::from_millis(48));
+        assert!((delta.as_secs_f32() - 0.032).abs() < 1e-6);
+    }
+
+    #[test]
+    fn spinning_draws_are_interrupted() {
+        let mut errors = ErrorLog::default();
+        // Compiled code does not run hooks, see `set_draw_timeout`.
+        let conf = "if jit then jit.off() end function draw() while true do end end";
+        let (_, full, _) = render_config(conf, Some(Duration::from_millis(50)), &mut errors);
+        assert!(full);
+        let (message, _) = errors.last.expect("the timeout is logged");
+        assert!(message.contains("draw took longer than 50ms"), "{message}");
+    }
+
+    #[test]
+    fn setters_called_while_drawing_apply_next_iteration() {
+        let conf = "function draw() set_margin(1, 2, 3, 4) set_visible(false, 2) end";
+        let (lua, _, _) = render_config(conf, None, &mut ErrorLog::default());
+        let events = std::mem::take(&mut lua.app_data_mut::<api::Context>().unwrap().events);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                Event::SetMargin { bar: 0, .. },
+                Event::SetVisible {
+                    bar: 1,
+                    visible: false,
+                },
+            ]
+        ));
+
+        // They are handled straight away rather than after the next frame.
+        let delay = Some(Duration::from_millis(16));
+        assert_eq!(dispatch_timeout(true, delay), Some(Duration::ZERO));
+        assert_eq!(dispatch_timeout(false, delay), delay);
+        assert_eq!(dispatch_timeout(false, None), None);
+    }
+
+    fn click(bar: usize, button: u32, position: (f64, f64), time: u32) -> Click {
+        Click {
+            bar,
+            button,
+            position,
+            time,
+        }
+    }
+
+    #[test]
+    fn click_followed_by_nearby_click_of_same_button() {
+        let press = click(0, 0x110, (10.0, 10.0), 1000);
+        assert!(press.followed_by(&click(0, 0x110, (14.0, 13.0), 1200), CLICK_TIME));
+        assert!(press.followed_by(&click(0, 0x110, (10.0, 10.0), 1500), CLICK_TIME));
+
+        assert!(!press.followed_by(&click(1, 0x110, (10.0, 10.0), 1200), CLICK_TIME));
+        assert!(!press.followed_by(&click(0, 0x111, (10.0, 10.0), 1200), CLICK_TIME));
+        assert!(!press.followed_by(&click(0, 0x110, (20.0, 10.0), 1200), CLICK_TIME));
+        assert!(!press.followed_by(&click(0, 0x110, (10.0, 10.0), 1501), CLICK_TIME));
+    }
+
+    #[test]
+    fn click_time_wraps() {
+        let press = click(0, 0x110, (0.0, 0.0), u32::MAX - 100);
+        assert!(press.followed_by(&click(0, 0x110, (0.0, 0.0), 100), CLICK_TIME));
+        assert!(!press.followed_by(&click(0, 0x110, (0.0, 0.0), u32::MAX - 200), CLICK_TIME));
+    }
+}